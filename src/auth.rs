@@ -0,0 +1,105 @@
+//! Proof of the UTXO ownership, that is required to connect to the room of the service, and
+//! the messages signed by the participant in the room.
+//!
+//! The service issues a random nonce for the participant, and the participant signs the
+//! [`challenge_message`] with the key of the UTXO owner. The message binds the nonce to the
+//! room, the UTXO, the RSA public key, the session key, the commitment to the entropy of the
+//! order, see [`ordering`](crate::ordering), and the address of the change the participant
+//! connects with, so the signature can't be replayed in another room or with the keys of
+//! someone else.
+//!
+//! The session key is the ephemeral key of the room, that signs every submission of the
+//! participant after the connect. As it is bound to the owner, the owner can't deny the
+//! submissions, while its own key signs only the challenge and the outputs of the room.
+
+use ethers_core::abi::AbiEncode;
+use ethers_core::types::{Address, H256, U256};
+use ethers_core::utils::keccak256;
+use uuid::Uuid;
+
+use crate::rsa::{PublicKeyParts, RsaPublicKey};
+
+/// Return the message the UTXO owner signs to connect to the room. The `change` address is
+/// passed, if the UTXO amount exceeds the room amount and the fee.
+pub fn challenge_message(
+    room_id: &Uuid,
+    utxo_id: U256,
+    nonce: H256,
+    rsa_pubkey: &RsaPublicKey,
+    session_key: Address,
+    commitment: H256,
+    change: Option<Address>,
+) -> [u8; 32] {
+    let mut message = Message::new(b"challenge", room_id, utxo_id);
+    message.push(nonce.as_bytes());
+    message.push(&rsa_pubkey.n().to_bytes_be());
+    message.push(&rsa_pubkey.e().to_bytes_be());
+    message.push(session_key.as_bytes());
+    message.push(commitment.as_bytes());
+    // Empty part stands for the UTXO without the change
+    message.push(change.as_ref().map_or(&[], |change| change.as_bytes()));
+
+    message.hash()
+}
+
+/// Return the message the UTXO owner signs to leave the room before the shuffle is started.
+pub fn leave_message(room_id: &Uuid, utxo_id: U256) -> [u8; 32] {
+    Message::new(b"leave", room_id, utxo_id).hash()
+}
+
+/// Return the message the participant signs by the session key to reveal the entropy it
+/// committed to on connect.
+pub fn reveal_message(room_id: &Uuid, utxo_id: U256, entropy: H256) -> [u8; 32] {
+    let mut message = Message::new(b"reveal", room_id, utxo_id);
+    message.push(entropy.as_bytes());
+
+    message.hash()
+}
+
+/// Return the message the participant signs by the session key to pass the outputs decoded
+/// in its round.
+pub fn decoded_outputs_message(
+    room_id: &Uuid,
+    utxo_id: U256,
+    decoded_outputs: &[Vec<u8>],
+) -> [u8; 32] {
+    let mut message = Message::new(b"decoded-outputs", room_id, utxo_id);
+    for output in decoded_outputs {
+        message.push(output);
+    }
+
+    message.hash()
+}
+
+/// Return the message the participant signs by the session key to pass its signature of
+/// the room outputs.
+pub fn outputs_signature_message(room_id: &Uuid, utxo_id: U256, signature: &[u8]) -> [u8; 32] {
+    let mut message = Message::new(b"outputs-signature", room_id, utxo_id);
+    message.push(signature);
+
+    message.hash()
+}
+
+/// Message of the participant in the room, where every part is prefixed by its length, so
+/// different parts can't produce the same message.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(kind: &[u8], room_id: &Uuid, utxo_id: U256) -> Self {
+        let mut message = Self(Vec::new());
+        message.push(kind);
+        message.push(room_id.as_bytes());
+        message.push(&utxo_id.encode());
+
+        message
+    }
+
+    fn push(&mut self, part: &[u8]) {
+        self.0.append(&mut U256::from(part.len()).encode());
+        self.0.extend_from_slice(part);
+    }
+
+    fn hash(self) -> [u8; 32] {
+        keccak256(self.0)
+    }
+}
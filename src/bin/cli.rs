@@ -0,0 +1,380 @@
+//! Tool of the node operators to run a shuffle against the local coordinator and to
+//! reproduce its steps by hand: ephemeral keys generation, chunked encoding and decoding
+//! of the outputs, and the status of the joined rooms.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ::rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
+use clap::{Parser, Subcommand};
+use coin_shuffle_core::client::{tcp::TcpTransport, Client, Event, Transport};
+use coin_shuffle_core::mock::ledger::FileLedger;
+use coin_shuffle_core::node::{storage::RoomMemoryStorage, Node};
+use coin_shuffle_core::protocol::{Request, Response};
+use coin_shuffle_core::rsa::{self, RsaPrivateKey, RsaPublicKey};
+use coin_shuffle_core::types::ShuffleStatus;
+use ethers_core::types::{Address, H256, U256};
+use ethers_core::utils::hex;
+use ethers_signers::{LocalWallet, Signer};
+use serde::{Deserialize, Serialize};
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate the RSA key pair, the public key is written next to the private one with
+    /// the `.pub` extension.
+    Keygen {
+        #[arg(long, default_value_t = rsa::DEFAULT_KEY_SIZE)]
+        bits: usize,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Encode the file by the public key, as the node encodes its output.
+    Encode {
+        /// PEM file of the public key.
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        output: PathBuf,
+        /// Hex nonce of the encoding, the random one is used by default.
+        #[arg(long)]
+        nonce: Option<String>,
+    },
+    /// Decode the file by the private key, as the node decodes the outputs in its round.
+    Decode {
+        /// PEM file of the private key.
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Create the wallet key file if it doesn't exist and print the wallet address.
+    Wallet { path: PathBuf },
+    /// Create the UTXO in the ledger of the local coordinator.
+    Deposit {
+        #[arg(long, default_value = "ledger.json")]
+        ledger: PathBuf,
+        #[arg(long, default_value = ZERO_ADDRESS)]
+        token: Address,
+        #[arg(long)]
+        owner: Address,
+        #[arg(long, value_parser = parse_u256)]
+        amount: U256,
+    },
+    /// Create the room of the UTXOs in the coordinator, which accepts it only from its host.
+    CreateRoom {
+        #[arg(long, default_value = "127.0.0.1:7700")]
+        coordinator: SocketAddr,
+        #[arg(long, default_value = ZERO_ADDRESS)]
+        token: Address,
+        #[arg(long, value_parser = parse_u256)]
+        amount: U256,
+        /// UTXO ids in the shuffle order.
+        #[arg(long, num_args = 1.., required = true, value_parser = parse_u256)]
+        participants: Vec<U256>,
+    },
+    /// Shuffle the UTXO into the output address in the room of the coordinator.
+    Join {
+        #[arg(long, default_value = "127.0.0.1:7700")]
+        coordinator: SocketAddr,
+        #[arg(long, default_value = "ledger.json")]
+        ledger: PathBuf,
+        /// File with the hex private key of the UTXO owner.
+        #[arg(long)]
+        wallet: PathBuf,
+        #[arg(long, value_parser = parse_u256)]
+        utxo_id: U256,
+        #[arg(long)]
+        output: Address,
+        #[arg(long, default_value_t = rsa::DEFAULT_KEY_SIZE)]
+        rsa_key_size: usize,
+        /// Maximum fee of the coordinator the UTXO owner approves.
+        #[arg(long, default_value = "0", value_parser = parse_u256)]
+        max_fee: U256,
+        #[arg(long, default_value = "rooms.json")]
+        status_file: PathBuf,
+    },
+    /// Print the status of the rooms joined by the `join` command.
+    Status {
+        #[arg(long, default_value = "rooms.json")]
+        status_file: PathBuf,
+    },
+}
+
+/// Status of the room joined by the UTXO, kept in the status file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RoomStatus {
+    room_id: Option<uuid::Uuid>,
+    status: ShuffleStatus,
+    outcome: Outcome,
+    /// Unix time of the last update in seconds.
+    updated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Outcome {
+    Running,
+    Finished { tx_hash: H256 },
+    Failed { error: String },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    match run(Args::parse().command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command) -> CliResult<()> {
+    match command {
+        Command::Keygen { bits, out } => keygen(bits, &out),
+        Command::Encode {
+            key,
+            input,
+            output,
+            nonce,
+        } => {
+            let key = RsaPublicKey::read_public_key_pem_file(key)?;
+            let nonce = nonce.map(hex::decode).transpose()?.unwrap_or_default();
+
+            let result = rsa::encode_by_chunks(std::fs::read(input)?, key, nonce)?;
+            std::fs::write(output, result.encoded_msg)?;
+            println!("nonce: {}", hex::encode(result.nonce));
+
+            Ok(())
+        }
+        Command::Decode { key, input, output } => {
+            let key = RsaPrivateKey::read_pkcs8_pem_file(key)?;
+
+            let decoded = rsa::decode_by_chunks(std::fs::read(input)?, &key)?;
+            std::fs::write(output, decoded)?;
+
+            Ok(())
+        }
+        Command::Wallet { path } => {
+            if !path.exists() {
+                let wallet = LocalWallet::new(&mut rand::thread_rng());
+                std::fs::write(&path, hex::encode(wallet.signer().to_bytes()))?;
+            }
+
+            println!("{:?}", read_wallet(&path)?.address());
+
+            Ok(())
+        }
+        Command::Deposit {
+            ledger,
+            token,
+            owner,
+            amount,
+        } => {
+            let id = FileLedger::new(ledger).deposit(token, owner, amount)?;
+            println!("{id}");
+
+            Ok(())
+        }
+        Command::CreateRoom {
+            coordinator,
+            token,
+            amount,
+            participants,
+        } => {
+            let mut transport = TcpTransport::connect(coordinator).await?;
+
+            match transport
+                .request(Request::CreateRoom {
+                    token,
+                    amount,
+                    participants,
+                })
+                .await?
+            {
+                Response::RoomCreated { room_id } => {
+                    println!("{room_id}");
+                    Ok(())
+                }
+                Response::Error(err) => Err(err.into()),
+                response => Err(format!("unexpected response: {response:?}").into()),
+            }
+        }
+        Command::Join {
+            coordinator,
+            ledger,
+            wallet,
+            utxo_id,
+            output,
+            rsa_key_size,
+            max_fee,
+            status_file,
+        } => {
+            let ledger = FileLedger::new(ledger);
+            let node = Node::new(RoomMemoryStorage::new(), ledger.clone())
+                .with_rsa_key_size(rsa_key_size)
+                .with_max_fee(max_fee);
+
+            join(
+                coordinator,
+                node,
+                ledger,
+                read_wallet(&wallet)?,
+                utxo_id,
+                output,
+                &status_file,
+            )
+            .await
+        }
+        Command::Status { status_file } => {
+            let rooms = read_statuses(&status_file)?;
+            if rooms.is_empty() {
+                println!("no rooms in {}", status_file.display());
+            }
+
+            for (utxo_id, room) in rooms {
+                let room_id = room
+                    .room_id
+                    .map_or_else(|| "-".to_string(), |room_id| room_id.to_string());
+                println!(
+                    "utxo {utxo_id}: room {room_id}, {:?}, {:?}, updated at {}",
+                    room.status, room.outcome, room.updated_at
+                );
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn keygen(bits: usize, out: &Path) -> CliResult<()> {
+    let private_key = rsa::generate_private_key(bits)?;
+    let public_key = RsaPublicKey::from(&private_key);
+
+    private_key.write_pkcs8_pem_file(out, LineEnding::LF)?;
+    public_key.write_public_key_pem_file(out.with_extension("pub"), LineEnding::LF)?;
+
+    Ok(())
+}
+
+async fn join(
+    coordinator: SocketAddr,
+    node: Node<LocalWallet, RoomMemoryStorage<LocalWallet>, FileLedger>,
+    ledger: FileLedger,
+    wallet: LocalWallet,
+    utxo_id: U256,
+    output: Address,
+    status_file: &Path,
+) -> CliResult<()> {
+    let transport = TcpTransport::connect(coordinator).await?;
+    let mut client = Client::new(node, transport, ledger);
+
+    let mut room = RoomStatus {
+        room_id: None,
+        status: ShuffleStatus::SearchParticipants,
+        outcome: Outcome::Running,
+        updated_at: now(),
+    };
+    write_status(status_file, utxo_id, &room)?;
+
+    let mut status_err = None;
+    let result = client
+        .run(utxo_id, output.as_bytes().to_vec(), wallet, |event| {
+            println!("{event:?}");
+
+            room.status = match event {
+                Event::Connected { room_id } => {
+                    room.room_id = Some(room_id);
+                    ShuffleStatus::SearchParticipants
+                }
+                Event::EntropyRevealed { .. } => ShuffleStatus::SearchParticipants,
+                Event::ShuffleStarted { .. } => ShuffleStatus::ShuffleStart,
+                Event::RoundProcessed { .. } => ShuffleStatus::Shuffle,
+                Event::OutputsSigned { .. } => ShuffleStatus::SigningOutputs,
+                Event::TransactionVerified { .. } => ShuffleStatus::TxHashDistribution,
+            };
+            room.updated_at = now();
+
+            if let Err(err) = write_status(status_file, utxo_id, &room) {
+                status_err.get_or_insert(err);
+            }
+        })
+        .await;
+
+    room.outcome = match &result {
+        Ok(tx_hash) => Outcome::Finished { tx_hash: *tx_hash },
+        Err(err) => Outcome::Failed {
+            error: err.to_string(),
+        },
+    };
+    room.updated_at = now();
+    write_status(status_file, utxo_id, &room)?;
+
+    if let Some(err) = status_err {
+        return Err(err);
+    }
+
+    let tx_hash = result?;
+    println!("{tx_hash:?}");
+
+    Ok(())
+}
+
+/// Parse decimal number, while [`U256`] is parsed from hex by default.
+fn parse_u256(value: &str) -> Result<U256, String> {
+    U256::from_dec_str(value).map_err(|err| err.to_string())
+}
+
+fn read_wallet(path: &Path) -> CliResult<LocalWallet> {
+    Ok(std::fs::read_to_string(path)?.trim().parse()?)
+}
+
+fn read_statuses(path: &Path) -> CliResult<BTreeMap<U256, RoomStatus>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_status(path: &Path, utxo_id: U256, room: &RoomStatus) -> CliResult<()> {
+    let mut rooms = read_statuses(path)?;
+    rooms.insert(utxo_id, room.clone());
+
+    // Nodes of the same operator may share the file, so it's replaced atomically
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(format!(".{utxo_id}.tmp"));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&rooms)?)?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,95 @@
+//! Coordinator of the shuffle serving the nodes over TCP, where every line is a JSON
+//! message of the protocol. Transfers are applied to the mock contract kept in the ledger
+//! file, so the whole shuffle can be run on a single machine.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use coin_shuffle_core::mock::ledger::FileLedger;
+use coin_shuffle_core::server::{self, Config, StorageBackend};
+use coin_shuffle_core::service::{config::ConfigError, ServiceConfig};
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Address to listen for the nodes on.
+    #[arg(long, default_value = "127.0.0.1:7700")]
+    listen: SocketAddr,
+    /// Policy of the rooms in TOML, or in JSON if the file has the `json` extension.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Minimal number of participants in the room, overrides the one from the config.
+    #[arg(long)]
+    min_participants: Option<usize>,
+    /// Maximal number of participants in the room, overrides the one from the config.
+    #[arg(long)]
+    max_participants: Option<usize>,
+    /// Period in seconds the rooms, that exceeded their time-to-live, are removed with.
+    #[arg(long, default_value_t = 10)]
+    reaper_period: u64,
+    /// Storage of the rooms and participants.
+    #[arg(long, value_enum, default_value_t = StorageBackend::Memory)]
+    storage: StorageBackend,
+    /// File of the mock contract ledger, that is shared with the nodes.
+    #[arg(long, default_value = "ledger.json")]
+    ledger: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let args = Args::parse();
+    let service = match service_config(&args) {
+        Ok(service) => service,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = Config {
+        listen: args.listen,
+        service,
+        storage: args.storage,
+        reaper_period: Duration::from_secs(args.reaper_period),
+    };
+
+    match server::run(config, FileLedger::new(args.ledger)).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("coordinator failed: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Load the config file, if it is passed, and apply the overrides from the arguments.
+fn service_config(args: &Args) -> Result<ServiceConfig, ConfigError> {
+    let mut config = match &args.config {
+        Some(path) => {
+            let content = std::fs::read_to_string(path).map_err(|err| {
+                ConfigError::Parse(format!("failed to read {}: {err}", path.display()))
+            })?;
+
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("json") => ServiceConfig::from_json(&content)?,
+                _ => ServiceConfig::from_toml(&content)?,
+            }
+        }
+        None => ServiceConfig::default(),
+    };
+
+    if let Some(min_participants) = args.min_participants {
+        config.min_participants = min_participants;
+    }
+    if let Some(max_participants) = args.max_participants {
+        config.max_participants = max_participants;
+    }
+    config.validate()?;
+
+    Ok(config)
+}
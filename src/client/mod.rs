@@ -0,0 +1,660 @@
+//! Driver that runs the node through the whole shuffle against the coordinator.
+
+use coin_shuffle_contracts_bindings::utxo::{types::Output, Contract};
+use ethers_core::types::{Signature, SignatureError, H256, U256};
+use uuid::Uuid;
+
+use crate::auth::{decoded_outputs_message, outputs_signature_message, reveal_message};
+use crate::node::{
+    self, signer::Signer, storage::RoomStorage, transaction::TransferProvider, Node,
+};
+use crate::ordering::Commitments;
+use crate::protocol::{self, Notification, Request, Response};
+
+pub mod transport;
+
+#[cfg(feature = "tcp")]
+pub mod tcp;
+
+pub use transport::Transport;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error<N, T>
+where
+    N: std::error::Error,
+    T: std::error::Error,
+{
+    #[error("node failed: {0}")]
+    Node(N),
+    #[error("transport failed: {0}")]
+    Transport(T),
+    #[error("request is rejected by the coordinator: {0}")]
+    Rejected(protocol::Error),
+    #[error("unexpected response: {0:?}")]
+    UnexpectedResponse(Box<Response>),
+    #[error("unexpected notification in the {state:?} state: {notification:?}")]
+    UnexpectedNotification {
+        state: State,
+        notification: Box<Notification>,
+    },
+    #[error("round {round} isn't the turn of the participant at position {position:?}")]
+    OutOfTurnRound {
+        round: usize,
+        position: Option<usize>,
+    },
+    #[error("room failed: {0}")]
+    RoomFailed(String),
+    #[error("invalid signature: {0}")]
+    InvalidSignature(SignatureError),
+}
+
+/// Error of the [`Client`] with the given node and transport.
+pub type ClientError<S, R, C, T> = Error<
+    node::Error<<C as Contract>::Error, <R as RoomStorage<S>>::Error, <S as Signer>::Error>,
+    <T as Transport>::Error,
+>;
+
+/// Stage of the shuffle the client is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Participant is connected and waits for the others.
+    Connected,
+    /// Participant revealed its entropy and waits for the shuffle order.
+    Revealed,
+    /// Shuffle is started and the participant waits for its round.
+    Shuffling,
+    /// Participant passed its round and waits for the outputs to sign.
+    Shuffled,
+    /// Participant signed the outputs and waits for the transaction.
+    Signed,
+}
+
+/// Progress of the shuffle reported by the [`Client::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Connected { room_id: Uuid },
+    EntropyRevealed { room_id: Uuid },
+    ShuffleStarted { room_id: Uuid },
+    RoundProcessed { room_id: Uuid, round: usize },
+    OutputsSigned { room_id: Uuid },
+    TransactionVerified { room_id: Uuid, tx_hash: H256 },
+}
+
+pub struct Client<S, R, C, T, P>
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+    C: Contract,
+    T: Transport,
+    P: TransferProvider,
+{
+    node: Node<S, R, C>,
+    transport: T,
+    transfer_provider: P,
+}
+
+impl<S, R, C, T, P> Client<S, R, C, T, P>
+where
+    S: Signer + Clone + Send + Sync,
+    S::Error: 'static,
+    R: RoomStorage<S>,
+    C: Contract,
+    T: Transport,
+    P: TransferProvider,
+{
+    pub fn new(node: Node<S, R, C>, transport: T, transfer_provider: P) -> Self {
+        Self {
+            node,
+            transport,
+            transfer_provider,
+        }
+    }
+
+    pub fn node(&self) -> &Node<S, R, C> {
+        &self.node
+    }
+
+    pub fn into_node(self) -> Node<S, R, C> {
+        self.node
+    }
+
+    /// Shuffle the UTXO into the output: connect to the room by signing the challenge of the
+    /// coordinator with the UTXO owner key, reveal the entropy and verify the shuffle order
+    /// derived from it, process the participant's round, sign the outputs and verify the
+    /// resulting transaction, whose hash is returned. Every passed stage is reported to
+    /// `on_event`.
+    ///
+    /// Notifications of the other rooms are skipped, so the UTXOs shuffled at the same time
+    /// need a transport each.
+    pub async fn run<F>(
+        &mut self,
+        utxo_id: U256,
+        output: Vec<u8>,
+        signer: S,
+        mut on_event: F,
+    ) -> Result<H256, ClientError<S, R, C, T>>
+    where
+        F: FnMut(Event),
+    {
+        let rsa_pubkey = self
+            .node
+            .init_room(utxo_id, output, signer)
+            .await
+            .map_err(Error::Node)?;
+
+        let (room_id, nonce, amount, fee) =
+            match self.request(Request::Challenge { utxo_id }).await? {
+                Response::Challenge {
+                    room_id,
+                    nonce,
+                    amount,
+                    fee,
+                } => (room_id, nonce, amount, fee),
+                response => return Err(Error::UnexpectedResponse(Box::new(response))),
+            };
+
+        self.node
+            .join_room(utxo_id, room_id, amount, fee)
+            .await
+            .map_err(Error::Node)?;
+        let (session_key, commitment, change, signature) = self
+            .node
+            .sign_challenge(utxo_id, nonce)
+            .await
+            .map_err(Error::Node)?;
+
+        match self
+            .request(Request::ConnectParticipant {
+                utxo_id,
+                rsa_pubkey,
+                session_key,
+                commitment,
+                change,
+                signature,
+            })
+            .await?
+        {
+            Response::Connected { room_id: connected } if connected == room_id => {}
+            response => return Err(Error::UnexpectedResponse(Box::new(response))),
+        }
+
+        let mut state = State::Connected;
+        // Position of the participant in the shuffle order is known once it is started
+        let mut position = None;
+        on_event(Event::Connected { room_id });
+
+        loop {
+            let notification = self
+                .transport
+                .notification()
+                .await
+                .map_err(Error::Transport)?;
+
+            // Notifications of the room the transport was used for before are stale
+            if notification.room_id() != &room_id {
+                continue;
+            }
+
+            match (state, notification) {
+                (State::Connected, Notification::RevealEntropy { commitments, .. }) => {
+                    self.reveal_entropy(room_id, utxo_id, commitments).await?;
+
+                    state = State::Revealed;
+                    on_event(Event::EntropyRevealed { room_id });
+                }
+                (
+                    State::Revealed,
+                    Notification::ShuffleStarted {
+                        participants,
+                        reveals,
+                        public_keys,
+                        ..
+                    },
+                ) => {
+                    self.node
+                        .verify_order(utxo_id, &participants, &reveals)
+                        .await
+                        .map_err(Error::Node)?;
+                    self.node
+                        .update_shuffle_info(public_keys, utxo_id)
+                        .await
+                        .map_err(Error::Node)?;
+                    position = participants.iter().position(|id| *id == utxo_id);
+
+                    state = State::Shuffling;
+                    on_event(Event::ShuffleStarted { room_id });
+                }
+                (State::Shuffling, Notification::ShuffleRound { round, .. }) => {
+                    if position != Some(round) {
+                        return Err(Error::OutOfTurnRound { round, position });
+                    }
+                    self.process_round(room_id, utxo_id).await?;
+
+                    state = State::Shuffled;
+                    on_event(Event::RoundProcessed { room_id, round });
+                }
+                (State::Shuffled, Notification::SignOutputs { outputs, .. }) => {
+                    self.sign_outputs(room_id, utxo_id, outputs).await?;
+
+                    state = State::Signed;
+                    on_event(Event::OutputsSigned { room_id });
+                }
+                (State::Signed, Notification::TransactionHash { tx_hash, .. }) => {
+                    self.node
+                        .verify_tx(&self.transfer_provider, utxo_id, tx_hash)
+                        .await
+                        .map_err(Error::Node)?;
+
+                    on_event(Event::TransactionVerified { room_id, tx_hash });
+                    return Ok(tx_hash);
+                }
+                (_, Notification::RoomFailed { reason, .. }) => {
+                    self.node.abort_room(utxo_id).await.map_err(Error::Node)?;
+
+                    return Err(Error::RoomFailed(reason));
+                }
+                (state, notification) => {
+                    return Err(Error::UnexpectedNotification {
+                        state,
+                        notification: Box::new(notification),
+                    })
+                }
+            }
+        }
+    }
+
+    async fn reveal_entropy(
+        &mut self,
+        room_id: Uuid,
+        utxo_id: U256,
+        commitments: Commitments,
+    ) -> Result<(), ClientError<S, R, C, T>> {
+        let entropy = self
+            .node
+            .reveal_entropy(utxo_id, commitments)
+            .await
+            .map_err(Error::Node)?;
+        let session_signature = self
+            .node
+            .sign_by_session_key(utxo_id, reveal_message(&room_id, utxo_id, entropy))
+            .await
+            .map_err(Error::Node)?;
+
+        match self
+            .request(Request::RevealEntropy {
+                utxo_id,
+                entropy,
+                session_signature,
+            })
+            .await?
+        {
+            Response::Revealed { room_id: revealed } if revealed == room_id => Ok(()),
+            response => Err(Error::UnexpectedResponse(Box::new(response))),
+        }
+    }
+
+    async fn process_round(
+        &mut self,
+        room_id: Uuid,
+        utxo_id: U256,
+    ) -> Result<(), ClientError<S, R, C, T>> {
+        let encoded_outputs = match self.request(Request::EncodedOutputs { utxo_id }).await? {
+            Response::EncodedOutputs { outputs } => outputs,
+            response => return Err(Error::UnexpectedResponse(Box::new(response))),
+        };
+
+        let decoded_outputs = self
+            .node
+            .shuffle_round(encoded_outputs, utxo_id)
+            .await
+            .map_err(Error::Node)?;
+        let session_signature = self
+            .node
+            .sign_by_session_key(
+                utxo_id,
+                decoded_outputs_message(&room_id, utxo_id, &decoded_outputs),
+            )
+            .await
+            .map_err(Error::Node)?;
+
+        match self
+            .request(Request::PassDecodedOutputs {
+                utxo_id,
+                decoded_outputs,
+                session_signature,
+            })
+            .await?
+        {
+            Response::ShuffleRound { .. } | Response::ShuffleFinished { .. } => {
+                self.node.confirm_round(utxo_id).await.map_err(Error::Node)
+            }
+            response => Err(Error::UnexpectedResponse(Box::new(response))),
+        }
+    }
+
+    async fn sign_outputs(
+        &mut self,
+        room_id: Uuid,
+        utxo_id: U256,
+        outputs: Vec<Output>,
+    ) -> Result<(), ClientError<S, R, C, T>> {
+        let signature = self
+            .node
+            .sign_tx(utxo_id, outputs)
+            .await
+            .map_err(Error::Node)?;
+        let session_signature = self
+            .node
+            .sign_by_session_key(
+                utxo_id,
+                outputs_signature_message(&room_id, utxo_id, &signature),
+            )
+            .await
+            .map_err(Error::Node)?;
+        let signature =
+            Signature::try_from(signature.as_slice()).map_err(Error::InvalidSignature)?;
+
+        match self
+            .request(Request::PassSignature {
+                room_id,
+                utxo_id,
+                signature,
+                session_signature,
+            })
+            .await?
+        {
+            Response::SignatureAccepted => Ok(()),
+            response => Err(Error::UnexpectedResponse(Box::new(response))),
+        }
+    }
+
+    /// Send the request and turn the error response into [`Error::Rejected`].
+    async fn request(&mut self, request: Request) -> Result<Response, ClientError<S, R, C, T>> {
+        match self
+            .transport
+            .request(request)
+            .await
+            .map_err(Error::Transport)?
+        {
+            Response::Error(err) => Err(Error::Rejected(err)),
+            response => Ok(response),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "service"))]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ethers_core::types::{Address, U256};
+    use ethers_signers::{LocalWallet, Signer};
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+    use super::{Client, Error, Event, Transport};
+    use crate::mock::MockContract;
+    use crate::node::{storage::RoomMemoryStorage, Node};
+    use crate::protocol::{codec, Envelope, Message, Notification, Request, Response};
+    use crate::rsa::MIN_KEY_SIZE;
+    use crate::service::{coordinator::Coordinator, Service};
+
+    #[derive(thiserror::Error, Debug)]
+    enum TestError {
+        #[error("invalid message: {0}")]
+        Codec(#[from] codec::Error),
+        #[error("connection is closed")]
+        Closed,
+        #[error("unexpected message: {0:?}")]
+        UnexpectedMessage(Box<Message>),
+    }
+
+    /// In-process coordinator, that exchanges the encoded envelopes with the connections
+    /// and pushes the notifications of the request before its response.
+    struct Wire {
+        coordinator: Coordinator<MockContract>,
+        connections: Mutex<HashMap<U256, UnboundedSender<String>>>,
+        /// Ids of the requests in order they are received by the coordinator.
+        requests: Mutex<Vec<(U256, u64)>>,
+        order: tokio::sync::Mutex<()>,
+        /// Notification of the first round is pushed to every connection instead of the
+        /// first participant.
+        broadcast_first_round: bool,
+    }
+
+    impl Wire {
+        fn new(coordinator: Coordinator<MockContract>) -> Arc<Self> {
+            Arc::new(Self {
+                coordinator,
+                connections: Mutex::new(HashMap::new()),
+                requests: Mutex::new(Vec::new()),
+                order: tokio::sync::Mutex::new(()),
+                broadcast_first_round: false,
+            })
+        }
+
+        fn connect(self: &Arc<Self>, utxo_id: U256) -> WireTransport {
+            let (sender, lines) = mpsc::unbounded_channel();
+            self.connections.lock().unwrap().insert(utxo_id, sender);
+
+            WireTransport {
+                wire: Arc::clone(self),
+                utxo_id,
+                lines,
+                last_id: 0,
+                notifications: VecDeque::new(),
+            }
+        }
+
+        async fn handle(&self, utxo_id: U256, line: String) -> Result<(), TestError> {
+            let _order = self.order.lock().await;
+
+            let (id, request) = match codec::from_json(&line)?.message {
+                Message::Request { id, request } => (id, request),
+                message => return Err(TestError::UnexpectedMessage(Box::new(message))),
+            };
+            self.requests.lock().unwrap().push((utxo_id, id));
+
+            let handled = self.coordinator.handle(request).await;
+
+            let connections = self.connections.lock().unwrap();
+            for (receiver, notification) in handled.notifications {
+                let receivers = match notification {
+                    Notification::ShuffleRound { round: 0, .. } if self.broadcast_first_round => {
+                        connections.keys().copied().collect()
+                    }
+                    _ => vec![receiver],
+                };
+                let line = codec::to_json(&Envelope::new(Message::Notification(notification)))?;
+                for receiver in receivers {
+                    if let Some(connection) = connections.get(&receiver) {
+                        let _ = connection.send(line.clone());
+                    }
+                }
+            }
+
+            let response = Message::Response {
+                id,
+                response: handled.response,
+            };
+            let _ = connections[&utxo_id].send(codec::to_json(&Envelope::new(response))?);
+
+            Ok(())
+        }
+    }
+
+    struct WireTransport {
+        wire: Arc<Wire>,
+        utxo_id: U256,
+        lines: UnboundedReceiver<String>,
+        last_id: u64,
+        notifications: VecDeque<Notification>,
+    }
+
+    impl WireTransport {
+        async fn receive(&mut self) -> Result<Message, TestError> {
+            let line = self.lines.recv().await.ok_or(TestError::Closed)?;
+
+            Ok(codec::from_json(&line)?.message)
+        }
+    }
+
+    #[async_trait]
+    impl Transport for WireTransport {
+        type Error = TestError;
+
+        async fn request(&mut self, request: Request) -> Result<Response, Self::Error> {
+            self.last_id += 1;
+            let id = self.last_id;
+
+            let line = codec::to_json(&Envelope::new(Message::Request { id, request }))?;
+            self.wire.handle(self.utxo_id, line).await?;
+
+            loop {
+                match self.receive().await? {
+                    Message::Response {
+                        id: response_id,
+                        response,
+                    } if response_id == id => return Ok(response),
+                    Message::Notification(notification) => {
+                        self.notifications.push_back(notification)
+                    }
+                    message => return Err(TestError::UnexpectedMessage(Box::new(message))),
+                }
+            }
+        }
+
+        async fn notification(&mut self) -> Result<Notification, Self::Error> {
+            if let Some(notification) = self.notifications.pop_front() {
+                return Ok(notification);
+            }
+
+            match self.receive().await? {
+                Message::Notification(notification) => Ok(notification),
+                message => Err(TestError::UnexpectedMessage(Box::new(message))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_finish_shuffle_over_envelopes() {
+        let contract = MockContract::new();
+        let service = Service::new();
+
+        let wallets: Vec<LocalWallet> = (0..3)
+            .map(|_| LocalWallet::new(&mut rand::thread_rng()))
+            .collect();
+        let utxos: Vec<U256> = wallets
+            .iter()
+            .map(|wallet| contract.deposit(Address::zero(), wallet.address(), U256::from(100)))
+            .collect();
+        let room = service
+            .create_room(Address::zero(), U256::from(100), utxos.clone())
+            .await
+            .expect("failed to create room");
+
+        let wire = Wire::new(Coordinator::new(service, contract.clone()));
+
+        let mut tasks = Vec::new();
+        for (wallet, utxo_id) in wallets.into_iter().zip(utxos.iter().copied()) {
+            let node = Node::new(RoomMemoryStorage::new(), contract.clone())
+                .with_rsa_key_size(MIN_KEY_SIZE);
+            let mut client = Client::new(node, wire.connect(utxo_id), contract.clone());
+            let output = Address::random().as_bytes().to_vec();
+
+            tasks.push(tokio::spawn(async move {
+                let mut events = Vec::new();
+                let tx_hash = client
+                    .run(utxo_id, output, wallet, |event| events.push(event))
+                    .await
+                    .expect("client failed");
+
+                (tx_hash, events)
+            }));
+        }
+
+        let mut hashes = Vec::new();
+        for task in tasks {
+            let (tx_hash, events) = task.await.expect("client task panicked");
+            assert_eq!(events.len(), 6);
+            assert_eq!(events[0], Event::Connected { room_id: room.id });
+            assert_eq!(events[1], Event::EntropyRevealed { room_id: room.id });
+            assert_eq!(events[2], Event::ShuffleStarted { room_id: room.id });
+            assert!(
+                matches!(events[3], Event::RoundProcessed { room_id, .. } if room_id == room.id)
+            );
+            assert_eq!(events[4], Event::OutputsSigned { room_id: room.id });
+            assert_eq!(
+                events[5],
+                Event::TransactionVerified {
+                    room_id: room.id,
+                    tx_hash
+                }
+            );
+            hashes.push(tx_hash);
+        }
+        assert!(hashes.iter().all(|hash| *hash == hashes[0]));
+        assert!(contract.transfer_by_hash(hashes[0]).is_some());
+
+        // Ids of every connection are sequential, so each response matched its request
+        let requests = wire.requests.lock().unwrap();
+        for utxo_id in utxos {
+            let ids: Vec<u64> = requests
+                .iter()
+                .filter(|(requester, _)| *requester == utxo_id)
+                .map(|(_, id)| *id)
+                .collect();
+            assert_eq!(ids, (1..=ids.len() as u64).collect::<Vec<_>>());
+            assert_eq!(ids.len(), 6);
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_turn_round_is_rejected() {
+        let contract = MockContract::new();
+        let service = Service::new();
+
+        let wallets: Vec<LocalWallet> = (0..2)
+            .map(|_| LocalWallet::new(&mut rand::thread_rng()))
+            .collect();
+        let utxos: Vec<U256> = wallets
+            .iter()
+            .map(|wallet| contract.deposit(Address::zero(), wallet.address(), U256::from(100)))
+            .collect();
+        service
+            .create_room(Address::zero(), U256::from(100), utxos.clone())
+            .await
+            .expect("failed to create room");
+
+        let mut wire = Wire::new(Coordinator::new(service, contract.clone()));
+        Arc::get_mut(&mut wire).unwrap().broadcast_first_round = true;
+
+        // The first round is pushed to the second participant too, which rejects it, while
+        // the first one waits for the next stage forever
+        let (sender, mut rejections) = mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for (wallet, utxo_id) in wallets.into_iter().zip(utxos.iter().copied()) {
+            let node = Node::new(RoomMemoryStorage::new(), contract.clone())
+                .with_rsa_key_size(MIN_KEY_SIZE);
+            let mut client = Client::new(node, wire.connect(utxo_id), contract.clone());
+            let output = Address::random().as_bytes().to_vec();
+            let sender = sender.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let result = client.run(utxo_id, output, wallet, |_| {}).await;
+                let _ = sender.send(matches!(
+                    result,
+                    Err(Error::OutOfTurnRound {
+                        round: 0,
+                        position: Some(1)
+                    })
+                ));
+            }));
+        }
+
+        assert_eq!(rejections.recv().await, Some(true));
+        for task in tasks {
+            task.abort();
+        }
+    }
+}
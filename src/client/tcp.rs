@@ -0,0 +1,93 @@
+//! [`Transport`] over TCP, where every [`Envelope`] is a line of JSON.
+
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::Transport;
+use crate::protocol::{codec, Envelope, Message, Notification, Request, Response};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TcpError {
+    #[error("connection failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid message: {0}")]
+    Codec(#[from] codec::Error),
+    #[error("connection is closed by the coordinator")]
+    Closed,
+    #[error("unexpected message: {0:?}")]
+    UnexpectedMessage(Box<Message>),
+}
+
+pub struct TcpTransport {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    last_id: u64,
+    /// Notifications received while waiting for the response.
+    notifications: VecDeque<Notification>,
+}
+
+impl TcpTransport {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, TcpError> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            last_id: 0,
+            notifications: VecDeque::new(),
+        })
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), TcpError> {
+        let mut line = codec::to_json(&Envelope::new(message))?;
+        line.push('\n');
+
+        self.writer.write_all(line.as_bytes()).await?;
+
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Message, TcpError> {
+        let line = self.lines.next_line().await?.ok_or(TcpError::Closed)?;
+
+        Ok(codec::from_json(&line)?.message)
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    type Error = TcpError;
+
+    async fn request(&mut self, request: Request) -> Result<Response, Self::Error> {
+        self.last_id += 1;
+        let id = self.last_id;
+
+        self.send(Message::Request { id, request }).await?;
+
+        loop {
+            match self.receive().await? {
+                Message::Response {
+                    id: response_id,
+                    response,
+                } if response_id == id => return Ok(response),
+                Message::Notification(notification) => self.notifications.push_back(notification),
+                message => return Err(TcpError::UnexpectedMessage(Box::new(message))),
+            }
+        }
+    }
+
+    async fn notification(&mut self) -> Result<Notification, Self::Error> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(notification);
+        }
+
+        match self.receive().await? {
+            Message::Notification(notification) => Ok(notification),
+            message => Err(TcpError::UnexpectedMessage(Box::new(message))),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+
+use crate::protocol::{Notification, Request, Response};
+
+/// Connection of the node to the coordinator. Implementation is responsible for wrapping the
+/// messages into [`Envelope`](crate::protocol::Envelope)s, encoding them and matching the
+/// responses with their requests. Notifications received while waiting for the response
+/// must be kept for the later [`Transport::notification`] calls.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Transport {
+    type Error: std::error::Error;
+
+    /// Send the request to the coordinator and wait for its response.
+    async fn request(&mut self, request: Request) -> Result<Response, Self::Error>;
+
+    /// Wait for the next notification pushed by the coordinator.
+    async fn notification(&mut self) -> Result<Notification, Self::Error>;
+}
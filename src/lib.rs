@@ -1,4 +1,8 @@
+pub mod auth;
+pub mod clock;
+pub mod ordering;
 pub mod rsa;
+pub mod telemetry;
 pub mod types;
 
 #[cfg(feature = "service")]
@@ -6,3 +10,22 @@ pub mod service;
 
 #[cfg(feature = "node")]
 pub mod node;
+
+#[cfg(feature = "protocol")]
+pub mod protocol;
+
+#[cfg(feature = "client")]
+pub mod client;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(any(test, feature = "dev-mock"))]
+pub mod mock;
+
+#[cfg(all(
+    any(test, feature = "test-utils"),
+    feature = "service",
+    feature = "client"
+))]
+pub mod simulation;
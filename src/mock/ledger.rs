@@ -0,0 +1,125 @@
+//! [`MockContract`] kept in a JSON file, so the coordinator and the nodes running in
+//! separate processes on the same machine share the UTXOs and transfers.
+//!
+//! The file is read before and written after every operation, writes replace the file
+//! atomically. Operations of different processes aren't serialized, so the ledger is
+//! meant for the local runs only.
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use coin_shuffle_contracts_bindings::utxo::{
+    types::{Input, Output, Utxo},
+    Contract,
+};
+use ethers_core::types::{Address, H256, U256};
+
+use super::{MockContract, MockError, Snapshot};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LedgerError {
+    #[error("failed to access ledger file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid ledger file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("contract rejected the call: {0}")]
+    Contract(#[from] MockError),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileLedger {
+    path: PathBuf,
+}
+
+impl FileLedger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the contract from the file, missing file is an empty ledger.
+    pub fn load(&self) -> Result<MockContract, LedgerError> {
+        let snapshot: Snapshot = match std::fs::read(&self.path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Snapshot::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(MockContract::from_snapshot(snapshot))
+    }
+
+    pub fn save(&self, contract: &MockContract) -> Result<(), LedgerError> {
+        let content = serde_json::to_vec_pretty(&contract.snapshot())?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+
+    /// Create the UTXO of the owner with given token and amount and return its id.
+    pub fn deposit(
+        &self,
+        token: Address,
+        owner: Address,
+        amount: U256,
+    ) -> Result<U256, LedgerError> {
+        let contract = self.load()?;
+        let id = contract.deposit(token, owner, amount);
+        self.save(&contract)?;
+
+        Ok(id)
+    }
+
+    /// Apply the transfer to the ledger, see [`MockContract::transfer`].
+    pub fn transfer(&self, inputs: Vec<Input>, outputs: Vec<Output>) -> Result<H256, LedgerError> {
+        let contract = self.load()?;
+        let result = contract.transfer(inputs, outputs);
+        // Failed calls are recorded too
+        self.save(&contract)?;
+
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl Contract for FileLedger {
+    type Error = LedgerError;
+
+    async fn get_utxo_by_id(&self, id: U256) -> Result<Option<Utxo>, Self::Error> {
+        Ok(self.load()?.utxo(id))
+    }
+}
+
+#[cfg(feature = "node")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl crate::node::transaction::TransferProvider for FileLedger {
+    type Error = LedgerError;
+
+    async fn get_transfer(
+        &self,
+        tx_hash: H256,
+    ) -> Result<Option<crate::node::transaction::Transfer>, Self::Error> {
+        Ok(self.load()?.get_transfer(tx_hash).await?)
+    }
+}
+
+#[cfg(feature = "service")]
+#[async_trait]
+impl crate::service::coordinator::TransactionSender for FileLedger {
+    type Error = LedgerError;
+
+    async fn send_transfer(
+        &self,
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+    ) -> Result<H256, Self::Error> {
+        self.transfer(inputs, outputs)
+    }
+}
@@ -0,0 +1,548 @@
+//! In-memory implementation of the UTXO contract for the tests, that doesn't require a
+//! running chain.
+//!
+//! [`MockContract`] keeps the UTXOs and applies the transfers with the same checks as the
+//! contract does: inputs must exist, be unspent and signed by their owners, all of them
+//! must have the same token and their amounts must be equal to the amounts of the outputs.
+//! Every transfer call is recorded, and errors of the next calls can be scripted with
+//! [`MockContract::fail_next`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use coin_shuffle_contracts_bindings::utxo::{
+    types::{Input, Output, Utxo},
+    Contract,
+};
+use ethers_core::abi::AbiEncode;
+use ethers_core::types::{Address, Signature, H256, U256};
+use ethers_core::utils::keccak256;
+
+#[cfg(feature = "protocol")]
+pub mod ledger;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum MockError {
+    #[error("utxo doesn't exist id: {0}")]
+    UtxoDoesntExist(U256),
+    #[error("utxo is already spent id: {0}")]
+    UtxoIsSpent(U256),
+    #[error("utxo is repeated in the inputs id: {0}")]
+    DuplicateInput(U256),
+    #[error("transfer has no inputs")]
+    EmptyInputs,
+    #[error("transfer has no outputs")]
+    EmptyOutputs,
+    #[error("utxo has another token than the first input id: {0}")]
+    TokenMismatch(U256),
+    #[error("inputs amount {inputs} isn't equal to outputs amount {outputs}")]
+    AmountMismatch { inputs: U256, outputs: U256 },
+    #[error("sum of the transfer amounts overflows")]
+    AmountOverflow,
+    #[error("input isn't signed by the utxo owner id: {0}")]
+    InvalidSignature(U256),
+    #[error("scripted error: {0}")]
+    Scripted(String),
+}
+
+/// Method of the mock, whose next call can be failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Call {
+    GetUtxo,
+    Transfer,
+    GetTransfer,
+}
+
+/// Recorded call of the [`MockContract::transfer`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferCall {
+    pub inputs: Vec<Input>,
+    pub outputs: Vec<Output>,
+    /// Hash of the transaction or the error the call is failed with.
+    pub result: Result<H256, MockError>,
+}
+
+/// UTXOs and transfer calls of the [`MockContract`], scripted errors aren't included.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub utxos: Vec<Utxo>,
+    pub transfers: Vec<TransferCall>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MockContract {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    utxos: HashMap<U256, Utxo>,
+    last_id: U256,
+    transfers: Vec<TransferCall>,
+    scripted: HashMap<Call, VecDeque<MockError>>,
+}
+
+impl MockContract {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the contract with the UTXOs and transfers of the snapshot.
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let last_id = snapshot
+            .utxos
+            .iter()
+            .map(|utxo| utxo.id)
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            state: Arc::new(Mutex::new(State {
+                utxos: snapshot
+                    .utxos
+                    .into_iter()
+                    .map(|utxo| (utxo.id, utxo))
+                    .collect(),
+                last_id,
+                transfers: snapshot.transfers,
+                scripted: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state();
+
+        let mut utxos: Vec<Utxo> = state.utxos.values().cloned().collect();
+        utxos.sort_by_key(|utxo| utxo.id);
+
+        Snapshot {
+            utxos,
+            transfers: state.transfers.clone(),
+        }
+    }
+
+    /// Create the UTXO of the owner with given token and amount and return its id.
+    pub fn deposit(&self, token: Address, owner: Address, amount: U256) -> U256 {
+        self.state().deposit(token, owner, amount)
+    }
+
+    pub fn utxo(&self, id: U256) -> Option<Utxo> {
+        self.state().utxos.get(&id).cloned()
+    }
+
+    /// Return UTXOs of the owner, including the spent ones.
+    pub fn utxos_of(&self, owner: Address) -> Vec<Utxo> {
+        let mut utxos: Vec<Utxo> = self
+            .state()
+            .utxos
+            .values()
+            .filter(|utxo| utxo.owner == owner)
+            .cloned()
+            .collect();
+        utxos.sort_by_key(|utxo| utxo.id);
+
+        utxos
+    }
+
+    /// Spend the inputs and create the UTXOs of the outputs, which have the token of the
+    /// inputs. Return hash of the transaction.
+    pub fn transfer(&self, inputs: Vec<Input>, outputs: Vec<Output>) -> Result<H256, MockError> {
+        let mut state = self.state();
+
+        let result = match state.scripted(Call::Transfer) {
+            Some(err) => Err(err),
+            None => state.transfer(&inputs, &outputs),
+        };
+
+        state.transfers.push(TransferCall {
+            inputs,
+            outputs,
+            result: result.clone(),
+        });
+
+        result
+    }
+
+    /// Return all the transfer calls in order they were made.
+    pub fn transfer_calls(&self) -> Vec<TransferCall> {
+        self.state().transfers.clone()
+    }
+
+    /// Return successful transfer made by the transaction with given hash.
+    pub fn transfer_by_hash(&self, tx_hash: H256) -> Option<TransferCall> {
+        self.state()
+            .transfers
+            .iter()
+            .find(|call| call.result.as_ref() == Ok(&tx_hash))
+            .cloned()
+    }
+
+    /// Fail the next call of the method with given error. Errors scripted for the same
+    /// method are returned in order they were added.
+    pub fn fail_next(&self, call: Call, err: MockError) {
+        self.state()
+            .scripted
+            .entry(call)
+            .or_default()
+            .push_back(err);
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("mock contract lock is poisoned")
+    }
+}
+
+impl State {
+    fn deposit(&mut self, token: Address, owner: Address, amount: U256) -> U256 {
+        self.last_id += U256::one();
+
+        let id = self.last_id;
+        self.utxos.insert(
+            id,
+            Utxo {
+                id,
+                token,
+                owner,
+                amount,
+                ..Default::default()
+            },
+        );
+
+        id
+    }
+
+    fn scripted(&mut self, call: Call) -> Option<MockError> {
+        self.scripted.get_mut(&call)?.pop_front()
+    }
+
+    fn transfer(&mut self, inputs: &[Input], outputs: &[Output]) -> Result<H256, MockError> {
+        if inputs.is_empty() {
+            return Err(MockError::EmptyInputs);
+        }
+        if outputs.is_empty() {
+            return Err(MockError::EmptyOutputs);
+        }
+
+        let mut token = None;
+        let mut inputs_amount = U256::zero();
+        let mut spent = HashSet::new();
+
+        for input in inputs.iter() {
+            let utxo = self
+                .utxos
+                .get(&input.id)
+                .ok_or(MockError::UtxoDoesntExist(input.id))?;
+
+            if utxo.is_spent {
+                return Err(MockError::UtxoIsSpent(input.id));
+            }
+            if !spent.insert(input.id) {
+                return Err(MockError::DuplicateInput(input.id));
+            }
+            if *token.get_or_insert(utxo.token) != utxo.token {
+                return Err(MockError::TokenMismatch(input.id));
+            }
+            if !is_signed_by_owner(utxo, input, outputs) {
+                return Err(MockError::InvalidSignature(input.id));
+            }
+
+            inputs_amount = inputs_amount
+                .checked_add(utxo.amount)
+                .ok_or(MockError::AmountOverflow)?;
+        }
+
+        let outputs_amount = outputs
+            .iter()
+            .try_fold(U256::zero(), |sum, output| sum.checked_add(output.amount))
+            .ok_or(MockError::AmountOverflow)?;
+        if inputs_amount != outputs_amount {
+            return Err(MockError::AmountMismatch {
+                inputs: inputs_amount,
+                outputs: outputs_amount,
+            });
+        }
+
+        for id in spent {
+            if let Some(utxo) = self.utxos.get_mut(&id) {
+                utxo.is_spent = true;
+            }
+        }
+
+        let token = token.unwrap_or_default();
+        for output in outputs.iter() {
+            self.deposit(token, output.owner, output.amount);
+        }
+
+        Ok(H256::random())
+    }
+}
+
+/// Check that the input signature is made by the UTXO owner over the message that the
+/// node signs: UTXO id followed by the UTXO amount and the owner of every output.
+fn is_signed_by_owner(utxo: &Utxo, input: &Input, outputs: &[Output]) -> bool {
+    let Ok(signature) = Signature::try_from(input.signature.as_ref()) else {
+        return false;
+    };
+
+    let mut message = utxo.id.encode();
+    for output in outputs.iter() {
+        message.append(&mut utxo.amount.encode());
+        message.extend_from_slice(output.owner.as_bytes());
+    }
+
+    signature
+        .verify(keccak256(message).to_vec(), utxo.owner)
+        .is_ok()
+}
+
+#[async_trait]
+impl Contract for MockContract {
+    type Error = MockError;
+
+    async fn get_utxo_by_id(&self, id: U256) -> Result<Option<Utxo>, Self::Error> {
+        let mut state = self.state();
+        if let Some(err) = state.scripted(Call::GetUtxo) {
+            return Err(err);
+        }
+
+        Ok(state.utxos.get(&id).cloned())
+    }
+}
+
+#[cfg(feature = "node")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl crate::node::transaction::TransferProvider for MockContract {
+    type Error = MockError;
+
+    async fn get_transfer(
+        &self,
+        tx_hash: H256,
+    ) -> Result<Option<crate::node::transaction::Transfer>, Self::Error> {
+        if let Some(err) = self.state().scripted(Call::GetTransfer) {
+            return Err(err);
+        }
+
+        Ok(self
+            .transfer_by_hash(tx_hash)
+            .map(|call| crate::node::transaction::Transfer {
+                inputs: call.inputs.iter().map(|input| input.id).collect(),
+                outputs: call.outputs,
+            }))
+    }
+}
+
+#[cfg(all(feature = "service", feature = "protocol"))]
+#[async_trait]
+impl crate::service::coordinator::TransactionSender for MockContract {
+    type Error = MockError;
+
+    async fn send_transfer(
+        &self,
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+    ) -> Result<H256, Self::Error> {
+        self.transfer(inputs, outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coin_shuffle_contracts_bindings::utxo::{
+        types::{Input, Output},
+        Contract,
+    };
+    use ethers_core::abi::AbiEncode;
+    use ethers_core::types::{Address, U256};
+    use ethers_core::utils::keccak256;
+    use ethers_signers::{LocalWallet, Signer};
+
+    use super::{Call, MockContract, MockError};
+
+    const AMOUNT: u64 = 100;
+
+    async fn sign(wallet: &LocalWallet, utxo_id: U256, amount: U256, outputs: &[Output]) -> Input {
+        let mut message = utxo_id.encode();
+        for output in outputs.iter() {
+            message.append(&mut amount.encode());
+            message.extend_from_slice(output.owner.as_bytes());
+        }
+
+        let signature = wallet
+            .sign_message(keccak256(message))
+            .await
+            .expect("failed to sign");
+
+        Input {
+            id: utxo_id,
+            signature: signature.to_vec().into(),
+        }
+    }
+
+    fn outputs(amounts: &[u64]) -> Vec<Output> {
+        amounts
+            .iter()
+            .map(|amount| Output {
+                amount: U256::from(*amount),
+                owner: Address::random(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let contract = MockContract::new();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let token = Address::random();
+        let utxo_id = contract.deposit(token, wallet.address(), U256::from(AMOUNT));
+
+        let outputs = outputs(&[60, 40]);
+        let input = sign(&wallet, utxo_id, U256::from(AMOUNT), &outputs).await;
+
+        contract
+            .transfer(vec![input.clone()], outputs.clone())
+            .expect("transfer failed");
+
+        let utxo = contract
+            .get_utxo_by_id(utxo_id)
+            .await
+            .expect("failed to get utxo")
+            .expect("utxo is absent");
+        assert!(utxo.is_spent);
+        for output in outputs.iter() {
+            let created = contract.utxos_of(output.owner);
+            assert_eq!(created.len(), 1);
+            assert_eq!(created[0].amount, output.amount);
+            assert_eq!(created[0].token, token);
+        }
+
+        assert_eq!(
+            contract.transfer(vec![input], outputs),
+            Err(MockError::UtxoIsSpent(utxo_id))
+        );
+        assert_eq!(contract.transfer_calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn transfer_rules() {
+        let contract = MockContract::new();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let first = contract.deposit(Address::zero(), wallet.address(), U256::from(AMOUNT));
+        let second = contract.deposit(Address::random(), wallet.address(), U256::from(AMOUNT));
+
+        let outputs = outputs(&[AMOUNT]);
+        let input = sign(&wallet, first, U256::from(AMOUNT), &outputs).await;
+
+        assert_eq!(
+            contract.transfer(vec![], outputs.clone()),
+            Err(MockError::EmptyInputs)
+        );
+        assert_eq!(
+            contract.transfer(vec![input.clone()], vec![]),
+            Err(MockError::EmptyOutputs)
+        );
+        assert_eq!(
+            contract.transfer(vec![input.clone(), input.clone()], outputs.clone()),
+            Err(MockError::DuplicateInput(first))
+        );
+
+        let other = sign(&wallet, second, U256::from(AMOUNT), &outputs).await;
+        assert_eq!(
+            contract.transfer(vec![input.clone(), other], outputs.clone()),
+            Err(MockError::TokenMismatch(second))
+        );
+
+        let stranger = LocalWallet::new(&mut rand::thread_rng());
+        assert_eq!(
+            contract.transfer(
+                vec![sign(&stranger, first, U256::from(AMOUNT), &outputs).await],
+                outputs.clone()
+            ),
+            Err(MockError::InvalidSignature(first))
+        );
+
+        let unbalanced = self::outputs(&[AMOUNT + 1]);
+        assert_eq!(
+            contract.transfer(
+                vec![sign(&wallet, first, U256::from(AMOUNT), &unbalanced).await],
+                unbalanced.clone()
+            ),
+            Err(MockError::AmountMismatch {
+                inputs: U256::from(AMOUNT),
+                outputs: U256::from(AMOUNT + 1),
+            })
+        );
+
+        let unknown = U256::from(42);
+        assert_eq!(
+            contract.transfer(
+                vec![sign(&wallet, unknown, U256::from(AMOUNT), &outputs).await],
+                outputs
+            ),
+            Err(MockError::UtxoDoesntExist(unknown))
+        );
+
+        let overflowing = vec![
+            Output {
+                amount: U256::MAX,
+                owner: Address::random(),
+            },
+            Output {
+                amount: U256::one(),
+                owner: Address::random(),
+            },
+        ];
+        assert_eq!(
+            contract.transfer(
+                vec![sign(&wallet, first, U256::from(AMOUNT), &overflowing).await],
+                overflowing.clone()
+            ),
+            Err(MockError::AmountOverflow)
+        );
+
+        let whale = contract.deposit(Address::zero(), wallet.address(), U256::MAX);
+        assert_eq!(
+            contract.transfer(
+                vec![
+                    sign(&wallet, first, U256::from(AMOUNT), &overflowing).await,
+                    sign(&wallet, whale, U256::MAX, &overflowing).await
+                ],
+                overflowing
+            ),
+            Err(MockError::AmountOverflow)
+        );
+
+        assert!(!contract.utxo(first).expect("utxo is absent").is_spent);
+    }
+
+    #[tokio::test]
+    async fn scripted_errors() {
+        let contract = MockContract::new();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let utxo_id = contract.deposit(Address::zero(), wallet.address(), U256::from(AMOUNT));
+
+        let err = MockError::Scripted("node is down".to_string());
+        contract.fail_next(Call::GetUtxo, err.clone());
+        contract.fail_next(Call::Transfer, err.clone());
+
+        assert_eq!(contract.get_utxo_by_id(utxo_id).await, Err(err.clone()));
+        assert!(contract
+            .get_utxo_by_id(utxo_id)
+            .await
+            .expect("scripted error is repeated")
+            .is_some());
+
+        let outputs = outputs(&[AMOUNT]);
+        let input = sign(&wallet, utxo_id, U256::from(AMOUNT), &outputs).await;
+        assert_eq!(
+            contract.transfer(vec![input.clone()], outputs.clone()),
+            Err(err)
+        );
+        assert!(contract.transfer(vec![input], outputs).is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+use ethers_core::types::U256;
+
+use crate::types::ShuffleStatus;
+
+/// UTXOs of the same user that are shuffled together, each one in a separate room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub utxos: Vec<U256>,
+}
+
+/// Progress of the UTXO shuffle in the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtxoProgress {
+    /// Room of the UTXO is stored with given status.
+    Active(ShuffleStatus),
+    /// Room of the UTXO is finished or aborted.
+    Removed,
+}
+
+/// Progress of all the UTXOs in the batch, in the batch order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub utxos: Vec<(U256, UtxoProgress)>,
+}
+
+impl BatchProgress {
+    /// Return true if rooms of all the UTXOs are removed.
+    pub fn is_completed(&self) -> bool {
+        self.utxos
+            .iter()
+            .all(|(_, progress)| progress == &UtxoProgress::Removed)
+    }
+
+    /// Return UTXOs that are still shuffled.
+    pub fn active(&self) -> Vec<U256> {
+        self.utxos
+            .iter()
+            .filter(|(_, progress)| progress != &UtxoProgress::Removed)
+            .map(|(utxo_id, _)| *utxo_id)
+            .collect()
+    }
+}
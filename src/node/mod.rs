@@ -1,16 +1,30 @@
+// Spans of the instrumented operations spell out the full error type of the node
+#![cfg_attr(feature = "telemetry", allow(clippy::type_complexity))]
+
+use self::batch::{Batch, BatchProgress, UtxoProgress};
 use self::{room::Room, storage::Outputs};
-use crate::rsa::{Error as RSAError, RsaPrivateKey, RsaPublicKey};
+use crate::auth::{challenge_message, leave_message};
+use crate::clock::{Clock, SystemClock};
+use crate::ordering::{self, Commitments, Reveals};
+use crate::rsa::{Error as RSAError, RsaPublicKey};
+use crate::types::{Fee, Resumption, ShuffleStatus};
 use crate::{node::storage::RoomStorage, rsa};
-use coin_shuffle_contracts_bindings::utxo::Contract;
+use coin_shuffle_contracts_bindings::utxo::{types::Output, Contract};
 use ethers_core::abi::AbiEncode;
-use ethers_core::types::U256;
+use ethers_core::types::{Address, Bytes, Signature, H256, U256};
 use ethers_core::utils::keccak256;
+use ethers_signers::WalletError;
 use signer::Signer;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use transaction::{TransferProvider, TxMismatch};
 
+pub mod batch;
 pub mod room;
 pub mod signer;
 pub mod storage;
+pub mod transaction;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error<E, R, S>
@@ -31,6 +45,10 @@ where
     InsertRoom(R),
     #[error("failed to update room: {0}")]
     UpdateRoom(R),
+    #[error("failed to remove room: {0}")]
+    RemoveRoom(R),
+    #[error("failed to list rooms: {0}")]
+    ListRooms(R),
     #[error("room with specified UTXO doesn't exist utxo_id: {0}")]
     RoomDoesntExist(U256),
     #[error("failed to decode by chunks: {0}")]
@@ -41,8 +59,67 @@ where
     IncorrectOutputsSize,
     #[error("incorrect signing data: self outputs is absent")]
     SelfOutputsIsAbsent,
+    #[error("incorrect signing data: output amount {actual}, while room amount is {expected}")]
+    OutputAmountMismatch { expected: U256, actual: U256 },
+    #[error("incorrect signing data: change of utxo {0} is absent")]
+    ChangeIsAbsent(U256),
+    #[error("incorrect signing data: fee {actual}, while {expected} is approved")]
+    FeeMismatch { expected: U256, actual: U256 },
+    #[error("incorrect signing data: fee amount overflows")]
+    FeeOverflow,
+    #[error("fee {fee} of the room exceeds the approved fee {max_fee}")]
+    FeeIsNotApproved { fee: U256, max_fee: U256 },
+    #[error("utxo {0} doesn't cover the room amount and the fee")]
+    InsufficientAmount(U256),
     #[error("failed to sing the message: {0}")]
     SignMessage(#[from] S),
+    #[error("failed to generate RSA key: {0}")]
+    GenerateKey(RSAError),
+    #[error("RSA key generation task failed: {0}")]
+    GenerateKeyTask(tokio::task::JoinError),
+    #[error("RSA private key of the room is already wiped utxo_id: {0}")]
+    RsaPrivateKeyIsWiped(U256),
+    #[error("failed to sign the message by the session key: {0}")]
+    SignSessionMessage(WalletError),
+    #[error("room isn't joined yet utxo_id: {0}")]
+    RoomIsNotJoined(U256),
+    #[error("outputs of the room aren't signed yet utxo_id: {0}")]
+    RoomIsNotSigned(U256),
+    #[error("failed to get transfer: {0}")]
+    GetTransfer(String),
+    #[error("transaction doesn't match the room: {0}")]
+    TxMismatch(TxMismatch),
+    #[error("batch of utxos is empty")]
+    EmptyBatch,
+    #[error("utxo is repeated in the batch utxo_id: {0}")]
+    DuplicateUtxo(U256),
+    #[error("output is already used by another room: {0}")]
+    OutputIsReused(Bytes),
+    #[error("utxos {0} and {1} can't be shuffled in the same room: {2}")]
+    UtxosShareRoom(U256, U256, uuid::Uuid),
+    #[error("room of utxo {0} doesn't match the service room {1}")]
+    RoomMismatch(U256, uuid::Uuid),
+    #[error("commitment of utxo {0} isn't published by the coordinator")]
+    CommitmentMismatch(U256),
+    #[error("invalid shuffle order: {0}")]
+    InvalidOrder(ordering::Error),
+}
+
+/// Step the shuffle of the restarted node should be resumed with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResumeStep {
+    /// Participant isn't connected, so it should pass the challenge and connect.
+    Connect,
+    /// Participant waits for the other participants.
+    Wait,
+    /// Participant should reveal its entropy by [`Node::reveal_entropy`].
+    Reveal(Commitments),
+    /// Participant should decode the outputs of its round by [`Node::shuffle_round`].
+    ShuffleRound(Outputs),
+    /// Participant should sign the outputs by [`Node::sign_tx`].
+    SignOutputs(Vec<Output>),
+    /// Participant should verify the transaction by [`Node::verify_tx`].
+    VerifyTx(H256),
 }
 
 #[derive(Debug, Clone)]
@@ -55,6 +132,9 @@ pub struct ShuffleRoundResult {
 pub struct Node<S: Signer + Clone + Send + Sync, R: RoomStorage<S>, C: Contract> {
     room_storage: R,
     utxo_conn: C,
+    rsa_key_size: usize,
+    max_fee: U256,
+    clock: Arc<dyn Clock>,
     phantom_data: PhantomData<S>,
 }
 
@@ -68,17 +148,54 @@ where
         Self {
             room_storage,
             utxo_conn,
+            rsa_key_size: rsa::DEFAULT_KEY_SIZE,
+            max_fee: U256::zero(),
+            clock: Arc::new(SystemClock),
             phantom_data: Default::default(),
         }
     }
 
+    /// Set size in bits of the ephemeral RSA keys generated for the rooms.
+    pub fn with_rsa_key_size(mut self, bits: usize) -> Self {
+        self.rsa_key_size = bits;
+        self
+    }
+
+    /// Set the maximum fee per UTXO the node approves, the rooms with a higher fee can't be
+    /// joined. No fee is approved by default.
+    pub fn with_max_fee(mut self, max_fee: U256) -> Self {
+        self.max_fee = max_fee;
+        self
+    }
+
+    /// Set the clock the creation time and the expiry of the rooms are counted by, it is the
+    /// system time by default.
+    pub fn with_clock<T: Clock + 'static>(mut self, clock: T) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Create room for the UTXO with a freshly generated ephemeral RSA key and return the
+    /// public part of the key, that should be passed to the service on connect.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
     pub async fn init_room(
         &mut self,
         utxo_id: U256,
         output: Vec<u8>,
-        rsa_private_key: RsaPrivateKey,
         signer: S,
-    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+    ) -> Result<RsaPublicKey, Error<C::Error, R::Error, S::Error>> {
+        // Reused output links the UTXOs of the user together
+        if self
+            .room_storage
+            .list()
+            .await
+            .map_err(Error::ListRooms)?
+            .iter()
+            .any(|room| room.output == output)
+        {
+            return Err(Error::OutputIsReused(output.into()));
+        }
+
         let utxo = self
             .utxo_conn
             .get_utxo_by_id(utxo_id)
@@ -86,16 +203,408 @@ where
             .map_err(Error::UtxoConnector)?
             .ok_or(Error::UtxoDoesntExist(utxo_id))?;
 
-        let room = Room::new(utxo, rsa_private_key, signer, output);
+        // Key generation takes a while, so it doesn't block the runtime
+        let bits = self.rsa_key_size;
+        let rsa_private_key = tokio::task::spawn_blocking(move || rsa::generate_private_key(bits))
+            .await
+            .map_err(Error::GenerateKeyTask)?
+            .map_err(Error::GenerateKey)?;
+        let rsa_public_key = RsaPublicKey::from(&rsa_private_key);
+
+        let mut room = Room::new(utxo, rsa_private_key, signer, output);
+        room.created_at = self.clock.now();
 
         self.room_storage
             .insert(&room)
             .await
             .map_err(Error::InsertRoom)?;
 
-        Ok(room)
+        Ok(rsa_public_key)
+    }
+
+    /// Create rooms for the batch of user UTXOs, where each UTXO is paired with its own
+    /// output, and return public keys of the created rooms. Every room has its own ephemeral
+    /// key and output, so the UTXOs can't be linked by them. If one of the rooms can't be
+    /// created, none of them are left in the storage.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxos = utxos.len())))]
+    pub async fn init_rooms(
+        &mut self,
+        utxos: Vec<(U256, Vec<u8>)>,
+        signer: S,
+    ) -> Result<(Batch, Vec<RsaPublicKey>), Error<C::Error, R::Error, S::Error>> {
+        if utxos.is_empty() {
+            return Err(Error::EmptyBatch);
+        }
+
+        for (position, (utxo_id, output)) in utxos.iter().enumerate() {
+            let rest = &utxos[position + 1..];
+
+            if rest.iter().any(|(other_id, _)| other_id == utxo_id) {
+                return Err(Error::DuplicateUtxo(*utxo_id));
+            }
+            if rest.iter().any(|(_, other_output)| other_output == output) {
+                return Err(Error::OutputIsReused(output.clone().into()));
+            }
+        }
+
+        let mut public_keys = Vec::with_capacity(utxos.len());
+        for (position, (utxo_id, output)) in utxos.iter().enumerate() {
+            match self
+                .init_room(*utxo_id, output.clone(), signer.clone())
+                .await
+            {
+                Ok(public_key) => public_keys.push(public_key),
+                Err(err) => {
+                    for (created_id, _) in utxos[..position].iter() {
+                        self.remove_room(*created_id).await?;
+                    }
+
+                    return Err(err);
+                }
+            }
+        }
+
+        let batch = Batch {
+            utxos: utxos.into_iter().map(|(utxo_id, _)| utxo_id).collect(),
+        };
+
+        Ok((batch, public_keys))
+    }
+
+    /// Return progress of the every UTXO in the batch.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(utxos = batch.utxos.len()))
+    )]
+    pub async fn batch_progress(
+        &self,
+        batch: &Batch,
+    ) -> Result<BatchProgress, Error<C::Error, R::Error, S::Error>> {
+        let mut utxos = Vec::with_capacity(batch.utxos.len());
+
+        for utxo_id in batch.utxos.iter() {
+            let progress = match self
+                .room_storage
+                .get(utxo_id)
+                .await
+                .map_err(Error::GetRoom)?
+            {
+                Some(room) => UtxoProgress::Active(room.status),
+                None => UtxoProgress::Removed,
+            };
+
+            utxos.push((*utxo_id, progress));
+        }
+
+        Ok(BatchProgress { utxos })
+    }
+
+    /// Save id, amount and fee of the service room the UTXO is placed in. Two UTXOs of the
+    /// node can't be shuffled in the same room, as it would link them together. The fee
+    /// shouldn't exceed the one approved by [`Node::with_max_fee`], and the UTXO should cover
+    /// the room amount and the fee.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(utxo_id = %utxo_id, room_id = %room_id))
+    )]
+    pub async fn join_room(
+        &mut self,
+        utxo_id: U256,
+        room_id: uuid::Uuid,
+        amount: U256,
+        fee: Option<Fee>,
+    ) -> Result<(), Error<C::Error, R::Error, S::Error>> {
+        if let Some(fee) = fee.as_ref().filter(|fee| fee.amount > self.max_fee) {
+            return Err(Error::FeeIsNotApproved {
+                fee: fee.amount,
+                max_fee: self.max_fee,
+            });
+        }
+
+        let rooms = self.room_storage.list().await.map_err(Error::ListRooms)?;
+
+        if let Some(other) = rooms
+            .iter()
+            .find(|room| room.room_id == Some(room_id) && room.utxo.id != utxo_id)
+        {
+            return Err(Error::UtxosShareRoom(utxo_id, other.utxo.id, room_id));
+        }
+
+        let mut room = rooms
+            .into_iter()
+            .find(|room| room.utxo.id == utxo_id)
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        if crate::types::change(room.utxo.amount, amount, fee.as_ref()).is_none() {
+            return Err(Error::InsufficientAmount(utxo_id));
+        }
+
+        room.room_id = Some(room_id);
+        room.amount = amount;
+        room.fee = fee;
+
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)
+    }
+
+    /// Set the address the change of the UTXO is sent to, the change is sent back to the
+    /// UTXO owner by default. The address is passed on connect openly, as the change is
+    /// linked to the UTXO anyway, so it shouldn't be the address of the shuffled outputs.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn set_change_address(
+        &mut self,
+        utxo_id: U256,
+        address: Address,
+    ) -> Result<(), Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        room.change_address = address;
+
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)
+    }
+
+    /// Sign the challenge nonce issued by the service with the UTXO owner key, so the
+    /// service accepts the RSA public key, the session key, the commitment to the entropy
+    /// of the room and the change address, if the UTXO has the change. The room should be
+    /// joined. Return address of the session key, the commitment and the change address
+    /// with the signature.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn sign_challenge(
+        &self,
+        utxo_id: U256,
+        nonce: H256,
+    ) -> Result<(Address, H256, Option<Address>, Signature), Error<C::Error, R::Error, S::Error>>
+    {
+        let room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        let room_id = room.room_id.ok_or(Error::RoomIsNotJoined(utxo_id))?;
+        let rsa_public_key = room
+            .rsa_private_key
+            .as_ref()
+            .map(RsaPublicKey::from)
+            .ok_or(Error::RsaPrivateKeyIsWiped(utxo_id))?;
+
+        let session_key = ethers_signers::Signer::address(&room.session_key);
+        let commitment = ordering::commitment(&room_id, utxo_id, room.entropy);
+        let change = room.change().map(|change| change.owner);
+        let message = challenge_message(
+            &room_id,
+            utxo_id,
+            nonce,
+            &rsa_public_key,
+            session_key,
+            commitment,
+            change,
+        );
+
+        Ok((
+            session_key,
+            commitment,
+            change,
+            room.signer.sign_message(message).await?,
+        ))
+    }
+
+    /// Save the commitments of the room participants published by the coordinator and
+    /// return the entropy of the room, that should be revealed. The commitment of the UTXO
+    /// should be published as it is made by the node.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn reveal_entropy(
+        &mut self,
+        utxo_id: U256,
+        commitments: Commitments,
+    ) -> Result<H256, Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        let room_id = room.room_id.ok_or(Error::RoomIsNotJoined(utxo_id))?;
+        if commitments.get(&utxo_id) != Some(&ordering::commitment(&room_id, utxo_id, room.entropy))
+        {
+            return Err(Error::CommitmentMismatch(utxo_id));
+        }
+
+        room.commitments = commitments;
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)?;
+
+        Ok(room.entropy)
+    }
+
+    /// Check that the shuffle order of the participants is derived from the reveals of the
+    /// saved commitments, so it isn't chosen by the coordinator.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn verify_order(
+        &mut self,
+        utxo_id: U256,
+        participants: &[U256],
+        reveals: &Reveals,
+    ) -> Result<(), Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        let room_id = room.room_id.ok_or(Error::RoomIsNotJoined(utxo_id))?;
+        ordering::verify_order(&room_id, &room.commitments, reveals, participants)
+            .map_err(Error::InvalidOrder)?;
+
+        room.participants_number = participants.len();
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)
+    }
+
+    /// Sign the message to leave the joined room with the UTXO owner key, the room can be
+    /// left before the shuffle is started.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn sign_leave(
+        &self,
+        utxo_id: U256,
+    ) -> Result<Signature, Error<C::Error, R::Error, S::Error>> {
+        let room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        let room_id = room.room_id.ok_or(Error::RoomIsNotJoined(utxo_id))?;
+
+        Ok(room
+            .signer
+            .sign_message(leave_message(&room_id, utxo_id))
+            .await?)
     }
 
+    /// Sign the message of the participant by the session key of the room.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn sign_by_session_key(
+        &self,
+        utxo_id: U256,
+        message: [u8; 32],
+    ) -> Result<Signature, Error<C::Error, R::Error, S::Error>> {
+        let room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        room.session_key
+            .sign_message(message)
+            .await
+            .map_err(Error::SignSessionMessage)
+    }
+
+    /// Reconcile the stored room of the UTXO with the state of the shuffle got from the
+    /// service by `Service::resume` after the restart of the node, and return the step the
+    /// shuffle should be resumed with.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn resume_room(
+        &mut self,
+        utxo_id: U256,
+        resumption: &Resumption,
+    ) -> Result<ResumeStep, Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        if room.room_id.is_some_and(|room_id| {
+            room_id != resumption.room_id
+                || room.amount != resumption.amount
+                || room.fee != resumption.fee
+        }) || !resumption.participants.contains(&utxo_id)
+        {
+            return Err(Error::RoomMismatch(utxo_id, resumption.room_id));
+        }
+
+        // Node may be restarted before the room is joined
+        if room.room_id.is_none() {
+            self.join_room(
+                utxo_id,
+                resumption.room_id,
+                resumption.amount,
+                resumption.fee.clone(),
+            )
+            .await?;
+            room.room_id = Some(resumption.room_id);
+            room.amount = resumption.amount;
+            room.fee = resumption.fee.clone();
+        }
+
+        // Node may be restarted before the keys are received
+        if let Some(public_keys) = &resumption.public_keys {
+            if room.status == ShuffleStatus::SearchParticipants {
+                let reveals = resumption.reveals.clone().unwrap_or_default();
+                ordering::verify_order(
+                    &resumption.room_id,
+                    &room.commitments,
+                    &reveals,
+                    &resumption.participants,
+                )
+                .map_err(Error::InvalidOrder)?;
+
+                room.public_keys = public_keys.clone();
+                room.status = ShuffleStatus::ShuffleStart;
+            }
+        }
+        room.participants_number = resumption.participants.len();
+
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)?;
+
+        let step = if let Some(tx_hash) = resumption.tx_hash {
+            ResumeStep::VerifyTx(tx_hash)
+        } else if let Some(outputs) = &resumption.outputs_to_sign {
+            ResumeStep::SignOutputs(outputs.clone())
+        } else if let Some(encoded_outputs) = &resumption.encoded_outputs {
+            // The key is wiped once the round is accepted, so the round can't be decoded again
+            if room.rsa_private_key.is_none() {
+                return Err(Error::RsaPrivateKeyIsWiped(utxo_id));
+            }
+
+            ResumeStep::ShuffleRound(encoded_outputs.clone())
+        } else if let Some(commitments) = &resumption.commitments {
+            ResumeStep::Reveal(commitments.clone())
+        } else if resumption.status == ShuffleStatus::SearchParticipants {
+            ResumeStep::Connect
+        } else {
+            ResumeStep::Wait
+        };
+
+        Ok(step)
+    }
+
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
     pub async fn update_shuffle_info(
         &mut self,
         public_keys: Vec<RsaPublicKey>,
@@ -108,6 +617,7 @@ where
             .map_err(Error::GetRoom)?
         {
             room_inner.public_keys = public_keys;
+            room_inner.status = ShuffleStatus::ShuffleStart;
 
             self.room_storage
                 .update(&room_inner)
@@ -118,6 +628,10 @@ where
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(utxo_id = %utxo_id, round = encoded_outputs.len()))
+    )]
     pub async fn shuffle_round(
         &mut self,
         encoded_outputs: Outputs,
@@ -136,22 +650,21 @@ where
             .map_err(Error::GetRoom)?
             .ok_or(Error::RoomDoesntExist(utxo_id))?;
 
-        room.participants_number = encoded_outputs.len() + room.public_keys.len() + 1;
-        self.room_storage
-            .update(&room.clone())
-            .await
-            .map_err(Error::UpdateRoom)?;
+        let rsa_private_key = room
+            .rsa_private_key
+            .as_ref()
+            .ok_or(Error::RsaPrivateKeyIsWiped(utxo_id))?;
 
-        for encoded_output in encoded_outputs {
+        for encoded_output in encoded_outputs.iter() {
             result_outputs.push(
-                rsa::decode_by_chunks(encoded_output, room.clone().rsa_private_key)
+                rsa::decode_by_chunks(encoded_output.clone(), rsa_private_key)
                     .map_err(Error::DecodeByChunks)?,
             );
         }
 
         let mut nonce = Vec::<u8>::new();
-        let mut encoded_self_output = room.output;
-        for public_key in room.public_keys {
+        let mut encoded_self_output = room.output.clone();
+        for public_key in room.public_keys.iter().cloned() {
             let encoding_result =
                 rsa::encode_by_chunks(encoded_self_output.clone(), public_key, nonce.clone())
                     .map_err(Error::EncodeByChunks)?;
@@ -162,34 +675,106 @@ where
 
         result_outputs.push(encoded_self_output);
 
+        // The key is kept until the coordinator accepts the round, see `confirm_round`,
+        // so the round can be decoded again if the submission is lost
+        room.status = ShuffleStatus::Shuffle;
+        room.participants_number = encoded_outputs.len() + room.public_keys.len() + 1;
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)?;
+
         Ok(result_outputs)
     }
 
+    /// Wipe the RSA key of the room once the coordinator accepted the outputs decoded by
+    /// [`Node::shuffle_round`], since the key is required only for the decoding of the round.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn confirm_round(
+        &mut self,
+        utxo_id: U256,
+    ) -> Result<(), Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        room.wipe_rsa_private_key();
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)
+    }
+
+    /// Sign the outputs of the room transaction by the UTXO owner key. The outputs should
+    /// start with the shuffled outputs of the room amount, including the output of the room,
+    /// followed by the change outputs, where the change of the UTXO should be present, and
+    /// the fee output of the fee approved on the join.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
     pub async fn sign_tx(
-        &self,
+        &mut self,
         utxo_id: U256,
-        outputs: Outputs,
+        outputs: Vec<Output>,
     ) -> Result<Vec<u8>, Error<C::Error, R::Error, S::Error>> {
-        let room = self
+        let mut room = self
             .room_storage
             .get(&utxo_id)
             .await
             .map_err(Error::GetRoom)?
             .ok_or(Error::RoomDoesntExist(utxo_id))?;
 
-        if room.participants_number != outputs.len() {
+        // Every participant may have the change, and the fee is paid by the single output
+        let participants_number = room.participants_number;
+        if !(participants_number..=participants_number * 2 + 1).contains(&outputs.len()) {
             Err(Error::IncorrectOutputsSize)?
         }
 
-        if !outputs.iter().any(|output| output == &room.output) {
-            Err(Error::IncorrectOutputsSize)?;
+        let (shuffled, rest) = outputs.split_at(participants_number);
+        if let Some(output) = shuffled.iter().find(|output| output.amount != room.amount) {
+            return Err(Error::OutputAmountMismatch {
+                expected: room.amount,
+                actual: output.amount,
+            });
+        }
+        if !shuffled
+            .iter()
+            .any(|output| output.owner.as_bytes() == room.output.as_slice())
+        {
+            Err(Error::SelfOutputsIsAbsent)?;
+        };
+        if room.change().is_some_and(|change| !rest.contains(&change)) {
+            return Err(Error::ChangeIsAbsent(utxo_id));
+        }
+
+        // Amounts are set by the coordinator, so their sums are checked for the overflow
+        let (expected_fee, fee) = match room.fee.as_ref() {
+            Some(fee) => (
+                fee.amount
+                    .checked_mul(U256::from(participants_number))
+                    .ok_or(Error::FeeOverflow)?,
+                rest.iter()
+                    .filter(|output| output.owner == fee.recipient)
+                    .try_fold(U256::zero(), |sum, output| sum.checked_add(output.amount))
+                    .ok_or(Error::FeeOverflow)?,
+            ),
+            None => (U256::zero(), U256::zero()),
         };
+        if fee != expected_fee {
+            return Err(Error::FeeMismatch {
+                expected: expected_fee,
+                actual: fee,
+            });
+        }
 
+        // Layout verified by the UTXO contract: the UTXO id, then the UTXO amount and the
+        // owner of every output
         let mut sign_message = room.utxo.id.encode();
 
-        for mut output in outputs.clone() {
+        for output in outputs.iter() {
             sign_message.append(&mut room.utxo.amount.encode());
-            sign_message.append(&mut output);
+            sign_message.extend_from_slice(output.owner.as_bytes());
         }
 
         let signed_message = room
@@ -198,6 +783,592 @@ where
             .await?
             .to_vec();
 
+        // Outputs to sign mean that all the rounds are accepted by the coordinator
+        room.wipe_rsa_private_key();
+        room.status = ShuffleStatus::SigningOutputs;
+        self.room_storage
+            .update(&room)
+            .await
+            .map_err(Error::UpdateRoom)?;
+
         Ok(signed_message)
     }
+
+    /// Remove the room once the transaction with given hash, that was built from the signed
+    /// outputs, is confirmed. Return the removed room.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(utxo_id = %utxo_id, tx_hash = ?tx_hash))
+    )]
+    pub async fn confirm_tx_hash(
+        &mut self,
+        utxo_id: U256,
+        tx_hash: H256,
+    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+        let mut room = self.finish_room(utxo_id).await?;
+        room.status = ShuffleStatus::TxHashDistribution;
+
+        log::debug!("room of utxo {utxo_id} is finished by tx {tx_hash:?}");
+
+        Ok(room)
+    }
+
+    /// Verify that the transaction with given hash, that was distributed by the service,
+    /// spent the room UTXO and sent its amount to the room output. The room is removed
+    /// if the transaction is valid, and returned.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(utxo_id = %utxo_id, tx_hash = ?tx_hash))
+    )]
+    pub async fn verify_tx<T: TransferProvider>(
+        &mut self,
+        transfer_provider: &T,
+        utxo_id: U256,
+        tx_hash: H256,
+    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+        let room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        if room.status != ShuffleStatus::SigningOutputs {
+            return Err(Error::RoomIsNotSigned(utxo_id));
+        }
+
+        let transfer = transfer_provider
+            .get_transfer(tx_hash)
+            .await
+            .map_err(|err| Error::GetTransfer(err.to_string()))?
+            .ok_or(Error::TxMismatch(TxMismatch::TransferNotFound(tx_hash)))?;
+
+        if !transfer.inputs.contains(&utxo_id) {
+            return Err(Error::TxMismatch(TxMismatch::InputIsAbsent(utxo_id)));
+        }
+
+        let utxo = self
+            .utxo_conn
+            .get_utxo_by_id(utxo_id)
+            .await
+            .map_err(Error::UtxoConnector)?
+            .ok_or(Error::UtxoDoesntExist(utxo_id))?;
+
+        if !utxo.is_spent {
+            return Err(Error::TxMismatch(TxMismatch::UtxoIsNotSpent(utxo_id)));
+        }
+
+        let output = transfer
+            .outputs
+            .iter()
+            .find(|output| output.owner.as_bytes() == room.output.as_slice())
+            .ok_or_else(|| {
+                Error::TxMismatch(TxMismatch::OutputIsAbsent(room.output.clone().into()))
+            })?;
+
+        if output.amount != room.amount {
+            return Err(Error::TxMismatch(TxMismatch::OutputAmount {
+                expected: room.amount,
+                actual: output.amount,
+            }));
+        }
+
+        if let Some(change) = room.change() {
+            if !transfer.outputs.contains(&change) {
+                return Err(Error::TxMismatch(TxMismatch::ChangeIsAbsent(change.owner)));
+            }
+        }
+
+        self.confirm_tx_hash(utxo_id, tx_hash).await
+    }
+
+    /// Remove the room of the successfully finished shuffle, its outputs must be already
+    /// signed. Return the removed room.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn finish_room(
+        &mut self,
+        utxo_id: U256,
+    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+        let room = self
+            .room_storage
+            .get(&utxo_id)
+            .await
+            .map_err(Error::GetRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        if !matches!(
+            room.status,
+            ShuffleStatus::SigningOutputs | ShuffleStatus::TxHashDistribution
+        ) {
+            return Err(Error::RoomIsNotSigned(utxo_id));
+        }
+
+        self.remove_room(utxo_id).await
+    }
+
+    /// Remove the room of the failed or abandoned shuffle whatever its status is. Return the
+    /// removed room.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(utxo_id = %utxo_id)))]
+    pub async fn abort_room(
+        &mut self,
+        utxo_id: U256,
+    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+        self.remove_room(utxo_id).await
+    }
+
+    /// Remove the rooms that were created earlier than `max_age` ago and return their UTXO ids.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(max_age = ?max_age)))]
+    pub async fn expire_rooms(
+        &mut self,
+        max_age: Duration,
+    ) -> Result<Vec<U256>, Error<C::Error, R::Error, S::Error>> {
+        let now = self.clock.now();
+
+        let expired = self
+            .room_storage
+            .list()
+            .await
+            .map_err(Error::ListRooms)?
+            .into_iter()
+            .filter(|room| now.duration_since(room.created_at).unwrap_or_default() > max_age)
+            .map(|room| room.utxo.id)
+            .collect::<Vec<U256>>();
+
+        for utxo_id in expired.iter() {
+            self.remove_room(*utxo_id).await?;
+        }
+
+        Ok(expired)
+    }
+
+    /// Expire the rooms older than `max_age` every `period`. The future runs until the
+    /// storage fails, so it is expected to be spawned on a clone of the node:
+    ///
+    /// ```ignore
+    /// let mut sweeper = node.clone();
+    /// tokio::spawn(async move { sweeper.run_sweeper(period, max_age).await });
+    /// ```
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(period = ?period, max_age = ?max_age))
+    )]
+    pub async fn run_sweeper(
+        &mut self,
+        period: Duration,
+        max_age: Duration,
+    ) -> Result<(), Error<C::Error, R::Error, S::Error>> {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let expired = self.expire_rooms(max_age).await?;
+            if !expired.is_empty() {
+                log::debug!("expired rooms of utxos: {expired:?}");
+            }
+        }
+    }
+
+    /// Remove the room from the storage and wipe its RSA private key.
+    async fn remove_room(
+        &mut self,
+        utxo_id: U256,
+    ) -> Result<Room<S>, Error<C::Error, R::Error, S::Error>> {
+        let mut room = self
+            .room_storage
+            .remove(&utxo_id)
+            .await
+            .map_err(Error::RemoveRoom)?
+            .ok_or(Error::RoomDoesntExist(utxo_id))?;
+
+        room.wipe_rsa_private_key();
+
+        Ok(room)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use coin_shuffle_contracts_bindings::utxo::types::{Input, Output};
+    use ethers_core::abi::AbiEncode;
+    use ethers_core::types::{Address, H256, U256};
+    use ethers_core::utils::keccak256;
+    use ethers_signers::{LocalWallet, Signer};
+
+    use super::{Error, Node, ResumeStep, TxMismatch};
+    use crate::clock::ManualClock;
+    use crate::mock::MockContract;
+    use crate::node::storage::{RoomMemoryStorage, RoomStorage};
+    use crate::rsa::{self, RsaPublicKey, MIN_KEY_SIZE};
+    use crate::types::{Fee, Resumption, ShuffleStatus};
+
+    type TestNode = Node<LocalWallet, RoomMemoryStorage<LocalWallet>, MockContract>;
+
+    fn node(contract: &MockContract) -> TestNode {
+        node_with_storage(contract, RoomMemoryStorage::new())
+    }
+
+    fn node_with_storage(
+        contract: &MockContract,
+        storage: RoomMemoryStorage<LocalWallet>,
+    ) -> TestNode {
+        Node::new(storage, contract.clone()).with_rsa_key_size(MIN_KEY_SIZE)
+    }
+
+    fn resumption(room_id: uuid::Uuid, participants: Vec<U256>) -> Resumption {
+        Resumption {
+            room_id,
+            token: Address::zero(),
+            amount: U256::from(100),
+            fee: None,
+            participants,
+            status: ShuffleStatus::SearchParticipants,
+            commitments: None,
+            reveals: None,
+            public_keys: None,
+            encoded_outputs: None,
+            outputs_to_sign: None,
+            tx_hash: None,
+        }
+    }
+
+    /// Deposit the UTXO of the new owner and init its room in the node.
+    async fn init_room(node: &mut TestNode, contract: &MockContract) -> (U256, RsaPublicKey) {
+        let owner = LocalWallet::new(&mut rand::thread_rng());
+        let utxo_id = contract.deposit(Address::zero(), owner.address(), U256::from(100));
+        let public_key = node
+            .init_room(utxo_id, Address::random().as_bytes().to_vec(), owner)
+            .await
+            .expect("failed to init room");
+
+        (utxo_id, public_key)
+    }
+
+    #[tokio::test]
+    async fn round_is_decoded_again_until_confirmed() {
+        let contract = MockContract::new();
+        let mut node = node(&contract);
+        let (utxo_id, public_key) = init_room(&mut node, &contract).await;
+
+        let output = Address::random().as_bytes().to_vec();
+        let encoded_output = rsa::encode_by_chunks(output.clone(), public_key, Vec::new())
+            .expect("failed to encode")
+            .encoded_msg;
+
+        // Submission of the round may be lost, so the round is decoded again
+        for _ in 0..2 {
+            let decoded_outputs = node
+                .shuffle_round(vec![encoded_output.clone()], utxo_id)
+                .await
+                .expect("failed to decode round");
+            assert_eq!(decoded_outputs[0], output);
+        }
+
+        node.confirm_round(utxo_id)
+            .await
+            .expect("failed to confirm round");
+        assert!(matches!(
+            node.shuffle_round(vec![encoded_output], utxo_id).await,
+            Err(Error::RsaPrivateKeyIsWiped(id)) if id == utxo_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn room_is_resumed_after_restart() {
+        let contract = MockContract::new();
+        let storage = RoomMemoryStorage::new();
+        let (utxo_id, public_key) = init_room(
+            &mut node_with_storage(&contract, storage.clone()),
+            &contract,
+        )
+        .await;
+
+        // Node is restarted before the room is joined
+        let mut node = node_with_storage(&contract, storage.clone());
+        let room_id = uuid::Uuid::new_v4();
+        let mut resumption = resumption(room_id, vec![utxo_id, U256::from(1000)]);
+        let step = node
+            .resume_room(utxo_id, &resumption)
+            .await
+            .expect("failed to resume room");
+        assert_eq!(step, ResumeStep::Connect);
+
+        let room = storage
+            .get(&utxo_id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        assert_eq!(room.room_id, Some(room_id));
+
+        // Node is restarted in its round, so the round is decoded by the persisted key
+        let mut node = node_with_storage(&contract, storage.clone());
+        let output = Address::random().as_bytes().to_vec();
+        let encoded_outputs = vec![
+            rsa::encode_by_chunks(output.clone(), public_key, Vec::new())
+                .expect("failed to encode")
+                .encoded_msg,
+        ];
+        resumption.status = ShuffleStatus::ShuffleStart;
+        resumption.encoded_outputs = Some(encoded_outputs.clone());
+        let step = node
+            .resume_room(utxo_id, &resumption)
+            .await
+            .expect("failed to resume room");
+        assert_eq!(step, ResumeStep::ShuffleRound(encoded_outputs.clone()));
+
+        let decoded_outputs = node
+            .shuffle_round(encoded_outputs, utxo_id)
+            .await
+            .expect("failed to decode round");
+        assert_eq!(decoded_outputs[0], output);
+
+        resumption.room_id = uuid::Uuid::new_v4();
+        assert!(matches!(
+            node.resume_room(utxo_id, &resumption).await,
+            Err(Error::RoomMismatch(id, _)) if id == utxo_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn tx_is_verified_against_transfer() {
+        let contract = MockContract::new();
+        let mut storage = RoomMemoryStorage::new();
+        let mut node = node_with_storage(&contract, storage.clone());
+
+        let mut rooms = Vec::new();
+        for _ in 0..2 {
+            let (utxo_id, _) = init_room(&mut node, &contract).await;
+            let mut room = storage
+                .get(&utxo_id)
+                .await
+                .expect("failed to get room")
+                .expect("room is absent");
+            room.participants_number = 2;
+            storage.update(&room).await.expect("failed to update room");
+            rooms.push(room);
+        }
+
+        let outputs: Vec<Output> = rooms
+            .iter()
+            .map(|room| Output {
+                amount: room.amount,
+                owner: Address::from_slice(&room.output),
+            })
+            .collect();
+        let mut inputs = Vec::new();
+        for room in rooms.iter() {
+            let signature = node
+                .sign_tx(room.utxo.id, outputs.clone())
+                .await
+                .expect("failed to sign tx");
+            inputs.push(Input {
+                id: room.utxo.id,
+                signature: signature.into(),
+            });
+        }
+
+        let (first, second) = (rooms[0].utxo.id, rooms[1].utxo.id);
+
+        // Room can't be finished by the transaction, that isn't made yet
+        let tx_hash = H256::random();
+        assert!(matches!(
+            node.verify_tx(&contract, first, tx_hash).await,
+            Err(Error::TxMismatch(TxMismatch::TransferNotFound(hash))) if hash == tx_hash
+        ));
+
+        // Transfer of another UTXO to the same outputs
+        let owner = LocalWallet::new(&mut rand::thread_rng());
+        let other_id = contract.deposit(Address::zero(), owner.address(), U256::from(200));
+        let mut message = other_id.encode();
+        for output in outputs.iter() {
+            message.append(&mut U256::from(200).encode());
+            message.extend_from_slice(output.owner.as_bytes());
+        }
+        let signature = owner
+            .sign_message(keccak256(message))
+            .await
+            .expect("failed to sign");
+        let other_hash = contract
+            .transfer(
+                vec![Input {
+                    id: other_id,
+                    signature: signature.to_vec().into(),
+                }],
+                outputs.clone(),
+            )
+            .expect("failed to transfer");
+        assert!(matches!(
+            node.verify_tx(&contract, first, other_hash).await,
+            Err(Error::TxMismatch(TxMismatch::InputIsAbsent(id))) if id == first
+        ));
+
+        let tx_hash = contract
+            .transfer(inputs, outputs)
+            .expect("failed to transfer");
+        for utxo_id in [first, second] {
+            let room = node
+                .verify_tx(&contract, utxo_id, tx_hash)
+                .await
+                .expect("failed to verify tx");
+            assert_eq!(room.status, ShuffleStatus::TxHashDistribution);
+            assert!(storage
+                .get(&utxo_id)
+                .await
+                .expect("failed to get room")
+                .is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn fee_outputs_are_checked() {
+        let contract = MockContract::new();
+        let mut storage = RoomMemoryStorage::new();
+        let mut node = node_with_storage(&contract, storage.clone());
+        let (utxo_id, _) = init_room(&mut node, &contract).await;
+
+        let recipient = Address::random();
+        let mut room = storage
+            .get(&utxo_id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        room.participants_number = 2;
+        room.amount = U256::from(90);
+        room.fee = Some(Fee {
+            recipient,
+            amount: U256::MAX,
+        });
+        storage.update(&room).await.expect("failed to update room");
+
+        let output = |amount: U256, owner: Address| Output { amount, owner };
+        let outputs = |fees: &[U256]| {
+            let mut outputs = vec![
+                output(room.amount, Address::from_slice(&room.output)),
+                output(room.amount, Address::random()),
+            ];
+            outputs.extend(fees.iter().map(|fee| output(*fee, recipient)));
+            outputs
+        };
+
+        // Fee of the room is multiplied by the number of participants
+        assert!(matches!(
+            node.sign_tx(utxo_id, outputs(&[U256::from(20)])).await,
+            Err(Error::FeeOverflow)
+        ));
+
+        room.fee = Some(Fee {
+            recipient,
+            amount: U256::from(10),
+        });
+        storage.update(&room).await.expect("failed to update room");
+
+        assert!(matches!(
+            node.sign_tx(utxo_id, outputs(&[U256::MAX, U256::one()]))
+                .await,
+            Err(Error::FeeOverflow)
+        ));
+        assert!(matches!(
+            node.sign_tx(utxo_id, outputs(&[U256::from(19)])).await,
+            Err(Error::FeeMismatch { expected, actual })
+                if expected == U256::from(20) && actual == U256::from(19)
+        ));
+
+        node.sign_tx(utxo_id, outputs(&[U256::from(15), U256::from(5)]))
+            .await
+            .expect("failed to sign tx");
+    }
+
+    #[tokio::test]
+    async fn failed_batch_is_rolled_back() {
+        let contract = MockContract::new();
+        let storage = RoomMemoryStorage::new();
+        let mut node = node_with_storage(&contract, storage.clone());
+
+        let owner = LocalWallet::new(&mut rand::thread_rng());
+        let mut utxos: Vec<(U256, Vec<u8>)> = (0..3)
+            .map(|_| {
+                (
+                    contract.deposit(Address::zero(), owner.address(), U256::from(100)),
+                    Address::random().as_bytes().to_vec(),
+                )
+            })
+            .collect();
+        let absent_id = U256::from(1000);
+        utxos[1].0 = absent_id;
+
+        assert!(matches!(
+            node.init_rooms(utxos.clone(), owner.clone()).await,
+            Err(Error::UtxoDoesntExist(id)) if id == absent_id
+        ));
+        assert!(storage
+            .list()
+            .await
+            .expect("failed to list rooms")
+            .is_empty());
+
+        utxos.remove(1);
+        let (batch, public_keys) = node
+            .init_rooms(utxos, owner)
+            .await
+            .expect("failed to init rooms");
+        assert_eq!(public_keys.len(), 2);
+        assert_eq!(
+            storage.list().await.expect("failed to list rooms").len(),
+            batch.utxos.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn aborted_room_is_removed() {
+        let contract = MockContract::new();
+        let storage = RoomMemoryStorage::new();
+        let mut node = node_with_storage(&contract, storage.clone());
+        let (utxo_id, _) = init_room(&mut node, &contract).await;
+
+        // Room can't be finished before the outputs are signed
+        assert!(matches!(
+            node.finish_room(utxo_id).await,
+            Err(Error::RoomIsNotSigned(id)) if id == utxo_id
+        ));
+
+        let room = node
+            .abort_room(utxo_id)
+            .await
+            .expect("failed to abort room");
+        assert!(room.rsa_private_key.is_none());
+        assert!(storage
+            .get(&utxo_id)
+            .await
+            .expect("failed to get room")
+            .is_none());
+
+        assert!(matches!(
+            node.abort_room(utxo_id).await,
+            Err(Error::RoomDoesntExist(id)) if id == utxo_id
+        ));
+    }
+    #[tokio::test]
+    async fn expired_rooms_are_removed() {
+        let contract = MockContract::new();
+        let storage = RoomMemoryStorage::new();
+        let clock = ManualClock::default();
+        let mut node = node_with_storage(&contract, storage.clone()).with_clock(clock.clone());
+        let (old_utxo_id, _) = init_room(&mut node, &contract).await;
+
+        clock.advance(Duration::from_secs(60));
+        let (new_utxo_id, _) = init_room(&mut node, &contract).await;
+
+        clock.advance(Duration::from_secs(30));
+        let expired = node
+            .expire_rooms(Duration::from_secs(45))
+            .await
+            .expect("failed to expire rooms");
+        assert_eq!(expired, vec![old_utxo_id]);
+
+        let rooms = storage.list().await.expect("failed to list rooms");
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].utxo.id, new_utxo_id);
+    }
 }
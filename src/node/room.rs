@@ -1,31 +1,79 @@
+use ethers_core::types::{Address, H256, U256};
+use ethers_signers::LocalWallet;
 use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::time::SystemTime;
 
 use super::Signer;
-use crate::types::ShuffleStatus;
-use coin_shuffle_contracts_bindings::utxo::types::Utxo;
+use crate::ordering::Commitments;
+use crate::types::{Fee, ShuffleStatus};
+use coin_shuffle_contracts_bindings::utxo::types::{Output, Utxo};
 
 // todo #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Room<S: Signer + Clone + Send + Sync + Send> {
+    /// Id of the service room the UTXO is shuffled in, it is unknown until the join.
+    pub room_id: Option<uuid::Uuid>,
     pub utxo: Utxo,
+    /// Amount of the service room, it is the UTXO amount until the room is joined.
+    pub amount: U256,
+    /// Fee of the service room approved on the join.
+    pub fee: Option<Fee>,
     pub output: Vec<u8>,
+    /// Address the change of the UTXO above the room amount and the fee is sent to, it is
+    /// the UTXO owner by default.
+    pub change_address: Address,
     pub public_keys: Vec<RsaPublicKey>,
     pub status: ShuffleStatus,
-    pub rsa_private_key: RsaPrivateKey,
+    /// Ephemeral RSA key of the room, it is wiped when the key isn't needed anymore.
+    pub rsa_private_key: Option<RsaPrivateKey>,
     pub signer: S,
+    /// Ephemeral key of the room, that signs the messages of the participant after the
+    /// connect, see [`auth`](crate::auth).
+    pub session_key: LocalWallet,
+    /// Random entropy the shuffle order is derived from, see [`ordering`](crate::ordering).
+    pub entropy: H256,
+    /// Commitments of the room participants to their entropy, they are known once all the
+    /// participants are connected.
+    pub commitments: Commitments,
     pub participants_number: usize,
+    pub created_at: SystemTime,
 }
 
 impl<S: Signer + Clone + Send + Sync> Room<S> {
     pub fn new(utxo: Utxo, rsa_private_key: RsaPrivateKey, signer: S, output: Vec<u8>) -> Self {
         Self {
+            room_id: None,
+            amount: utxo.amount,
+            fee: None,
+            change_address: utxo.owner,
             utxo,
             output,
             status: ShuffleStatus::SearchParticipants,
-            rsa_private_key,
+            rsa_private_key: Some(rsa_private_key),
             signer,
+            session_key: LocalWallet::new(&mut rand::thread_rng()),
+            entropy: H256::random(),
+            commitments: Commitments::new(),
             public_keys: Vec::new(),
             participants_number: usize::default(),
+            created_at: SystemTime::now(),
         }
     }
+
+    /// Return the output of the UTXO change, if the UTXO amount exceeds the room amount and
+    /// the fee.
+    pub fn change(&self) -> Option<Output> {
+        crate::types::change(self.utxo.amount, self.amount, self.fee.as_ref())
+            .filter(|change| !change.is_zero())
+            .map(|amount| Output {
+                amount,
+                owner: self.change_address,
+            })
+    }
+
+    /// Wipe the RSA private key of the room. The key memory is zeroized on drop, so the
+    /// clones of the room that are kept by the storage must be updated as well.
+    pub fn wipe_rsa_private_key(&mut self) {
+        drop(self.rsa_private_key.take());
+    }
 }
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use ethers_core::types::Signature;
+use ethers_signers::{LocalWallet, WalletError};
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
@@ -11,3 +12,16 @@ pub trait Signer {
         message: S,
     ) -> Result<Signature, Self::Error>;
 }
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Signer for LocalWallet {
+    type Error = WalletError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        ethers_signers::Signer::sign_message(self, message).await
+    }
+}
@@ -0,0 +1,375 @@
+//! Conformance suite for the [`RoomStorage`] implementations.
+//!
+//! Every check panics with a describing message if the storage behaves differently from
+//! the [`RoomMemoryStorage`](super::RoomMemoryStorage). The checks build rooms with the
+//! passed `make_room` function, which must return a room for the given UTXO id, and use
+//! random UTXO ids, so they can be run one after another against the same storage:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn my_storage_conforms() {
+//!     let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+//!     let signer = LocalWallet::new(&mut rand::thread_rng());
+//!
+//!     coin_shuffle_core::node::storage::conformance::run(MyStorage::new(), |utxo_id| {
+//!         Room::new(Utxo { id: utxo_id, ..Default::default() }, key.clone(), signer.clone(), vec![])
+//!     })
+//!     .await;
+//! }
+//! ```
+
+use ethers_core::types::U256;
+
+use super::RoomStorage;
+use crate::node::{room::Room, signer::Signer};
+use crate::types::ShuffleStatus;
+
+/// Number of tasks writing to the storage at the same time in the concurrent checks.
+const CONCURRENT_WRITERS: usize = 16;
+
+/// Run all the checks of the suite.
+pub async fn run<S, R, F>(mut storage: R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync + 'static,
+    R: RoomStorage<S> + Clone + Send + Sync + 'static,
+    F: Fn(U256) -> Room<S>,
+{
+    insert_get_remove(&mut storage, &make_room).await;
+    update(&mut storage, &make_room).await;
+    duplicate_insert(&mut storage, &make_room).await;
+    concurrent_writers(&storage, &make_room).await;
+    concurrent_duplicate_inserts(&storage, &make_room).await;
+    cleanup(&mut storage, &make_room).await;
+}
+
+pub async fn insert_get_remove<S, R, F>(storage: &mut R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+    F: Fn(U256) -> Room<S>,
+{
+    let room = make_room(random_utxo_id());
+
+    assert!(
+        storage
+            .get(&room.utxo.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is present before the insert"
+    );
+
+    storage.insert(&room).await.expect("insert room");
+
+    let stored = storage
+        .get(&room.utxo.id)
+        .await
+        .expect("get room")
+        .expect("inserted room is absent");
+    assert_room_eq(&stored, &room);
+    assert!(
+        listed_ids(storage).await.contains(&room.utxo.id),
+        "inserted room isn't listed"
+    );
+
+    let removed = storage
+        .remove(&room.utxo.id)
+        .await
+        .expect("remove room")
+        .expect("remove must return the removed room");
+    assert_room_eq(&removed, &room);
+
+    assert!(
+        storage
+            .get(&room.utxo.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is present after the remove"
+    );
+    assert!(
+        !listed_ids(storage).await.contains(&room.utxo.id),
+        "removed room is listed"
+    );
+}
+
+pub async fn update<S, R, F>(storage: &mut R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+    F: Fn(U256) -> Room<S>,
+{
+    let room = make_room(random_utxo_id());
+
+    assert!(
+        storage.update(&room).await.is_err(),
+        "update of the absent room must fail"
+    );
+    assert!(
+        storage
+            .get(&room.utxo.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is created by the failed update"
+    );
+
+    storage.insert(&room).await.expect("insert room");
+
+    let mut updated = room.clone();
+    updated.status = ShuffleStatus::Shuffle;
+    updated.participants_number = 5;
+    storage.update(&updated).await.expect("update room");
+
+    let stored = storage
+        .get(&room.utxo.id)
+        .await
+        .expect("get room")
+        .expect("updated room is absent");
+    assert_room_eq(&stored, &updated);
+
+    storage.remove(&room.utxo.id).await.expect("remove room");
+}
+
+pub async fn duplicate_insert<S, R, F>(storage: &mut R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+    F: Fn(U256) -> Room<S>,
+{
+    let room = make_room(random_utxo_id());
+    storage.insert(&room).await.expect("insert room");
+
+    let mut duplicate = room.clone();
+    duplicate.participants_number = 7;
+    assert!(
+        storage.insert(&duplicate).await.is_err(),
+        "insert of the room with the same UTXO must fail"
+    );
+
+    let stored = storage
+        .get(&room.utxo.id)
+        .await
+        .expect("get room")
+        .expect("room is absent after the duplicate insert");
+    assert_room_eq(&stored, &room);
+
+    storage.remove(&room.utxo.id).await.expect("remove room");
+}
+
+/// Insert and update rooms from several tasks at once, none of the writes must be lost.
+pub async fn concurrent_writers<S, R, F>(storage: &R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync + 'static,
+    R: RoomStorage<S> + Clone + Send + Sync + 'static,
+    F: Fn(U256) -> Room<S>,
+{
+    let rooms = (0..CONCURRENT_WRITERS)
+        .map(|_| make_room(random_utxo_id()))
+        .collect::<Vec<_>>();
+
+    let handles = rooms
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, mut room)| {
+            let mut storage = storage.clone();
+            tokio::spawn(async move {
+                storage.insert(&room).await.expect("insert room");
+                tokio::task::yield_now().await;
+
+                room.participants_number = i;
+                storage.update(&room).await.expect("update room");
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.await.expect("writer task panicked");
+    }
+
+    let mut storage = storage.clone();
+    let listed = listed_ids(&storage).await;
+    assert!(
+        rooms.iter().all(|room| listed.contains(&room.utxo.id)),
+        "concurrently inserted room isn't listed"
+    );
+
+    for (i, room) in rooms.iter().enumerate() {
+        let stored = storage
+            .get(&room.utxo.id)
+            .await
+            .expect("get room")
+            .expect("concurrently inserted room is lost");
+        assert_eq!(
+            stored.participants_number, i,
+            "concurrent room update is lost"
+        );
+
+        storage.remove(&room.utxo.id).await.expect("remove room");
+    }
+}
+
+/// Insert the same room from several tasks at once, only one of the inserts must succeed.
+pub async fn concurrent_duplicate_inserts<S, R, F>(storage: &R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync + 'static,
+    R: RoomStorage<S> + Clone + Send + Sync + 'static,
+    F: Fn(U256) -> Room<S>,
+{
+    let room = make_room(random_utxo_id());
+
+    let handles = (0..CONCURRENT_WRITERS)
+        .map(|_| {
+            let mut storage = storage.clone();
+            let room = room.clone();
+            tokio::spawn(async move { storage.insert(&room).await.is_ok() })
+        })
+        .collect::<Vec<_>>();
+
+    let mut succeeded = 0;
+    for handle in handles {
+        if handle.await.expect("writer task panicked") {
+            succeeded += 1;
+        }
+    }
+    assert_eq!(
+        succeeded, 1,
+        "exactly one of the concurrent inserts of the same room must succeed"
+    );
+
+    let mut storage = storage.clone();
+    storage.remove(&room.utxo.id).await.expect("remove room");
+}
+
+/// Removed rooms must not be reachable anymore.
+pub async fn cleanup<S, R, F>(storage: &mut R, make_room: F)
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+    F: Fn(U256) -> Room<S>,
+{
+    let rooms = (0..3)
+        .map(|_| make_room(random_utxo_id()))
+        .collect::<Vec<_>>();
+
+    for room in rooms.iter() {
+        storage.insert(room).await.expect("insert room");
+    }
+
+    for room in rooms.iter() {
+        assert!(
+            storage
+                .remove(&room.utxo.id)
+                .await
+                .expect("remove room")
+                .is_some(),
+            "remove of the stored room returned nothing"
+        );
+        assert!(
+            storage
+                .remove(&room.utxo.id)
+                .await
+                .expect("removing of the absent room must not fail")
+                .is_none(),
+            "second remove of the same room returned a room"
+        );
+        assert!(
+            storage.update(room).await.is_err(),
+            "update of the removed room must fail"
+        );
+    }
+
+    for room in rooms.iter() {
+        assert!(
+            storage
+                .get(&room.utxo.id)
+                .await
+                .expect("get room")
+                .is_none(),
+            "room is present after the cleanup"
+        );
+    }
+
+    let listed = listed_ids(storage).await;
+    assert!(
+        rooms.iter().all(|room| !listed.contains(&room.utxo.id)),
+        "room is listed after the cleanup"
+    );
+
+    // Removed UTXO can be used for the new room.
+    storage.insert(&rooms[0]).await.expect("insert room");
+    storage
+        .remove(&rooms[0].utxo.id)
+        .await
+        .expect("remove room");
+}
+
+async fn listed_ids<S, R>(storage: &R) -> Vec<U256>
+where
+    S: Signer + Clone + Send + Sync,
+    R: RoomStorage<S>,
+{
+    storage
+        .list()
+        .await
+        .expect("list rooms")
+        .iter()
+        .map(|room| room.utxo.id)
+        .collect()
+}
+
+fn random_utxo_id() -> U256 {
+    U256::from_big_endian(&rand::random::<[u8; 32]>())
+}
+
+fn assert_room_eq<S: Signer + Clone + Send + Sync>(stored: &Room<S>, expected: &Room<S>) {
+    assert_eq!(stored.utxo.id, expected.utxo.id, "room UTXO mismatch");
+    assert_eq!(stored.output, expected.output, "room output mismatch");
+    assert_eq!(
+        stored.public_keys, expected.public_keys,
+        "room public keys mismatch"
+    );
+    assert_eq!(stored.status, expected.status, "room status mismatch");
+    assert_eq!(
+        stored.participants_number, expected.participants_number,
+        "room participants number mismatch"
+    );
+    assert!(
+        stored.rsa_private_key == expected.rsa_private_key,
+        "room RSA private key mismatch"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use coin_shuffle_contracts_bindings::utxo::types::Utxo;
+    use ethers_core::types::Address;
+    use ethers_signers::LocalWallet;
+    use rsa::RsaPrivateKey;
+
+    use crate::node::room::Room;
+    use crate::node::storage::RoomMemoryStorage;
+
+    #[tokio::test]
+    async fn memory_storage_conforms() {
+        let key =
+            RsaPrivateKey::new(&mut rand::thread_rng(), 2048).expect("failed to generate a key");
+        let signer = LocalWallet::new(&mut rand::thread_rng());
+
+        super::run(RoomMemoryStorage::new(), |utxo_id| {
+            let utxo = Utxo {
+                id: utxo_id,
+                ..Default::default()
+            };
+
+            Room::new(
+                utxo,
+                key.clone(),
+                signer.clone(),
+                Address::random().as_bytes().to_vec(),
+            )
+        })
+        .await;
+    }
+}
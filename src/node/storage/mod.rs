@@ -5,6 +5,9 @@ use tokio::sync::Mutex;
 
 use super::room::Room;
 
+#[cfg(any(test, feature = "test-utils"))]
+pub mod conformance;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("utxo with this key already presented: {0}")]
@@ -25,6 +28,7 @@ pub trait RoomStorage<S: Signer + Clone + Send + Sync> {
     async fn update(&mut self, room: &Room<S>) -> Result<(), Self::Error>;
     async fn get(&self, utxo_id: &U256) -> Result<Option<Room<S>>, Self::Error>;
     async fn remove(&mut self, utxo_id: &U256) -> Result<Option<Room<S>>, Self::Error>;
+    async fn list(&self) -> Result<Vec<Room<S>>, Self::Error>;
 }
 
 /// Default realization of the Node's RoomStorage
@@ -80,4 +84,10 @@ impl<S: Signer + Clone + Send + Sync> RoomStorage<S> for RoomMemoryStorage<S> {
 
         Ok(storage.remove(utxo_id))
     }
+
+    async fn list(&self) -> Result<Vec<Room<S>>, Self::Error> {
+        let storage = self.room_list.lock().await;
+
+        Ok(storage.values().cloned().collect())
+    }
 }
@@ -0,0 +1,215 @@
+use async_trait::async_trait;
+use coin_shuffle_contracts_bindings::utxo::types::Output;
+use ethers_core::types::{Address, Bytes, H256, U256};
+
+/// UTXO transfer that was made on chain by a transaction.
+#[derive(Debug, Clone, Default)]
+pub struct Transfer {
+    /// Ids of the UTXOs spent by the transfer.
+    pub inputs: Vec<U256>,
+    /// Outputs created by the transfer.
+    pub outputs: Vec<Output>,
+}
+
+/// Source of the transfers made by the UTXO contract. `ChainTransferProvider` of the `chain`
+/// feature reads them from the chain, while the mock contract of the tests records them.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait TransferProvider {
+    type Error: std::error::Error;
+
+    /// Return the transfer made by the transaction with given hash. `None` is returned if
+    /// the transaction isn't mined, reverted or isn't a transfer of the UTXO contract.
+    async fn get_transfer(&self, tx_hash: H256) -> Result<Option<Transfer>, Self::Error>;
+}
+
+/// Signature of the `transfer` function of the UTXO contract, which inputs are `(id,
+/// signature)` and outputs are `(amount, owner)` tuples as in the contract bindings.
+#[cfg(feature = "chain")]
+const TRANSFER_SIGNATURE: &str = "transfer((uint256,bytes)[],(uint256,address)[])";
+
+/// [`TransferProvider`] that reads the transfers from the chain by the JSON-RPC
+/// [`Middleware`](ethers_providers::Middleware). The transfer is decoded from the call data
+/// of the transaction sent to the UTXO contract, which receipt has the successful status.
+#[cfg(feature = "chain")]
+#[derive(Debug, Clone)]
+pub struct ChainTransferProvider<M> {
+    client: std::sync::Arc<M>,
+    contract: Address,
+}
+
+#[cfg(feature = "chain")]
+impl<M: ethers_providers::Middleware> ChainTransferProvider<M> {
+    pub fn new(client: std::sync::Arc<M>, contract: Address) -> Self {
+        Self { client, contract }
+    }
+}
+
+#[cfg(feature = "chain")]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M: ethers_providers::Middleware> TransferProvider for ChainTransferProvider<M> {
+    type Error = M::Error;
+
+    async fn get_transfer(&self, tx_hash: H256) -> Result<Option<Transfer>, Self::Error> {
+        let Some(receipt) = self.client.get_transaction_receipt(tx_hash).await? else {
+            return Ok(None);
+        };
+        if receipt.status != Some(1.into()) {
+            return Ok(None);
+        }
+
+        let Some(tx) = self.client.get_transaction(tx_hash).await? else {
+            return Ok(None);
+        };
+        if tx.to != Some(self.contract) {
+            return Ok(None);
+        }
+
+        Ok(decode_transfer(&tx.input))
+    }
+}
+
+/// Decode the call data of the `transfer` function, `None` is returned for a call of
+/// another function or malformed arguments. The arguments are decoded by the ABI types of
+/// the contract bindings, which [`TRANSFER_SIGNATURE`] is checked against in the tests.
+#[cfg(feature = "chain")]
+fn decode_transfer(data: &[u8]) -> Option<Transfer> {
+    use coin_shuffle_contracts_bindings::utxo::types::Input;
+    use ethers_core::abi::{self, AbiType, Tokenizable};
+
+    let selector = &ethers_core::utils::keccak256(TRANSFER_SIGNATURE)[..4];
+    let args = data.strip_prefix(selector)?;
+
+    let params = [Vec::<Input>::param_type(), Vec::<Output>::param_type()];
+    let mut tokens = abi::decode(&params, args).ok()?.into_iter();
+    let inputs = Vec::<Input>::from_token(tokens.next()?).ok()?;
+    let outputs = Vec::<Output>::from_token(tokens.next()?).ok()?;
+
+    Some(Transfer {
+        inputs: inputs.into_iter().map(|input| input.id).collect(),
+        outputs,
+    })
+}
+
+/// Difference between the transaction and the room it should finish.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TxMismatch {
+    #[error("transfer isn't found by tx hash: {0:?}")]
+    TransferNotFound(H256),
+    #[error("utxo isn't an input of the transfer utxo_id: {0}")]
+    InputIsAbsent(U256),
+    #[error("utxo isn't spent utxo_id: {0}")]
+    UtxoIsNotSpent(U256),
+    #[error("output isn't created by the transfer: {0}")]
+    OutputIsAbsent(Bytes),
+    #[error("invalid output amount: expected {expected}, actual {actual}")]
+    OutputAmount { expected: U256, actual: U256 },
+    #[error("change isn't sent by the transfer to: {0:?}")]
+    ChangeIsAbsent(Address),
+}
+
+#[cfg(all(test, feature = "chain"))]
+mod tests {
+    use std::sync::Arc;
+
+    use coin_shuffle_contracts_bindings::utxo::types::{Input, Output};
+    use ethers_core::abi::{self, AbiType, Tokenizable};
+    use ethers_core::types::{Address, Bytes, Transaction, TransactionReceipt, H256, U256};
+    use ethers_core::utils::keccak256;
+    use ethers_providers::{MockProvider, Provider};
+
+    use super::{ChainTransferProvider, TransferProvider, TRANSFER_SIGNATURE};
+
+    fn transfer_data(inputs: &[U256], outputs: &[Output]) -> Bytes {
+        let inputs = inputs
+            .iter()
+            .map(|id| Input {
+                id: *id,
+                signature: vec![1; 65].into(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = keccak256(TRANSFER_SIGNATURE)[..4].to_vec();
+        data.extend(abi::encode(&[
+            inputs.into_token(),
+            outputs.to_vec().into_token(),
+        ]));
+
+        data.into()
+    }
+
+    /// Push the responses of the receipt and transaction requests, which are popped from
+    /// the end of the mock.
+    fn respond(mock: &MockProvider, to: Address, input: Bytes, status: u64) {
+        let tx = Transaction {
+            to: Some(to),
+            input,
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            status: Some(status.into()),
+            ..Default::default()
+        };
+
+        mock.push(tx).expect("failed to push tx");
+        mock.push(receipt).expect("failed to push receipt");
+    }
+
+    /// The selector is computed from the signature, so it has to follow the ABI types of the
+    /// `transfer` arguments in the contract bindings.
+    #[test]
+    fn transfer_signature_matches_bindings() {
+        let signature = format!(
+            "transfer({},{})",
+            Vec::<Input>::param_type(),
+            Vec::<Output>::param_type()
+        );
+        assert_eq!(signature, TRANSFER_SIGNATURE);
+    }
+
+    #[tokio::test]
+    async fn transfer_is_decoded_from_chain() {
+        let (provider, mock) = Provider::mocked();
+        let contract = Address::random();
+        let transfer_provider = ChainTransferProvider::new(Arc::new(provider), contract);
+
+        let inputs = vec![U256::from(1), U256::from(2)];
+        let outputs = vec![
+            Output {
+                amount: U256::from(100),
+                owner: Address::random(),
+            },
+            Output {
+                amount: U256::from(100),
+                owner: Address::random(),
+            },
+        ];
+
+        respond(&mock, contract, transfer_data(&inputs, &outputs), 1);
+        let transfer = transfer_provider
+            .get_transfer(H256::random())
+            .await
+            .expect("failed to get transfer")
+            .expect("transfer is absent");
+        assert_eq!(transfer.inputs, inputs);
+        assert_eq!(transfer.outputs, outputs);
+
+        // Reverted transaction, transaction to another contract and another call
+        respond(&mock, contract, transfer_data(&inputs, &outputs), 0);
+        respond(
+            &mock,
+            Address::random(),
+            transfer_data(&inputs, &outputs),
+            1,
+        );
+        respond(&mock, contract, Bytes::from(vec![0; 36]), 1);
+        for _ in 0..3 {
+            assert!(transfer_provider
+                .get_transfer(H256::random())
+                .await
+                .expect("failed to get transfer")
+                .is_none());
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Shuffle order of the room participants, that can't be chosen by the coordinator.
+//!
+//! Every participant commits to its random entropy on connect, and reveals the entropy
+//! once all the participants are connected and their [`Commitments`] are published. The
+//! order is derived from the [`seed`] of all the reveals, so it is fixed before anyone can
+//! see the entropy of the others, and each participant checks it by [`verify_order`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ethers_core::abi::AbiEncode;
+use ethers_core::types::{H256, U256};
+use ethers_core::utils::keccak256;
+use uuid::Uuid;
+
+/// Commitments to the entropy of the room participants by their UTXO ids.
+pub type Commitments = BTreeMap<U256, H256>;
+/// Entropy revealed by the room participants by their UTXO ids.
+pub type Reveals = BTreeMap<U256, H256>;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("participant {0} isn't committed to the entropy")]
+    NotCommitted(U256),
+    #[error("entropy of participant {0} isn't revealed")]
+    NotRevealed(U256),
+    #[error("entropy of participant {0} doesn't match its commitment")]
+    InvalidReveal(U256),
+    #[error("order of the participants isn't derived from the reveals")]
+    InvalidOrder,
+}
+
+/// Return the commitment of the participant to its entropy in the room.
+pub fn commitment(room_id: &Uuid, utxo_id: U256, entropy: H256) -> H256 {
+    let mut message = room_id.as_bytes().to_vec();
+    message.append(&mut utxo_id.encode());
+    message.extend_from_slice(entropy.as_bytes());
+
+    H256::from(keccak256(message))
+}
+
+/// Return the seed of the order, that depends on the entropy of every participant.
+pub fn seed(reveals: &Reveals) -> H256 {
+    let mut message = Vec::with_capacity(reveals.len() * 64);
+    for (utxo_id, entropy) in reveals.iter() {
+        message.append(&mut utxo_id.encode());
+        message.extend_from_slice(entropy.as_bytes());
+    }
+
+    H256::from(keccak256(message))
+}
+
+/// Return the participants in the shuffle order derived from the seed.
+pub fn shuffle_order(participants: &[U256], seed: H256) -> Vec<U256> {
+    let mut order = participants.to_vec();
+    order.sort_by_cached_key(|utxo_id| {
+        let mut message = seed.as_bytes().to_vec();
+        message.append(&mut utxo_id.encode());
+
+        keccak256(message)
+    });
+
+    order
+}
+
+/// Check that every participant revealed the entropy it committed to, and the participants
+/// are in the order derived from the reveals.
+pub fn verify_order(
+    room_id: &Uuid,
+    commitments: &Commitments,
+    reveals: &Reveals,
+    participants: &[U256],
+) -> Result<(), Error> {
+    let participants_set: BTreeSet<_> = participants.iter().copied().collect();
+    if let Some(utxo_id) = participants_set
+        .iter()
+        .find(|utxo_id| !commitments.contains_key(utxo_id))
+    {
+        return Err(Error::NotCommitted(*utxo_id));
+    }
+
+    for (utxo_id, committed) in commitments.iter() {
+        let entropy = reveals.get(utxo_id).ok_or(Error::NotRevealed(*utxo_id))?;
+        if commitment(room_id, *utxo_id, *entropy) != *committed {
+            return Err(Error::InvalidReveal(*utxo_id));
+        }
+    }
+
+    if participants_set.len() != participants.len()
+        || reveals.len() != participants.len()
+        || shuffle_order(participants, seed(reveals)) != participants
+    {
+        return Err(Error::InvalidOrder);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{H256, U256};
+    use uuid::Uuid;
+
+    use super::{commitment, seed, shuffle_order, verify_order, Commitments, Error, Reveals};
+
+    fn reveals(room_id: &Uuid, number: u64) -> (Commitments, Reveals) {
+        let reveals: Reveals = (0..number)
+            .map(|utxo_id| (U256::from(utxo_id), H256::random()))
+            .collect();
+        let commitments = reveals
+            .iter()
+            .map(|(utxo_id, entropy)| (*utxo_id, commitment(room_id, *utxo_id, *entropy)))
+            .collect();
+
+        (commitments, reveals)
+    }
+
+    #[test]
+    fn order_is_derived_from_reveals() {
+        let room_id = Uuid::new_v4();
+        let (commitments, mut reveals) = reveals(&room_id, 8);
+        let participants: Vec<U256> = reveals.keys().copied().collect();
+
+        let order = shuffle_order(&participants, seed(&reveals));
+        verify_order(&room_id, &commitments, &reveals, &order).expect("order is invalid");
+
+        // Order chosen by the coordinator is rejected, as well as the one without someone
+        let mut swapped = order.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            verify_order(&room_id, &commitments, &reveals, &swapped),
+            Err(Error::InvalidOrder)
+        );
+        assert_eq!(
+            verify_order(&room_id, &commitments, &reveals, &order[..7]),
+            Err(Error::InvalidOrder)
+        );
+
+        // Entropy can't be changed after the commitment
+        reveals.insert(U256::from(3), H256::random());
+        assert_eq!(
+            verify_order(&room_id, &commitments, &reveals, &order),
+            Err(Error::InvalidReveal(U256::from(3)))
+        );
+    }
+}
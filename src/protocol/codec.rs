@@ -0,0 +1,55 @@
+//! Canonical encodings of the [`Envelope`]. JSON is meant for the text transports and
+//! debugging, the binary one is `bincode` with its default fixed-size integers encoding.
+//! The version of the envelope is checked before the message is decoded, so the peer
+//! running the other protocol version receives [`Error::UnsupportedVersion`].
+
+use serde::Deserialize;
+
+use super::{Envelope, PROTOCOL_VERSION};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("unsupported protocol version: {0}, expected {PROTOCOL_VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("invalid json message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid binary message: {0}")]
+    Binary(#[from] bincode::Error),
+}
+
+/// Leading part of the envelope that is decoded first to check the version.
+#[derive(Deserialize)]
+struct Version {
+    version: u16,
+}
+
+pub fn to_json(envelope: &Envelope) -> Result<String, Error> {
+    Ok(serde_json::to_string(envelope)?)
+}
+
+pub fn from_json(msg: &str) -> Result<Envelope, Error> {
+    let Version { version } = serde_json::from_str(msg)?;
+    check_version(version)?;
+
+    Ok(serde_json::from_str(msg)?)
+}
+
+pub fn to_binary(envelope: &Envelope) -> Result<Vec<u8>, Error> {
+    Ok(bincode::serialize(envelope)?)
+}
+
+pub fn from_binary(msg: &[u8]) -> Result<Envelope, Error> {
+    // Legacy bincode functions allow trailing bytes, so only the version is decoded here
+    let version: u16 = bincode::deserialize(msg)?;
+    check_version(version)?;
+
+    Ok(bincode::deserialize(msg)?)
+}
+
+fn check_version(version: u16) -> Result<(), Error> {
+    if version != PROTOCOL_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,489 @@
+//! Messages exchanged between the node and the coordinator running the service.
+//!
+//! The protocol doesn't depend on the transport: every message is wrapped into the
+//! [`Envelope`] with the protocol version and encoded by one of the [`codec`] functions.
+//! Node sends [`Request`]s and receives a [`Response`] with the same id for each of them,
+//! while the coordinator pushes [`Notification`]s when the room moves to the next stage.
+
+use coin_shuffle_contracts_bindings::utxo::types::Output;
+use ethers_core::types::{Address, Signature, H256, U256};
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::ordering::{Commitments, Reveals};
+use crate::types::Fee;
+
+pub mod codec;
+
+/// Version of the protocol implemented by this crate.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Versioned protocol message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u16,
+    pub message: Message,
+}
+
+impl Envelope {
+    pub fn new(message: Message) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    Request { id: u64, request: Request },
+    Response { id: u64, response: Response },
+    Notification(Notification),
+}
+
+/// Request of the node to the coordinator. Requests that change the room state after the
+/// connect are signed by the session key of the participant, see [`auth`](crate::auth).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// Create room of the UTXOs with given token and amount. The order of the shuffle is
+    /// derived from the entropy of the participants, see [`ordering`](crate::ordering).
+    CreateRoom {
+        token: Address,
+        amount: U256,
+        participants: Vec<U256>,
+    },
+    /// Get the nonce the UTXO owner signs to connect to its room.
+    Challenge { utxo_id: U256 },
+    /// Connect the UTXO owner to its room with the ephemeral RSA public key, the address
+    /// of the session key and the commitment to the entropy of the order. The change of the
+    /// UTXO above the room amount and the fee is sent to the `change` address. The signature
+    /// of the [`challenge_message`](crate::auth::challenge_message) is made by the owner.
+    ConnectParticipant {
+        utxo_id: U256,
+        rsa_pubkey: RsaPublicKey,
+        session_key: Address,
+        commitment: H256,
+        change: Option<Address>,
+        signature: Signature,
+    },
+    /// Reveal the entropy the participant committed to on connect.
+    RevealEntropy {
+        utxo_id: U256,
+        entropy: H256,
+        session_signature: Signature,
+    },
+    /// Leave the room before the shuffle is started, the signature of the
+    /// [`leave_message`](crate::auth::leave_message) is made by the UTXO owner.
+    LeaveRoom { utxo_id: U256, signature: Signature },
+    /// Get outputs that the participant should decode in its round.
+    EncodedOutputs { utxo_id: U256 },
+    /// Pass outputs decoded by the participant in its round.
+    PassDecodedOutputs {
+        utxo_id: U256,
+        decoded_outputs: Vec<Vec<u8>>,
+        session_signature: Signature,
+    },
+    /// Get outputs of the room that should be signed.
+    OutputsToSign { room_id: Uuid },
+    /// Pass signature of the room outputs.
+    PassSignature {
+        room_id: Uuid,
+        utxo_id: U256,
+        signature: Signature,
+        session_signature: Signature,
+    },
+}
+
+/// Response of the coordinator to the [`Request`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    /// Room is created.
+    RoomCreated { room_id: Uuid },
+    /// Nonce the UTXO owner signs to connect to the room, with the amount and the fee of the
+    /// room, that the owner approves by connecting.
+    Challenge {
+        room_id: Uuid,
+        nonce: H256,
+        amount: U256,
+        fee: Option<Fee>,
+    },
+    /// Participant is connected to the room.
+    Connected { room_id: Uuid },
+    /// Participant left the room.
+    Left { room_id: Uuid },
+    /// Entropy of the participant is accepted.
+    Revealed { room_id: Uuid },
+    /// Outputs to decode, empty for the first participant.
+    EncodedOutputs { outputs: Vec<Vec<u8>> },
+    /// Decoded outputs are accepted and the room moved to the next round.
+    ShuffleRound { round: usize },
+    /// Decoded outputs are accepted and they were the last ones.
+    ShuffleFinished { outputs: Vec<Output> },
+    /// Outputs of the room to sign.
+    OutputsToSign { outputs: Vec<Output> },
+    /// Signature is accepted.
+    SignatureAccepted,
+    /// Request failed.
+    Error(Error),
+}
+
+/// Message pushed by the coordinator to the room participant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Notification {
+    /// All participants are connected, so they should reveal the entropy they committed to.
+    RevealEntropy {
+        room_id: Uuid,
+        commitments: Commitments,
+    },
+    /// All participants revealed the entropy, so the participants are ordered by it. The
+    /// keys are the ones the participant encodes its output with, in the encoding order.
+    ShuffleStarted {
+        room_id: Uuid,
+        participants: Vec<U256>,
+        reveals: Reveals,
+        public_keys: Vec<RsaPublicKey>,
+    },
+    /// It is the participant's round of the shuffle.
+    ShuffleRound { room_id: Uuid, round: usize },
+    /// Shuffle is finished and the outputs should be signed.
+    SignOutputs { room_id: Uuid, outputs: Vec<Output> },
+    /// Transaction made of the signed outputs is sent.
+    TransactionHash { room_id: Uuid, tx_hash: H256 },
+    /// Room can't be finished anymore.
+    RoomFailed { room_id: Uuid, reason: String },
+}
+
+impl Notification {
+    /// Return id of the room the notification is about.
+    pub fn room_id(&self) -> &Uuid {
+        match self {
+            Self::RevealEntropy { room_id, .. }
+            | Self::ShuffleStarted { room_id, .. }
+            | Self::ShuffleRound { room_id, .. }
+            | Self::SignOutputs { room_id, .. }
+            | Self::TransactionHash { room_id, .. }
+            | Self::RoomFailed { room_id, .. } => room_id,
+        }
+    }
+}
+
+/// Error returned in the [`Response`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[error("{kind:?}: {message}")]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    ParticipantNotFound,
+    ParticipantAlreadyInRoom,
+    ParticipantNotInRoom,
+    RoomNotFound,
+    InvalidRound,
+    InvalidStatus,
+    InvalidNumberOfOutputs,
+    InvalidOutputs,
+    /// Participant failed to prove the ownership of the UTXO, the message isn't signed by
+    /// its session key, or the request is reserved for the operator of the coordinator.
+    Unauthorized,
+    /// Revealed entropy doesn't match the commitment of the participant.
+    InvalidReveal,
+    /// UTXO doesn't cover the room amount and the fee, or the change address isn't passed
+    /// for the change of the UTXO.
+    InvalidChange,
+    /// Request repeats the applied one, but differs from it.
+    Conflict,
+    /// Request violates the policy of the coordinator, the message names the policy.
+    PolicyViolation,
+    /// Message can't be decoded or isn't expected.
+    InvalidMessage,
+    /// Message has the version that isn't supported by the receiver.
+    UnsupportedVersion,
+    /// Coordinator failed by the reason that doesn't depend on the request.
+    Internal,
+}
+
+#[cfg(feature = "service")]
+impl From<&crate::service::error::Error> for Error {
+    fn from(err: &crate::service::error::Error) -> Self {
+        use crate::service::error::Error as ServiceError;
+
+        let kind = match err {
+            ServiceError::ParticipantNotFound => ErrorKind::ParticipantNotFound,
+            ServiceError::ParticipantAlreadyInRoom => ErrorKind::ParticipantAlreadyInRoom,
+            ServiceError::ParticipantNotInRoom => ErrorKind::ParticipantNotInRoom,
+            ServiceError::RoomNotFound => ErrorKind::RoomNotFound,
+            ServiceError::InvalidRound => ErrorKind::InvalidRound,
+            ServiceError::InvalidStatus => ErrorKind::InvalidStatus,
+            ServiceError::InvalidNumberOfOutputs => ErrorKind::InvalidNumberOfOutputs,
+            ServiceError::InvalidOutputs(_)
+            | ServiceError::GetDecodedOutputs(_)
+            | ServiceError::DuplicateOutputs(_)
+            | ServiceError::ForwardedOutputs(_)
+            | ServiceError::InvalidOutputLength(..) => ErrorKind::InvalidOutputs,
+            ServiceError::NoRSAPubKey => ErrorKind::InvalidStatus,
+            ServiceError::UtxoNotFound
+            | ServiceError::InvalidSignature
+            | ServiceError::InvalidSessionSignature => ErrorKind::Unauthorized,
+            ServiceError::ConflictingResubmission => ErrorKind::Conflict,
+            ServiceError::InvalidReveal => ErrorKind::InvalidReveal,
+            ServiceError::InsufficientAmount | ServiceError::InvalidChange => {
+                ErrorKind::InvalidChange
+            }
+            ServiceError::Policy(_) => ErrorKind::PolicyViolation,
+            ServiceError::FeeOverflow
+            | ServiceError::Transfer(_)
+            | ServiceError::Contract(_)
+            | ServiceError::Rsa(_)
+            | ServiceError::Storage(_) => ErrorKind::Internal,
+        };
+
+        Self::new(kind, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coin_shuffle_contracts_bindings::utxo::types::Output;
+    use ethers_core::types::{Address, Signature, H256, U256};
+    use rsa::RsaPublicKey;
+
+    use super::{codec, Envelope, Error, ErrorKind, Message, Notification, Request, Response};
+    use crate::rsa::{generate_private_key, MIN_KEY_SIZE};
+    use crate::types::Fee;
+
+    fn messages() -> Vec<Message> {
+        let rsa_pubkey = RsaPublicKey::from(
+            &generate_private_key(MIN_KEY_SIZE).expect("failed to generate a key"),
+        );
+        let room_id = uuid::Uuid::new_v4();
+        let utxo_id = U256::from(42);
+        let signature = Signature {
+            r: U256::from(1),
+            s: U256::MAX,
+            v: 27,
+        };
+        let outputs = vec![
+            Output {
+                amount: U256::from(100),
+                owner: Address::random(),
+            },
+            Output {
+                amount: U256::from(100),
+                owner: Address::random(),
+            },
+        ];
+
+        vec![
+            Message::Request {
+                id: 0,
+                request: Request::CreateRoom {
+                    token: Address::random(),
+                    amount: U256::from(100),
+                    participants: vec![utxo_id, U256::from(43)],
+                },
+            },
+            Message::Request {
+                id: 1,
+                request: Request::Challenge { utxo_id },
+            },
+            Message::Request {
+                id: 1,
+                request: Request::ConnectParticipant {
+                    utxo_id,
+                    rsa_pubkey: rsa_pubkey.clone(),
+                    session_key: Address::random(),
+                    commitment: H256::random(),
+                    change: Some(Address::random()),
+                    signature,
+                },
+            },
+            Message::Request {
+                id: 2,
+                request: Request::RevealEntropy {
+                    utxo_id,
+                    entropy: H256::random(),
+                    session_signature: signature,
+                },
+            },
+            Message::Request {
+                id: 2,
+                request: Request::LeaveRoom { utxo_id, signature },
+            },
+            Message::Request {
+                id: 2,
+                request: Request::EncodedOutputs { utxo_id },
+            },
+            Message::Request {
+                id: 3,
+                request: Request::PassDecodedOutputs {
+                    utxo_id,
+                    decoded_outputs: vec![vec![1, 2, 3], vec![]],
+                    session_signature: signature,
+                },
+            },
+            Message::Request {
+                id: 4,
+                request: Request::OutputsToSign { room_id },
+            },
+            Message::Request {
+                id: 5,
+                request: Request::PassSignature {
+                    room_id,
+                    utxo_id,
+                    signature,
+                    session_signature: signature,
+                },
+            },
+            Message::Response {
+                id: 0,
+                response: Response::RoomCreated { room_id },
+            },
+            Message::Response {
+                id: 1,
+                response: Response::Challenge {
+                    room_id,
+                    nonce: H256::random(),
+                    amount: U256::from(100),
+                    fee: Some(Fee {
+                        recipient: Address::random(),
+                        amount: U256::from(1),
+                    }),
+                },
+            },
+            Message::Response {
+                id: 1,
+                response: Response::Connected { room_id },
+            },
+            Message::Response {
+                id: 2,
+                response: Response::Left { room_id },
+            },
+            Message::Response {
+                id: 2,
+                response: Response::Revealed { room_id },
+            },
+            Message::Response {
+                id: 2,
+                response: Response::EncodedOutputs {
+                    outputs: vec![vec![0; 256]],
+                },
+            },
+            Message::Response {
+                id: 3,
+                response: Response::ShuffleRound { round: 2 },
+            },
+            Message::Response {
+                id: 3,
+                response: Response::ShuffleFinished {
+                    outputs: outputs.clone(),
+                },
+            },
+            Message::Response {
+                id: 4,
+                response: Response::OutputsToSign {
+                    outputs: outputs.clone(),
+                },
+            },
+            Message::Response {
+                id: 5,
+                response: Response::SignatureAccepted,
+            },
+            Message::Response {
+                id: 6,
+                response: Response::Error(Error::new(ErrorKind::InvalidRound, "Invalid round")),
+            },
+            Message::Notification(Notification::RevealEntropy {
+                room_id,
+                commitments: [(utxo_id, H256::random()), (U256::from(43), H256::random())]
+                    .into_iter()
+                    .collect(),
+            }),
+            Message::Notification(Notification::ShuffleStarted {
+                room_id,
+                participants: vec![U256::from(43), utxo_id],
+                reveals: [(utxo_id, H256::random()), (U256::from(43), H256::random())]
+                    .into_iter()
+                    .collect(),
+                public_keys: vec![rsa_pubkey.clone(), rsa_pubkey],
+            }),
+            Message::Notification(Notification::ShuffleRound { room_id, round: 1 }),
+            Message::Notification(Notification::SignOutputs { room_id, outputs }),
+            Message::Notification(Notification::TransactionHash {
+                room_id,
+                tx_hash: H256::random(),
+            }),
+            Message::Notification(Notification::RoomFailed {
+                room_id,
+                reason: "participant is gone".to_string(),
+            }),
+        ]
+    }
+
+    #[test]
+    fn json_round_trip() {
+        for message in messages() {
+            let envelope = Envelope::new(message);
+
+            let encoded = codec::to_json(&envelope).expect("failed to encode");
+            let decoded = codec::from_json(&encoded).expect("failed to decode");
+
+            assert_eq!(decoded, envelope, "decoded message differs: {encoded}");
+            assert_eq!(
+                codec::to_json(&decoded).expect("failed to encode"),
+                encoded,
+                "encoding isn't canonical"
+            );
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        for message in messages() {
+            let envelope = Envelope::new(message);
+
+            let encoded = codec::to_binary(&envelope).expect("failed to encode");
+            let decoded = codec::from_binary(&encoded).expect("failed to decode");
+
+            assert_eq!(decoded, envelope, "decoded message differs");
+            assert_eq!(
+                codec::to_binary(&decoded).expect("failed to encode"),
+                encoded,
+                "encoding isn't canonical"
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut envelope = Envelope::new(Message::Notification(Notification::ShuffleRound {
+            room_id: uuid::Uuid::new_v4(),
+            round: 0,
+        }));
+        envelope.version += 1;
+
+        let json = codec::to_json(&envelope).expect("failed to encode");
+        assert!(matches!(
+            codec::from_json(&json),
+            Err(codec::Error::UnsupportedVersion(version)) if version == envelope.version
+        ));
+
+        let binary = codec::to_binary(&envelope).expect("failed to encode");
+        assert!(matches!(
+            codec::from_binary(&binary),
+            Err(codec::Error::UnsupportedVersion(version)) if version == envelope.version
+        ));
+    }
+}
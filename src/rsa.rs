@@ -1,9 +1,14 @@
 use ethers_core::k256::elliptic_curve::rand_core::{self, CryptoRng, CryptoRngCore, RngCore};
 use ethers_core::k256::sha2::Sha256;
-pub use rsa::{errors::Error as RSAError, Oaep, PublicKey, RsaPrivateKey, RsaPublicKey};
+pub use rsa::{
+    errors::Error as RSAError, Oaep, PublicKey, PublicKeyParts, RsaPrivateKey, RsaPublicKey,
+};
+use std::time::Instant;
 
-const ENCRYPTING_CHUNK_SIZE2048PUB_KEY: usize = 126;
-const ENCRYPTED_CHUNK_SIZE2048PUB_KEY: usize = 256;
+/// Size of the RSA keys generated for the rooms if another one isn't specified.
+pub const DEFAULT_KEY_SIZE: usize = 2048;
+/// Minimal size of the RSA key in bits, smaller keys can't encrypt a message chunk.
+pub const MIN_KEY_SIZE: usize = 1024;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -13,6 +18,10 @@ pub enum Error {
     FailedToDecryptWithPrivateKey(RSAError),
     #[error("invalid chunk size: {0}")]
     InvalidChunkSize(usize),
+    #[error("unsupported key size: {0} bits, minimal is {MIN_KEY_SIZE}")]
+    UnsupportedKeySize(usize),
+    #[error("failed to generate private key: {0}")]
+    FailedToGeneratePrivateKey(RSAError),
 }
 
 #[derive(Default, Clone)]
@@ -27,11 +36,45 @@ pub struct DecryptionResult {
     pub nonce: Vec<u8>,
 }
 
+/// Generate the ephemeral RSA private key of the given size in bits.
+pub fn generate_private_key(bits: usize) -> Result<RsaPrivateKey, Error> {
+    if bits < MIN_KEY_SIZE {
+        return Err(Error::UnsupportedKeySize(bits));
+    }
+
+    RsaPrivateKey::new(&mut rand::thread_rng(), bits).map_err(Error::FailedToGeneratePrivateKey)
+}
+
+/// Return size of the message chunk that is encrypted at once by the key with the modulus
+/// of `key_size` bytes. It is a half of the modulus without two bytes, which fits the
+/// OAEP with SHA-256 padding for all the keys since [`MIN_KEY_SIZE`], while the smaller
+/// keys are rejected.
+pub fn encrypting_chunk_size(key_size: usize) -> Result<usize, Error> {
+    if key_size < MIN_KEY_SIZE / 8 {
+        return Err(Error::UnsupportedKeySize(key_size * 8));
+    }
+
+    Ok(key_size / 2 - 2)
+}
+
+/// Return length of the message of `msg_len` bytes encoded by chunks with the keys of the
+/// given modulus sizes in bytes, in the order the keys are applied.
+pub fn encoded_len(
+    msg_len: usize,
+    key_sizes: impl IntoIterator<Item = usize>,
+) -> Result<usize, Error> {
+    key_sizes.into_iter().try_fold(msg_len, |len, key_size| {
+        Ok(len.div_ceil(encrypting_chunk_size(key_size)?) * key_size)
+    })
+}
+
 pub fn encode_by_chunks(
     msg: Vec<u8>,
     pub_key: RsaPublicKey,
     nonce: Vec<u8>,
 ) -> Result<EncryptionResult, Error> {
+    let started_at = Instant::now();
+    let chunk_size = encrypting_chunk_size(pub_key.size())?;
     let mut msg_buffer = msg;
     let result = &mut EncryptionResult::default();
     let mut rng = Noncer::new(rand::thread_rng(), nonce);
@@ -39,9 +82,9 @@ pub fn encode_by_chunks(
     while !msg_buffer.is_empty() {
         let mut chunk = msg_buffer.to_vec();
 
-        if chunk.len() >= ENCRYPTING_CHUNK_SIZE2048PUB_KEY {
-            chunk = chunk[..ENCRYPTING_CHUNK_SIZE2048PUB_KEY].to_vec();
-            msg_buffer = msg_buffer[ENCRYPTING_CHUNK_SIZE2048PUB_KEY..].to_vec();
+        if chunk.len() >= chunk_size {
+            chunk = chunk[..chunk_size].to_vec();
+            msg_buffer = msg_buffer[chunk_size..].to_vec();
         } else {
             msg_buffer = Vec::new();
         }
@@ -54,20 +97,23 @@ pub fn encode_by_chunks(
     }
 
     result.nonce = rng.nonce;
+    crate::telemetry::rsa_encrypted(started_at.elapsed());
     Ok(result.clone())
 }
 
-pub fn decode_by_chunks(msg: Vec<u8>, private_key: RsaPrivateKey) -> Result<Vec<u8>, Error> {
+pub fn decode_by_chunks(msg: Vec<u8>, private_key: &RsaPrivateKey) -> Result<Vec<u8>, Error> {
+    let started_at = Instant::now();
+    let chunk_size = private_key.size();
     let mut msg_buffer = msg;
     let mut decrypted_msg: Vec<u8> = Vec::new();
 
     while !msg_buffer.is_empty() {
-        if msg_buffer.len() < ENCRYPTED_CHUNK_SIZE2048PUB_KEY {
+        if msg_buffer.len() < chunk_size {
             Err(Error::InvalidChunkSize(msg_buffer.len()))?
         }
 
-        let chunk = msg_buffer[..ENCRYPTED_CHUNK_SIZE2048PUB_KEY].to_vec();
-        msg_buffer = msg_buffer[ENCRYPTED_CHUNK_SIZE2048PUB_KEY..].to_vec();
+        let chunk = msg_buffer[..chunk_size].to_vec();
+        msg_buffer = msg_buffer[chunk_size..].to_vec();
 
         decrypted_msg.append(
             &mut private_key
@@ -77,6 +123,7 @@ pub fn decode_by_chunks(msg: Vec<u8>, private_key: RsaPrivateKey) -> Result<Vec<
         );
     }
 
+    crate::telemetry::rsa_decrypted(started_at.elapsed());
     Ok(decrypted_msg)
 }
 
@@ -122,8 +169,11 @@ impl<R: CryptoRngCore> RngCore for Noncer<R> {
 
 #[cfg(test)]
 mod tests {
-    use crate::rsa::{decode_by_chunks, encode_by_chunks};
-    use rsa::{RsaPrivateKey, RsaPublicKey};
+    use crate::rsa::{
+        decode_by_chunks, encode_by_chunks, encoded_len, encrypting_chunk_size,
+        generate_private_key, Error, MIN_KEY_SIZE,
+    };
+    use rsa::{PublicKeyParts, RsaPrivateKey, RsaPublicKey};
 
     #[tokio::test]
     async fn happy_path() {
@@ -137,7 +187,7 @@ mod tests {
 
         let encode_result =
             encode_by_chunks(encode_message.as_bytes().to_vec(), pub_key, Vec::new()).unwrap();
-        let decode_result = decode_by_chunks(encode_result.encoded_msg, private_key).unwrap();
+        let decode_result = decode_by_chunks(encode_result.encoded_msg, &private_key).unwrap();
 
         assert_eq!(
             decode_result,
@@ -218,4 +268,65 @@ mod tests {
             "nonces are the same"
         );
     }
+
+    #[tokio::test]
+    async fn custom_key_size() {
+        let private_key = generate_private_key(1024).expect("failed to generate a key");
+        let pub_key = RsaPublicKey::from(&private_key);
+
+        let encode_message = [42u8; 300];
+
+        let encode_result = encode_by_chunks(encode_message.to_vec(), pub_key, Vec::new()).unwrap();
+        assert_eq!(
+            encode_result.encoded_msg.len(),
+            5 * 128,
+            "message isn't split into chunks of the key size"
+        );
+
+        let decode_result = decode_by_chunks(encode_result.encoded_msg, &private_key).unwrap();
+        assert_eq!(
+            decode_result, encode_message,
+            "source message isn't eq to the result message"
+        );
+    }
+
+    #[test]
+    fn encoded_len_of_layers() {
+        let keys = [MIN_KEY_SIZE, MIN_KEY_SIZE * 2].map(|bits| {
+            RsaPublicKey::from(&generate_private_key(bits).expect("failed to generate a key"))
+        });
+
+        let mut encoded_msg = vec![42u8; 20];
+        for key in keys.iter() {
+            encoded_msg = encode_by_chunks(encoded_msg, key.clone(), Vec::new())
+                .unwrap()
+                .encoded_msg;
+        }
+
+        assert_eq!(
+            encoded_msg.len(),
+            encoded_len(20, keys.iter().map(|key| key.size())).unwrap(),
+            "encoded length doesn't match the layers"
+        );
+    }
+
+    #[test]
+    fn too_small_key_is_rejected() {
+        assert!(matches!(
+            encrypting_chunk_size(3),
+            Err(Error::UnsupportedKeySize(24))
+        ));
+        assert!(matches!(
+            encoded_len(20, [MIN_KEY_SIZE / 8, 0]),
+            Err(Error::UnsupportedKeySize(0))
+        ));
+    }
+
+    #[test]
+    fn too_small_key_size() {
+        assert!(matches!(
+            generate_private_key(512),
+            Err(Error::UnsupportedKeySize(512))
+        ));
+    }
 }
@@ -0,0 +1,399 @@
+//! [`Coordinator`] served over TCP. Every line of the connection is a JSON [`Envelope`]:
+//! the node sends [`Request`]s and receives the responses with the same ids, while the
+//! notifications of the participant are pushed to the connection it was connected to the
+//! room from. Lines that can't be decoded are answered by the error response with id `0`.
+//!
+//! Rooms are created by the operator, so [`Request::CreateRoom`] is accepted only from the
+//! loopback connections, otherwise anyone could lock the UTXOs of others in the rooms.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use coin_shuffle_contracts_bindings::utxo::Contract;
+use ethers_core::types::U256;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::protocol::{self, codec, Envelope, ErrorKind, Message, Request, Response};
+use crate::service::{
+    coordinator::{Coordinator, TransactionSender},
+    storage::{inmemory, Storage},
+    Service, ServiceConfig,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum StorageBackend {
+    /// Rooms are kept in memory and lost on restart.
+    #[default]
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub service: ServiceConfig,
+    pub storage: StorageBackend,
+    /// Period the expired rooms are removed with, see [`Service::reap`].
+    pub reaper_period: Duration,
+}
+
+/// Bind the listener from the config and serve the service with the storage backend from
+/// it until the listener fails. The expired rooms are reaped in the background.
+pub async fn run<T>(config: Config, sender: T) -> std::io::Result<()>
+where
+    T: TransactionSender + Contract + 'static,
+{
+    let listener = TcpListener::bind(config.listen).await?;
+    log::info!("listening on {}", listener.local_addr()?);
+
+    match config.storage {
+        StorageBackend::Memory => {
+            let service = Service::new()
+                .with_config(config.service)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+            let reaper = service.clone();
+            tokio::spawn(async move {
+                if let Err(err) = reaper.run_reaper(config.reaper_period).await {
+                    log::error!("reaper failed: {err}");
+                }
+            });
+
+            let coordinator = Coordinator::new(service, sender);
+
+            Server::new(coordinator).serve(listener).await
+        }
+    }
+}
+
+pub struct Server<T: TransactionSender, S: Storage = inmemory::ServiceStorage> {
+    shared: Arc<Shared<T, S>>,
+}
+
+struct Shared<T: TransactionSender, S: Storage> {
+    coordinator: Coordinator<T, S>,
+    /// Connections the notifications of the participants are pushed to.
+    connections: Mutex<HashMap<U256, UnboundedSender<Message>>>,
+    /// Connects are handled exclusively, so the connection of the participant is registered
+    /// before the notifications of the other requests are pushed to it. The rest of the
+    /// requests are handled concurrently.
+    connecting: tokio::sync::RwLock<()>,
+}
+
+impl<T, S> Server<T, S>
+where
+    T: TransactionSender + Contract + 'static,
+    S: Storage + 'static,
+{
+    pub fn new(coordinator: Coordinator<T, S>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                coordinator,
+                connections: Mutex::new(HashMap::new()),
+                connecting: tokio::sync::RwLock::new(()),
+            }),
+        }
+    }
+
+    /// Accept the connections and serve each of them in a separate task.
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            log::debug!("accepted connection from {addr}");
+
+            let shared = Arc::clone(&self.shared);
+            tokio::spawn(async move {
+                if let Err(err) = shared.serve_connection(stream, addr).await {
+                    log::debug!("connection {addr} failed: {err}");
+                }
+            });
+        }
+    }
+}
+
+impl<T, S> Shared<T, S>
+where
+    T: TransactionSender + Contract + 'static,
+    S: Storage + 'static,
+{
+    async fn serve_connection(&self, stream: TcpStream, peer: SocketAddr) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+        let writing = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let mut line = codec::to_json(&Envelope::new(message))
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                line.push('\n');
+
+                writer.write_all(line.as_bytes()).await?;
+            }
+
+            Ok::<_, std::io::Error>(())
+        });
+
+        let mut participants = Vec::new();
+        let mut lines = BufReader::new(reader).lines();
+        let result = loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            };
+
+            let (id, request) = match codec::from_json(&line) {
+                Ok(Envelope {
+                    message: Message::Request { id, request },
+                    ..
+                }) => (id, request),
+                Ok(_) => {
+                    let err = protocol::Error::new(ErrorKind::InvalidMessage, "expected request");
+                    let _ = sender.send(error_response(err));
+                    continue;
+                }
+                Err(err) => {
+                    let kind = match err {
+                        codec::Error::UnsupportedVersion(_) => ErrorKind::UnsupportedVersion,
+                        _ => ErrorKind::InvalidMessage,
+                    };
+                    let _ =
+                        sender.send(error_response(protocol::Error::new(kind, err.to_string())));
+                    continue;
+                }
+            };
+
+            if let Some(utxo_id) = self.handle(id, request, peer, &sender).await {
+                participants.push(utxo_id);
+            }
+        };
+
+        {
+            let mut connections = self.connections();
+            for utxo_id in participants.iter() {
+                if connections
+                    .get(utxo_id)
+                    .is_some_and(|connection| connection.same_channel(&sender))
+                {
+                    connections.remove(utxo_id);
+                }
+            }
+        }
+
+        drop(sender);
+        writing.await.map_err(std::io::Error::other)??;
+
+        result
+    }
+
+    /// Handle the request and send the response to the connection before the
+    /// notifications are pushed. Return UTXO id of the participant, if it is connected to
+    /// the room by the request, so its notifications are pushed to this connection.
+    async fn handle(
+        &self,
+        id: u64,
+        request: Request,
+        peer: SocketAddr,
+        sender: &UnboundedSender<Message>,
+    ) -> Option<U256> {
+        if matches!(request, Request::CreateRoom { .. }) && !peer.ip().to_canonical().is_loopback()
+        {
+            let err = protocol::Error::new(
+                ErrorKind::Unauthorized,
+                "rooms are created only from the coordinator host",
+            );
+            let _ = sender.send(Message::Response {
+                id,
+                response: Response::Error(err),
+            });
+
+            return None;
+        }
+
+        let connecting = match &request {
+            Request::ConnectParticipant { utxo_id, .. } => Some(*utxo_id),
+            _ => None,
+        };
+        let (_exclusive, _shared) = match connecting {
+            Some(_) => (Some(self.connecting.write().await), None),
+            None => (None, Some(self.connecting.read().await)),
+        };
+        let handled = self.coordinator.handle(request).await;
+
+        let mut connections = self.connections();
+        let connected =
+            connecting.filter(|_| matches!(handled.response, Response::Connected { .. }));
+        if let Some(utxo_id) = connected {
+            connections.insert(utxo_id, sender.clone());
+        }
+
+        let _ = sender.send(Message::Response {
+            id,
+            response: handled.response,
+        });
+
+        for (utxo_id, notification) in handled.notifications {
+            match connections.get(&utxo_id) {
+                Some(connection) => {
+                    let _ = connection.send(Message::Notification(notification));
+                }
+                None => log::debug!("participant {utxo_id} isn't connected"),
+            }
+        }
+
+        connected
+    }
+
+    fn connections(&self) -> std::sync::MutexGuard<'_, HashMap<U256, UnboundedSender<Message>>> {
+        self.connections
+            .lock()
+            .expect("connections lock is poisoned")
+    }
+}
+
+fn error_response(err: protocol::Error) -> Message {
+    Message::Response {
+        id: 0,
+        response: Response::Error(err),
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use ethers_core::types::{Address, U256};
+    use ethers_signers::{LocalWallet, Signer};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::Server;
+    use crate::client::{tcp::TcpTransport, Client, Transport};
+    use crate::mock::{ledger::FileLedger, MockContract};
+    use crate::node::{storage::RoomMemoryStorage, Node};
+    use crate::protocol::{ErrorKind, Message, Request, Response};
+    use crate::rsa::MIN_KEY_SIZE;
+    use crate::service::{coordinator::Coordinator, Service};
+
+    const AMOUNT: u64 = 100;
+
+    #[tokio::test]
+    async fn room_is_created_only_from_loopback() {
+        let contract = MockContract::new();
+        let utxos = (0..3)
+            .map(|_| contract.deposit(Address::zero(), Address::random(), U256::from(AMOUNT)))
+            .collect::<Vec<_>>();
+        let server = Server::new(Coordinator::new(Service::new(), contract));
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        let create_room = || Request::CreateRoom {
+            token: Address::zero(),
+            amount: U256::from(AMOUNT),
+            participants: utxos.clone(),
+        };
+
+        let remote = "192.0.2.1:7700".parse().expect("invalid address");
+        server
+            .shared
+            .handle(1, create_room(), remote, &sender)
+            .await;
+        assert!(matches!(
+            receiver.recv().await,
+            Some(Message::Response { id: 1, response: Response::Error(err) })
+                if err.kind == ErrorKind::Unauthorized
+        ));
+
+        // IPv4 loopback mapped to IPv6 is the loopback too
+        let local = "[::ffff:127.0.0.1]:7700".parse().expect("invalid address");
+        server.shared.handle(2, create_room(), local, &sender).await;
+        assert!(matches!(
+            receiver.recv().await,
+            Some(Message::Response {
+                id: 2,
+                response: Response::RoomCreated { .. }
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn shuffle_over_tcp() {
+        let ledger = FileLedger::new(
+            std::env::temp_dir().join(format!("ledger-{}.json", uuid::Uuid::new_v4())),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to get address");
+        let server = Server::new(Coordinator::new(Service::new(), ledger.clone()));
+        tokio::spawn(server.serve(listener));
+
+        let wallets: Vec<LocalWallet> = (0..3)
+            .map(|_| LocalWallet::new(&mut rand::thread_rng()))
+            .collect();
+        let mut utxos = Vec::new();
+        for wallet in wallets.iter() {
+            utxos.push(
+                ledger
+                    .deposit(Address::zero(), wallet.address(), U256::from(AMOUNT))
+                    .expect("failed to deposit"),
+            );
+        }
+
+        let mut admin = TcpTransport::connect(addr)
+            .await
+            .expect("failed to connect");
+        let response = admin
+            .request(Request::CreateRoom {
+                token: Address::zero(),
+                amount: U256::from(AMOUNT),
+                participants: vec![utxos[0]],
+            })
+            .await
+            .expect("request failed");
+        assert!(matches!(
+            response,
+            Response::Error(err) if err.kind == ErrorKind::PolicyViolation
+        ));
+        let response = admin
+            .request(Request::CreateRoom {
+                token: Address::zero(),
+                amount: U256::from(AMOUNT),
+                participants: utxos.clone(),
+            })
+            .await
+            .expect("request failed");
+        assert!(matches!(response, Response::RoomCreated { .. }));
+
+        let mut tasks = Vec::new();
+        for (wallet, utxo_id) in wallets.into_iter().zip(utxos.iter().copied()) {
+            let transport = TcpTransport::connect(addr)
+                .await
+                .expect("failed to connect");
+            let node =
+                Node::new(RoomMemoryStorage::new(), ledger.clone()).with_rsa_key_size(MIN_KEY_SIZE);
+            let mut client = Client::new(node, transport, ledger.clone());
+
+            tasks.push(tokio::spawn(async move {
+                let output = Address::random().as_bytes().to_vec();
+                client.run(utxo_id, output, wallet, |_| {}).await
+            }));
+        }
+
+        let mut hashes = Vec::new();
+        for task in tasks {
+            hashes.push(
+                task.await
+                    .expect("participant task panicked")
+                    .expect("shuffle failed"),
+            );
+        }
+        assert!(hashes.windows(2).all(|pair| pair[0] == pair[1]));
+
+        let contract = ledger.load().expect("failed to load ledger");
+        for utxo_id in utxos {
+            assert!(contract.utxo(utxo_id).expect("utxo is absent").is_spent);
+        }
+
+        std::fs::remove_file(ledger.path()).expect("failed to remove ledger");
+    }
+}
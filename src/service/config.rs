@@ -0,0 +1,377 @@
+//! Policy the [`Service`](super::Service) enforces on the rooms. The config is built in code
+//! or deserialized from JSON or TOML, where the missing fields take the default values:
+//!
+//! ```toml
+//! min_participants = 3
+//! max_participants = 8
+//! allowed_tokens = ["0x0000000000000000000000000000000000000000"]
+//! connect_timeout = 300
+//!
+//! transcript_retention = 86400
+//!
+//! [room_ttl]
+//! waiting = 600
+//! shuffle = 120
+//!
+//! [fee]
+//! recipient = "0x0000000000000000000000000000000000000001"
+//! amount = "0x1"
+//! ```
+
+use std::time::Duration;
+
+use ethers_core::types::{Address, U256};
+
+use super::types::RoomState;
+use crate::rsa::MIN_KEY_SIZE;
+use crate::types::Fee;
+
+/// Minimum number of participants in the room, see [`ServiceConfig::min_participants`].
+pub const DEFAULT_MIN_PARTICIPANTS: usize = 2;
+/// Maximum number of participants in the room, see [`ServiceConfig::max_participants`].
+pub const DEFAULT_MAX_PARTICIPANTS: usize = 16;
+/// Maximum size of the RSA key of the participant in bits, see
+/// [`ServiceConfig::max_rsa_key_size`].
+pub const DEFAULT_MAX_RSA_KEY_SIZE: usize = 4096;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceConfig {
+    /// Rooms with less participants can't be created, and the room is dissolved once it has
+    /// less participants after the removal.
+    pub min_participants: usize,
+    /// Rooms with more participants can't be created.
+    pub max_participants: usize,
+    /// Tokens the rooms can be created for, any token is allowed if it is empty.
+    pub allowed_tokens: Vec<Address>,
+    /// Amounts the rooms can be created for, any amount is allowed if it is empty.
+    pub allowed_amounts: Vec<U256>,
+    /// Bounds of the size of the RSA keys the participants connect with, in bits.
+    pub min_rsa_key_size: usize,
+    pub max_rsa_key_size: usize,
+    /// Time since the creation of the room, in which all participants should connect to it.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub connect_timeout: Option<Duration>,
+    /// Time since the creation of the room, in which the outputs should be shuffled and
+    /// signed.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub shuffle_timeout: Option<Duration>,
+    /// Fee every participant of the new rooms pays, it isn't charged if it is absent.
+    pub fee: Option<Fee>,
+    /// Time the rooms are kept in every state without the progress, see [`RoomTtl`].
+    pub room_ttl: RoomTtl,
+    /// Time the transcript of the removed room is kept since its last event, the
+    /// transcripts are kept forever if it is absent.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub transcript_retention: Option<Duration>,
+}
+
+/// Time-to-live of the rooms by their state, that is counted since the last event of the
+/// room. The room is removed by [`Service::reap`](super::Service::reap) once it is exceeded,
+/// so its UTXOs can be placed in another room, and it is never removed if the time of its
+/// state is absent.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomTtl {
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub waiting: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub connecting: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub revealing: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub shuffle: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub signatures: Option<Duration>,
+    /// Time the finished room is kept after its transaction is sent.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub finished: Option<Duration>,
+}
+
+impl RoomTtl {
+    /// Return time-to-live of the room in the state.
+    pub fn of(&self, state: &RoomState) -> Option<Duration> {
+        match state {
+            RoomState::Waiting => self.waiting,
+            RoomState::Connecting(_) => self.connecting,
+            RoomState::Revealing(_) => self.revealing,
+            RoomState::Shuffle(_) => self.shuffle,
+            RoomState::Signatures(_) => self.signatures,
+            RoomState::TransactionHash(_) => self.finished,
+        }
+    }
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            min_participants: DEFAULT_MIN_PARTICIPANTS,
+            max_participants: DEFAULT_MAX_PARTICIPANTS,
+            allowed_tokens: Vec::new(),
+            allowed_amounts: Vec::new(),
+            min_rsa_key_size: MIN_KEY_SIZE,
+            max_rsa_key_size: DEFAULT_MAX_RSA_KEY_SIZE,
+            connect_timeout: None,
+            shuffle_timeout: None,
+            fee: None,
+            room_ttl: RoomTtl::default(),
+            transcript_retention: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("invalid config: {0}")]
+    Invalid(String),
+    #[error("failed to parse config: {0}")]
+    Parse(String),
+}
+
+/// Policy of the [`ServiceConfig`] the request violates.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("room should have from {min} to {max} participants")]
+    ParticipantsNumber { min: usize, max: usize },
+    #[error("token {0:?} isn't allowed")]
+    Token(Address),
+    #[error("amount {0} isn't allowed")]
+    Amount(U256),
+    #[error("RSA key of {size} bits, should be from {min} to {max} bits")]
+    RsaKeySize { size: usize, min: usize, max: usize },
+    #[error("connect timeout of the room is exceeded")]
+    ConnectDeadline,
+    #[error("shuffle timeout of the room is exceeded")]
+    ShuffleDeadline,
+}
+
+impl ServiceConfig {
+    /// Parse the config from JSON and validate it.
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            serde_json::from_str(json).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parse the config from TOML and validate it.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            toml::from_str(toml).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check that the bounds of the config are consistent.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.min_participants < DEFAULT_MIN_PARTICIPANTS {
+            return Err(ConfigError::Invalid(format!(
+                "min_participants should be at least {DEFAULT_MIN_PARTICIPANTS}"
+            )));
+        }
+        if self.min_participants > self.max_participants {
+            return Err(ConfigError::Invalid(
+                "min_participants is greater than max_participants".to_string(),
+            ));
+        }
+        if self.min_rsa_key_size < MIN_KEY_SIZE {
+            return Err(ConfigError::Invalid(format!(
+                "min_rsa_key_size should be at least {MIN_KEY_SIZE}"
+            )));
+        }
+        if self.min_rsa_key_size > self.max_rsa_key_size {
+            return Err(ConfigError::Invalid(
+                "min_rsa_key_size is greater than max_rsa_key_size".to_string(),
+            ));
+        }
+        // Fee output of the room is the fee of every participant
+        if let Some(fee) = &self.fee {
+            if fee
+                .amount
+                .checked_mul(U256::from(self.max_participants))
+                .is_none()
+            {
+                return Err(ConfigError::Invalid(
+                    "fee of max_participants overflows".to_string(),
+                ));
+            }
+        }
+        if let (Some(connect), Some(shuffle)) = (self.connect_timeout, self.shuffle_timeout) {
+            if connect > shuffle {
+                return Err(ConfigError::Invalid(
+                    "connect_timeout is greater than shuffle_timeout".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_room(
+        &self,
+        token: Address,
+        amount: U256,
+        participants: usize,
+    ) -> Result<(), PolicyViolation> {
+        if !(self.min_participants..=self.max_participants).contains(&participants) {
+            return Err(PolicyViolation::ParticipantsNumber {
+                min: self.min_participants,
+                max: self.max_participants,
+            });
+        }
+        if !self.allowed_tokens.is_empty() && !self.allowed_tokens.contains(&token) {
+            return Err(PolicyViolation::Token(token));
+        }
+        if !self.allowed_amounts.is_empty() && !self.allowed_amounts.contains(&amount) {
+            return Err(PolicyViolation::Amount(amount));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_rsa_key_size(&self, size: usize) -> Result<(), PolicyViolation> {
+        if !(self.min_rsa_key_size..=self.max_rsa_key_size).contains(&size) {
+            return Err(PolicyViolation::RsaKeySize {
+                size,
+                min: self.min_rsa_key_size,
+                max: self.max_rsa_key_size,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Durations are written as the number of seconds.
+#[cfg(feature = "serde")]
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .map(|duration| duration.as_secs())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers_core::types::{Address, U256};
+
+    use super::{ConfigError, PolicyViolation, ServiceConfig};
+
+    #[test]
+    fn json_config_takes_defaults() {
+        let config = ServiceConfig::from_json(r#"{"max_participants": 4, "connect_timeout": 60}"#)
+            .expect("failed to parse config");
+
+        assert_eq!(
+            config,
+            ServiceConfig {
+                max_participants: 4,
+                connect_timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            }
+        );
+        assert!(matches!(
+            ServiceConfig::from_json(r#"{"min_participants": 5, "max_participants": 4}"#),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            ServiceConfig::from_json(r#"{"max_participant": 4}"#),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_config() {
+        use crate::types::Fee;
+
+        let config = ServiceConfig::from_toml(
+            r#"
+            allowed_amounts = ["0x64"]
+            shuffle_timeout = 600
+
+            [room_ttl]
+            waiting = 60
+
+            [fee]
+            recipient = "0x0000000000000000000000000000000000000001"
+            amount = "0x1"
+            "#,
+        )
+        .expect("failed to parse config");
+
+        assert_eq!(config.allowed_amounts, vec![U256::from(100)]);
+        assert_eq!(config.shuffle_timeout, Some(Duration::from_secs(600)));
+        assert_eq!(config.room_ttl.waiting, Some(Duration::from_secs(60)));
+        assert_eq!(
+            config.fee,
+            Some(Fee {
+                recipient: Address::from_low_u64_be(1),
+                amount: U256::from(1),
+            })
+        );
+    }
+
+    #[test]
+    fn overflowing_fee_is_invalid() {
+        use crate::types::Fee;
+
+        let mut config = ServiceConfig {
+            fee: Some(Fee {
+                recipient: Address::zero(),
+                amount: U256::MAX / 16,
+            }),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.max_participants = 17;
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn room_policy() {
+        let config = ServiceConfig {
+            allowed_tokens: vec![Address::zero()],
+            allowed_amounts: vec![U256::from(100)],
+            ..Default::default()
+        };
+
+        assert!(config
+            .check_room(Address::zero(), U256::from(100), 2)
+            .is_ok());
+        assert_eq!(
+            config.check_room(Address::zero(), U256::from(100), 17),
+            Err(PolicyViolation::ParticipantsNumber { min: 2, max: 16 })
+        );
+        assert_eq!(
+            config.check_room(Address::repeat_byte(1), U256::from(100), 2),
+            Err(PolicyViolation::Token(Address::repeat_byte(1)))
+        );
+        assert_eq!(
+            config.check_room(Address::zero(), U256::from(50), 2),
+            Err(PolicyViolation::Amount(U256::from(50)))
+        );
+    }
+}
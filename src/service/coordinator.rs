@@ -0,0 +1,561 @@
+//! Protocol front of the [`Service`]: handles [`Request`]s of the nodes and tells which
+//! [`Notification`]s should be pushed to the room participants as the result.
+
+use async_trait::async_trait;
+use coin_shuffle_contracts_bindings::utxo::{
+    types::{Input, Output},
+    Contract,
+};
+use ethers_core::abi::ethereum_types::H520;
+use ethers_core::types::{Address, Signature, H256, U256};
+use uuid::Uuid;
+
+use super::{
+    error::Error,
+    storage::{inmemory, Storage},
+    PassDecodedOutputsResult, Removal, Service, ServiceResult, ShuffleStart, Submission,
+};
+use crate::ordering::Commitments;
+use crate::protocol::{self, Notification, Request, Response};
+
+/// Sender of the transfer transaction made of the room inputs and outputs.
+#[async_trait]
+pub trait TransactionSender: Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Send the transfer and return hash of its transaction.
+    async fn send_transfer(
+        &self,
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+    ) -> Result<H256, Self::Error>;
+}
+
+/// Result of the handled request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handled {
+    pub response: Response,
+    /// Notifications with the UTXO ids of participants they should be pushed to.
+    pub notifications: Vec<(U256, Notification)>,
+}
+
+impl Handled {
+    fn response(response: Response) -> Self {
+        Self {
+            response,
+            notifications: Vec::new(),
+        }
+    }
+}
+
+/// Coordinator of the rooms, where the sender is the UTXO contract, that is also used to
+/// authenticate the connecting participants.
+#[derive(Clone)]
+pub struct Coordinator<T: TransactionSender, S: Storage = inmemory::ServiceStorage> {
+    service: Service<S>,
+    sender: T,
+}
+
+impl<T: TransactionSender + Contract, S: Storage> Coordinator<T, S> {
+    pub fn new(service: Service<S>, sender: T) -> Self {
+        Self { service, sender }
+    }
+
+    pub fn service(&self) -> &Service<S> {
+        &self.service
+    }
+
+    /// Handle the request. Failures of the service are returned as [`Response::Error`].
+    pub async fn handle(&self, request: Request) -> Handled {
+        match self.try_handle(request).await {
+            Ok(handled) => handled,
+            Err(err) => Handled::response(Response::Error(protocol::Error::from(&err))),
+        }
+    }
+
+    async fn try_handle(&self, request: Request) -> ServiceResult<Handled> {
+        match request {
+            Request::CreateRoom {
+                token,
+                amount,
+                participants,
+            } => {
+                let room = self
+                    .service
+                    .create_room(token, amount, participants)
+                    .await?;
+
+                Ok(Handled::response(Response::RoomCreated {
+                    room_id: room.id,
+                }))
+            }
+            Request::Challenge { utxo_id } => {
+                let (room_id, nonce) = self.service.challenge(&utxo_id).await?;
+                let room = self
+                    .service
+                    .get_room(&room_id)
+                    .await?
+                    .ok_or(Error::RoomNotFound)?;
+
+                Ok(Handled::response(Response::Challenge {
+                    room_id,
+                    nonce,
+                    amount: room.amount,
+                    fee: room.fee,
+                }))
+            }
+            Request::ConnectParticipant {
+                utxo_id,
+                rsa_pubkey,
+                session_key,
+                commitment,
+                change,
+                signature,
+            } => {
+                self.connect_participant(
+                    utxo_id,
+                    rsa_pubkey,
+                    session_key,
+                    commitment,
+                    change,
+                    signature,
+                )
+                .await
+            }
+            Request::RevealEntropy {
+                utxo_id,
+                entropy,
+                session_signature,
+            } => {
+                let submission = self
+                    .service
+                    .reveal_entropy(&utxo_id, entropy, session_signature)
+                    .await?;
+                let participant = self
+                    .service
+                    .get_participant(&utxo_id)
+                    .await?
+                    .ok_or(Error::ParticipantNotFound)?;
+                let room_id = participant.room_id;
+
+                let mut handled = Handled::response(Response::Revealed { room_id });
+                // Notifications of the repeated reveal are already pushed
+                if let Submission::Applied(Some(start)) = submission {
+                    handled.notifications = Self::shuffle_started(room_id, start);
+                }
+
+                Ok(handled)
+            }
+            Request::LeaveRoom { utxo_id, signature } => {
+                let participant = self
+                    .service
+                    .get_participant(&utxo_id)
+                    .await?
+                    .ok_or(Error::ParticipantNotFound)?;
+                let room_id = participant.room_id;
+                let removal = self
+                    .service
+                    .leave_room(&self.sender, &utxo_id, signature)
+                    .await?;
+
+                Ok(Handled {
+                    response: Response::Left { room_id },
+                    notifications: self.removal_notifications(room_id, removal).await?,
+                })
+            }
+            Request::EncodedOutputs { utxo_id } => {
+                let outputs = self.service.encoded_outputs(&utxo_id).await?;
+
+                Ok(Handled::response(Response::EncodedOutputs { outputs }))
+            }
+            Request::PassDecodedOutputs {
+                utxo_id,
+                decoded_outputs,
+                session_signature,
+            } => {
+                self.pass_decoded_outputs(utxo_id, decoded_outputs, session_signature)
+                    .await
+            }
+            Request::OutputsToSign { room_id } => {
+                let outputs = self.service.outputs_to_sign(&room_id).await?;
+
+                Ok(Handled::response(Response::OutputsToSign { outputs }))
+            }
+            Request::PassSignature {
+                room_id,
+                utxo_id,
+                signature,
+                session_signature,
+            } => {
+                let submission = self
+                    .service
+                    .pass_signature(
+                        &room_id,
+                        &utxo_id,
+                        H520::from(<[u8; 65]>::from(signature)),
+                        session_signature,
+                    )
+                    .await?;
+
+                // Transfer of the repeated last signature is already sent
+                let Submission::Applied(Some((outputs, inputs))) = submission else {
+                    return Ok(Handled::response(Response::SignatureAccepted));
+                };
+
+                self.send_transfer(room_id, inputs, outputs).await
+            }
+        }
+    }
+
+    async fn connect_participant(
+        &self,
+        utxo_id: U256,
+        rsa_pubkey: rsa::RsaPublicKey,
+        session_key: Address,
+        commitment: H256,
+        change: Option<Address>,
+        signature: Signature,
+    ) -> ServiceResult<Handled> {
+        let submission = self
+            .service
+            .connect_participant(
+                &self.sender,
+                &utxo_id,
+                rsa_pubkey,
+                session_key,
+                commitment,
+                change,
+                signature,
+            )
+            .await?;
+
+        let participant = self
+            .service
+            .get_participant(&utxo_id)
+            .await?
+            .ok_or(Error::ParticipantNotFound)?;
+        let room_id = participant.room_id;
+
+        let mut handled = Handled::response(Response::Connected { room_id });
+        // Notifications of the repeated connect are already pushed
+        let Submission::Applied(Some(commitments)) = submission else {
+            return Ok(handled);
+        };
+
+        handled.notifications = Self::reveal_started(room_id, commitments);
+
+        Ok(handled)
+    }
+
+    /// Remove the participant from the room by the operator. Return the notifications that
+    /// should be pushed to the room participants, the kicked one is notified that the room
+    /// is failed for it.
+    pub async fn kick_participant(
+        &self,
+        room_id: Uuid,
+        utxo_id: U256,
+    ) -> ServiceResult<Vec<(U256, Notification)>> {
+        let removal = self.service.kick_participant(&room_id, &utxo_id).await?;
+
+        let mut notifications = self.removal_notifications(room_id, removal).await?;
+        notifications.push((
+            utxo_id,
+            Notification::RoomFailed {
+                room_id,
+                reason: "participant is kicked".to_string(),
+            },
+        ));
+
+        Ok(notifications)
+    }
+
+    async fn removal_notifications(
+        &self,
+        room_id: Uuid,
+        removal: Removal,
+    ) -> ServiceResult<Vec<(U256, Notification)>> {
+        match removal {
+            Removal::Resized => Ok(Vec::new()),
+            Removal::Started(commitments) => Ok(Self::reveal_started(room_id, commitments)),
+            Removal::Dissolved(participants) => Ok(participants
+                .into_iter()
+                .map(|participant_id| {
+                    (
+                        participant_id,
+                        Notification::RoomFailed {
+                            room_id,
+                            reason: "room is dissolved".to_string(),
+                        },
+                    )
+                })
+                .collect()),
+        }
+    }
+
+    /// Return notifications of the started reveal with the commitments of the participants.
+    fn reveal_started(room_id: Uuid, commitments: Commitments) -> Vec<(U256, Notification)> {
+        commitments
+            .keys()
+            .map(|participant_id| {
+                (
+                    *participant_id,
+                    Notification::RevealEntropy {
+                        room_id,
+                        commitments: commitments.clone(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Return notifications of the started shuffle with the order and the keys of the
+    /// participants.
+    fn shuffle_started(room_id: Uuid, start: ShuffleStart) -> Vec<(U256, Notification)> {
+        let ShuffleStart {
+            participants,
+            reveals,
+            mut keys,
+        } = start;
+
+        let mut notifications = Vec::new();
+        for participant_id in participants.iter() {
+            let public_keys = keys.remove(participant_id).unwrap_or_default();
+
+            notifications.push((
+                *participant_id,
+                Notification::ShuffleStarted {
+                    room_id,
+                    participants: participants.clone(),
+                    reveals: reveals.clone(),
+                    public_keys,
+                },
+            ));
+        }
+        if let Some(first) = participants.first() {
+            notifications.push((*first, Notification::ShuffleRound { room_id, round: 0 }));
+        }
+
+        notifications
+    }
+
+    async fn pass_decoded_outputs(
+        &self,
+        utxo_id: U256,
+        decoded_outputs: Vec<Vec<u8>>,
+        session_signature: Signature,
+    ) -> ServiceResult<Handled> {
+        let submission = self
+            .service
+            .pass_decoded_outputs(&utxo_id, decoded_outputs, session_signature)
+            .await?;
+        let repeated = submission.is_repeated();
+
+        let participant = self
+            .service
+            .get_participant(&utxo_id)
+            .await?
+            .ok_or(Error::ParticipantNotFound)?;
+        let room_id = participant.room_id;
+        let participants = self.participants(&room_id).await?;
+
+        let mut handled = match submission.into_inner() {
+            PassDecodedOutputsResult::Round(round) => Handled {
+                response: Response::ShuffleRound { round },
+                notifications: participants
+                    .get(round)
+                    .map(|next| (*next, Notification::ShuffleRound { room_id, round }))
+                    .into_iter()
+                    .collect(),
+            },
+            PassDecodedOutputsResult::Finished(outputs) => Handled {
+                notifications: participants
+                    .iter()
+                    .map(|participant_id| {
+                        (
+                            *participant_id,
+                            Notification::SignOutputs {
+                                room_id,
+                                outputs: outputs.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+                response: Response::ShuffleFinished { outputs },
+            },
+        };
+        // Notifications of the repeated pass are already pushed
+        if repeated {
+            handled.notifications.clear();
+        }
+
+        Ok(handled)
+    }
+
+    /// Send the transfer of the room, whose participants passed all the signatures, and
+    /// notify them with the transaction hash or the room failure.
+    async fn send_transfer(
+        &self,
+        room_id: Uuid,
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+    ) -> ServiceResult<Handled> {
+        let participants = self.participants(&room_id).await?;
+
+        let notification = match self.sender.send_transfer(inputs, outputs).await {
+            Ok(tx_hash) => {
+                self.service.set_transaction_hash(&room_id, tx_hash).await?;
+
+                Notification::TransactionHash { room_id, tx_hash }
+            }
+            Err(err) => {
+                log::debug!("failed to send transfer of room {room_id}: {err}");
+
+                Notification::RoomFailed {
+                    room_id,
+                    reason: format!("failed to send transfer: {err}"),
+                }
+            }
+        };
+
+        Ok(Handled {
+            response: Response::SignatureAccepted,
+            notifications: participants
+                .into_iter()
+                .map(|participant_id| (participant_id, notification.clone()))
+                .collect(),
+        })
+    }
+
+    async fn participants(&self, room_id: &Uuid) -> ServiceResult<Vec<U256>> {
+        let room = self
+            .service
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound)?;
+
+        Ok(room.participants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::{Address, H256, U256};
+    use ethers_signers::{LocalWallet, Signer};
+
+    use super::Coordinator;
+    use crate::auth::{challenge_message, decoded_outputs_message};
+    use crate::mock::MockContract;
+    use crate::protocol::{ErrorKind, Request, Response};
+    use crate::rsa::{generate_private_key, RsaPublicKey, MIN_KEY_SIZE};
+    use crate::service::Service;
+
+    fn random_wallet() -> LocalWallet {
+        LocalWallet::new(&mut rand::thread_rng())
+    }
+
+    fn is_unauthorized(response: &Response) -> bool {
+        matches!(response, Response::Error(err) if err.kind == ErrorKind::Unauthorized)
+    }
+
+    #[tokio::test]
+    async fn connect_is_authenticated() {
+        let contract = MockContract::new();
+        let owner = random_wallet();
+        let hijacker = random_wallet();
+        let session = random_wallet();
+        let utxo_id = contract.deposit(Address::zero(), owner.address(), U256::from(100));
+        let other_id = contract.deposit(Address::zero(), hijacker.address(), U256::from(100));
+
+        let coordinator = Coordinator::new(Service::new(), contract);
+        let response = coordinator
+            .handle(Request::CreateRoom {
+                token: Address::zero(),
+                amount: U256::from(100),
+                participants: vec![utxo_id, other_id],
+            })
+            .await
+            .response;
+        assert!(matches!(response, Response::RoomCreated { .. }));
+
+        let rsa_pubkey = RsaPublicKey::from(
+            &generate_private_key(MIN_KEY_SIZE).expect("failed to generate a key"),
+        );
+        let other_key = RsaPublicKey::from(
+            &generate_private_key(MIN_KEY_SIZE).expect("failed to generate a key"),
+        );
+
+        // Connect with the signature of the challenge by the wallet, where the signed
+        // message has the given RSA key
+        let commitment = H256::random();
+        let connect = |wallet: LocalWallet, signed_key: RsaPublicKey| {
+            let coordinator = &coordinator;
+            let rsa_pubkey = rsa_pubkey.clone();
+            let session_key = session.address();
+            async move {
+                let Response::Challenge { room_id, nonce, .. } = coordinator
+                    .handle(Request::Challenge { utxo_id })
+                    .await
+                    .response
+                else {
+                    panic!("challenge isn't issued");
+                };
+                let message = challenge_message(
+                    &room_id,
+                    utxo_id,
+                    nonce,
+                    &signed_key,
+                    session_key,
+                    commitment,
+                    None,
+                );
+                let signature = wallet.sign_message(message).await.expect("failed to sign");
+
+                coordinator
+                    .handle(Request::ConnectParticipant {
+                        utxo_id,
+                        rsa_pubkey,
+                        session_key,
+                        commitment,
+                        change: None,
+                        signature,
+                    })
+                    .await
+                    .response
+            }
+        };
+
+        // Signature of someone else or of the message with another key is rejected
+        assert!(is_unauthorized(
+            &connect(hijacker.clone(), rsa_pubkey.clone()).await
+        ));
+        assert!(is_unauthorized(&connect(owner.clone(), other_key).await));
+
+        let Response::Connected { room_id } = connect(owner.clone(), rsa_pubkey.clone()).await
+        else {
+            panic!("participant isn't connected");
+        };
+
+        // Repeated connect gets the original response
+        let Response::Connected { room_id: repeated } = connect(owner, rsa_pubkey.clone()).await
+        else {
+            panic!("repeated connect is rejected");
+        };
+        assert_eq!(repeated, room_id);
+
+        // Submissions are accepted only under the session key bound on connect
+        let decoded_outputs = vec![vec![1, 2, 3]];
+        let session_signature = hijacker
+            .sign_message(decoded_outputs_message(&room_id, utxo_id, &decoded_outputs))
+            .await
+            .expect("failed to sign");
+        let response = coordinator
+            .handle(Request::PassDecodedOutputs {
+                utxo_id,
+                decoded_outputs,
+                session_signature,
+            })
+            .await
+            .response;
+        assert!(is_unauthorized(&response));
+    }
+}
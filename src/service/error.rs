@@ -1,4 +1,7 @@
 use ethers_core::abi::AbiError;
+use ethers_core::types::U256;
+
+use super::config::PolicyViolation;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -16,8 +19,6 @@ pub enum Error {
     InvalidStatus,
     #[error("Invalid number of outputs")]
     InvalidNumberOfOutputs,
-    #[error("Invalid number of participants")]
-    InvalidNumberOfParticipants,
     #[error("Failed to create transfer")]
     Transfer(String),
     #[error("No RSA pub key")]
@@ -26,4 +27,40 @@ pub enum Error {
     GetDecodedOutputs(String),
     #[error("invalid outputs: {0}")]
     InvalidOutputs(AbiError),
+    #[error("participant {0} passed duplicate outputs")]
+    DuplicateOutputs(U256),
+    #[error("participant {0} passed the outputs of the previous participant unchanged")]
+    ForwardedOutputs(U256),
+    #[error("participant {0} passed outputs of invalid length, {1} bytes are expected")]
+    InvalidOutputLength(U256, usize),
+    #[error("UTXO not found")]
+    UtxoNotFound,
+    #[error("signature isn't made by the UTXO owner")]
+    InvalidSignature,
+    #[error("message isn't signed by the session key of the participant")]
+    InvalidSessionSignature,
+    #[error("revealed entropy doesn't match the commitment")]
+    InvalidReveal,
+    #[error("UTXO amount doesn't cover the room amount and the fee")]
+    InsufficientAmount,
+    #[error("fee output of the room overflows")]
+    FeeOverflow,
+    #[error("change address should be passed only for the UTXO with the change")]
+    InvalidChange,
+    #[error("request conflicts with the applied one")]
+    ConflictingResubmission,
+    #[error("policy violation: {0}")]
+    Policy(#[from] PolicyViolation),
+    #[error("failed to get UTXO: {0}")]
+    Contract(String),
+    #[error("invalid RSA key: {0}")]
+    Rsa(#[from] crate::rsa::Error),
+    #[error("storage error: {0}")]
+    Storage(String),
+}
+
+impl Error {
+    pub(crate) fn storage<E: std::error::Error>(err: E) -> Self {
+        Self::Storage(err.to_string())
+    }
 }
@@ -1,23 +1,54 @@
+pub mod config;
 pub mod error;
+pub mod query;
 pub mod storage;
+pub mod transcript;
 pub mod types;
 
+#[cfg(feature = "protocol")]
+pub mod coordinator;
+
 use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use crate::auth::{
+    challenge_message, decoded_outputs_message, leave_message, outputs_signature_message,
+    reveal_message,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::ordering::{self, Commitments, Reveals};
 use crate::service::types::RoomState;
-use coin_shuffle_contracts_bindings::utxo::types::{Input, Output};
-use ethers_core::abi::ethereum_types::Signature;
-use ethers_core::types::{Address, Bytes, U256};
-use rsa::RsaPublicKey;
+use crate::telemetry;
+use crate::types::{Resumption, ShuffleStatus};
+use coin_shuffle_contracts_bindings::utxo::{
+    types::{Input, Output},
+    Contract,
+};
+use ethers_core::abi::{ethereum_types::Signature, Hash};
+use ethers_core::types::{Address, Bytes, Signature as EcdsaSignature, H256, U256};
+use rsa::{PublicKeyParts, RsaPublicKey};
 
-use self::types::{EncodedOutput, Participant, ParticipantState, Room};
+use self::query::RoomStateKind;
+use self::storage::{ParticipantsStorage, RoomsStorage, Storage, TranscriptsStorage};
+use self::transcript::{Event, Transcript};
+use self::types::{
+    room_outputs, validate_decoded_outputs, EncodedOutput, InvalidDecodedOutputs, Participant,
+    ParticipantState, Room,
+};
 use self::{error::Error, storage::inmemory};
 
+pub use self::config::{
+    ConfigError, PolicyViolation, RoomTtl, ServiceConfig, DEFAULT_MIN_PARTICIPANTS,
+};
+
 pub type ServiceResult<T> = std::result::Result<T, Error>;
 
 #[derive(Clone)]
-pub struct Service {
-    storage: inmemory::ServiceStorage,
+pub struct Service<S: Storage = inmemory::ServiceStorage> {
+    storage: S,
+    config: ServiceConfig,
+    clock: Arc<dyn Clock>,
 }
 
 impl Default for Service {
@@ -28,75 +59,740 @@ impl Default for Service {
 
 impl Service {
     pub fn new() -> Self {
+        Self::with_storage(inmemory::ServiceStorage::new())
+    }
+}
+
+impl<S: Storage> Service<S> {
+    pub fn with_storage(storage: S) -> Self {
         Self {
-            storage: inmemory::ServiceStorage::new(),
+            storage,
+            config: ServiceConfig::default(),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Set the policy the service enforces on the rooms, the config is validated first.
+    pub fn with_config(mut self, config: ServiceConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        self.config = config;
+        Ok(self)
+    }
+
+    /// Set the clock the time of the rooms and transcripts is taken from, it is the system
+    /// time by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
     /// Create room with given participants, where each participant is represented by his UTXO id,
-    /// and return room.
-    pub async fn create_room(&self, token: Address, amount: U256, participants: Vec<U256>) -> Room {
-        let room = Room::new(token, amount, participants);
+    /// and return room. The UTXO can't be placed in two rooms at the same time, and the room
+    /// should satisfy the [`ServiceConfig`] of the service.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(room_id, participants = participants.len()))
+    )]
+    pub async fn create_room(
+        &self,
+        token: Address,
+        amount: U256,
+        participants: Vec<U256>,
+    ) -> ServiceResult<Room> {
+        self.config.check_room(token, amount, participants.len())?;
+        if participants.iter().collect::<BTreeSet<_>>().len() != participants.len() {
+            return Err(Error::ParticipantAlreadyInRoom);
+        }
+
+        // Rooms are created under the lock of the nil room id, so the concurrent creations
+        // can't place the same UTXO in two rooms
+        let _creation = self.lock_room(&uuid::Uuid::nil()).await?;
+        for participant in participants.iter() {
+            if self.get_participant(participant).await?.is_some() {
+                return Err(Error::ParticipantAlreadyInRoom);
+            }
+        }
+
+        let mut room = Room::new(token, amount, participants);
+        room.fee = self.config.fee.clone();
+        room.created_at = self.clock.now();
+        room.phase_started_at = room.created_at;
+        let _lock = self.lock_room(&room.id).await?;
+        telemetry::record_room(&room.id);
+
+        self.record(
+            &room.id,
+            Event::RoomCreated {
+                token,
+                amount,
+                fee: room.fee.clone(),
+                participants: room.participants.clone(),
+            },
+        )
+        .await?;
 
-        self.storage.rooms().insert(room.clone()).await;
+        self.storage
+            .rooms()
+            .insert(room.clone())
+            .await
+            .map_err(Error::storage)?;
 
         for participant in room.participants.iter() {
             self.storage
                 .participants()
                 .insert(Participant::new(*participant, room.id))
-                .await;
+                .await
+                .map_err(Error::storage)?;
         }
+        telemetry::room_created();
+
+        Ok(room)
+    }
+
+    /// Issue the nonce the participant should sign to connect to the room, see
+    /// [`auth`](crate::auth). The nonce is kept, so the repeated requests return the same
+    /// one. Return the room id with the nonce.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
+    pub async fn challenge(&self, participant_id: &U256) -> ServiceResult<(uuid::Uuid, H256)> {
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
+
+        let nonce = match participant.state {
+            ParticipantState::Wait => {
+                let nonce = H256::random();
+                self.record(
+                    &participant.room_id,
+                    Event::Challenged {
+                        utxo_id: *participant_id,
+                        nonce,
+                    },
+                )
+                .await?;
+                self.update_participant_state(participant_id, ParticipantState::Challenged(nonce))
+                    .await?;
+                nonce
+            }
+            ParticipantState::Challenged(nonce) => nonce,
+            // Participant is connected with the issued nonce
+            _ => self
+                .events(&participant.room_id)
+                .await?
+                .into_iter()
+                .find_map(|event| match event {
+                    Event::Challenged { utxo_id, nonce } if utxo_id == *participant_id => {
+                        Some(nonce)
+                    }
+                    _ => None,
+                })
+                .ok_or(Error::InvalidStatus)?,
+        };
 
-        room
+        Ok((participant.room_id, nonce))
     }
 
-    /// Connect participant to the room with passed RSA public key. If all participants are connected,
-    /// then start the shuffling process and return the keys that are needed to decrypt and encrypt
-    /// the message for given room and participant.
-    pub async fn connect_participant(
+    /// Connect participant to the room with passed RSA public key, the address of the
+    /// session key, that signs the next messages of the participant, and the commitment to
+    /// the entropy of the shuffle order. The `signature` of the [`challenge_message`] should
+    /// be made by the owner of the participant UTXO, that is got from the `contract`. If all
+    /// participants are connected, then start the reveal of the entropy and return the
+    /// commitments of the participants.
+    ///
+    /// The UTXO should cover the room amount and the fee of the room, and the `change`
+    /// address should be passed if the UTXO amount exceeds them, the change is sent to it
+    /// by the room transaction.
+    ///
+    /// The size of the RSA key and the connect timeout of the room are checked against the
+    /// [`ServiceConfig`].
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
+    pub async fn connect_participant<C: Contract>(
         &self,
+        contract: &C,
         participant_id: &U256,
         rsa_pubkey: RsaPublicKey,
-    ) -> ServiceResult<Option<HashMap<U256, Vec<RsaPublicKey>>>> {
-        let participant = self.participant_by_id(participant_id).await?;
+        session_key: Address,
+        commitment: H256,
+        change: Option<Address>,
+        signature: EcdsaSignature,
+    ) -> ServiceResult<Submission<Option<Commitments>>> {
+        let (_lock, mut participant) = self.lock_participant(participant_id).await?;
 
         let room = self.room_by_id(&participant.room_id).await?;
         if !room.participants.contains(participant_id) {
             return Err(Error::ParticipantNotInRoom);
         }
 
-        self.update_participant_state(participant_id, ParticipantState::Start(rsa_pubkey))
-            .await;
-
-        let connected = match room.state {
-            RoomState::Waiting => {
-                let mut connected = BTreeSet::new();
-                connected.insert(*participant_id);
-                connected
+        let nonce = match participant.state {
+            ParticipantState::Challenged(nonce) => nonce,
+            ParticipantState::Wait => return Err(Error::InvalidStatus),
+            _ => {
+                return self
+                    .repeated_connect(
+                        &room,
+                        participant_id,
+                        rsa_pubkey,
+                        session_key,
+                        commitment,
+                        change,
+                        signature,
+                    )
+                    .await
             }
-            RoomState::Connecting(mut keys) => {
-                keys.insert(*participant_id);
-                keys
+        };
+
+        // Nothing is recorded for the room, that doesn't accept the connects anymore
+        let mut connected = match &room.state {
+            RoomState::Waiting => BTreeSet::new(),
+            RoomState::Connecting(keys) => keys.clone(),
+            _ => return Err(Error::InvalidStatus),
+        };
+        connected.insert(*participant_id);
+
+        self.config.check_rsa_key_size(rsa_pubkey.size() * 8)?;
+        self.check_deadline(
+            &room,
+            self.config.connect_timeout,
+            PolicyViolation::ConnectDeadline,
+        )?;
+
+        let utxo = contract
+            .get_utxo_by_id(*participant_id)
+            .await
+            .map_err(|err| Error::Contract(err.to_string()))?
+            .ok_or(Error::UtxoNotFound)?;
+
+        let message = challenge_message(
+            &room.id,
+            *participant_id,
+            nonce,
+            &rsa_pubkey,
+            session_key,
+            commitment,
+            change,
+        );
+        signature
+            .verify(message.to_vec(), utxo.owner)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        let change_amount = crate::types::change(utxo.amount, room.amount, room.fee.as_ref())
+            .ok_or(Error::InsufficientAmount)?;
+        let change_output = match change {
+            Some(owner) if !change_amount.is_zero() => Some(Output {
+                amount: change_amount,
+                owner,
+            }),
+            None if change_amount.is_zero() => None,
+            _ => return Err(Error::InvalidChange),
+        };
+
+        self.record(
+            &room.id,
+            Event::Connected {
+                utxo_id: *participant_id,
+                owner: utxo.owner,
+                amount: utxo.amount,
+                rsa_pubkey: rsa_pubkey.clone(),
+                session_key,
+                commitment,
+                change,
+                signature,
+            },
+        )
+        .await?;
+
+        participant.state = ParticipantState::Start(rsa_pubkey);
+        participant.session_key = Some(session_key);
+        participant.commitment = Some(commitment);
+        participant.change = change_output;
+        self.storage
+            .participants()
+            .insert(participant)
+            .await
+            .map_err(Error::storage)?;
+
+        if connected.len() == room.participants.len() {
+            let commitments = self.commitments(&room.participants).await?;
+
+            self.update_room_state(&room.id, RoomState::Revealing(Reveals::new()))
+                .await?;
+            return Ok(Submission::Applied(Some(commitments)));
+        }
+
+        self.update_room_state(&room.id, RoomState::Connecting(connected))
+            .await?;
+
+        Ok(Submission::Applied(None))
+    }
+
+    /// Handle the connect of the connected participant. If it is identical to the applied
+    /// one, return the result of the original connect, the commitments are returned if it
+    /// started the reveal.
+    #[allow(clippy::too_many_arguments)]
+    async fn repeated_connect(
+        &self,
+        room: &Room,
+        participant_id: &U256,
+        rsa_pubkey: RsaPublicKey,
+        session_key: Address,
+        commitment: H256,
+        change: Option<Address>,
+        signature: EcdsaSignature,
+    ) -> ServiceResult<Submission<Option<Commitments>>> {
+        let events = self.events(&room.id).await?;
+
+        let nonce = events
+            .iter()
+            .find_map(|event| match event {
+                Event::Challenged { utxo_id, nonce } if utxo_id == participant_id => Some(*nonce),
+                _ => None,
+            })
+            .ok_or(Error::InvalidStatus)?;
+        let (position, owner, connected) = events
+            .iter()
+            .enumerate()
+            .find_map(|(position, event)| match event {
+                Event::Connected {
+                    utxo_id,
+                    owner,
+                    rsa_pubkey,
+                    session_key,
+                    commitment,
+                    change,
+                    signature,
+                    ..
+                } if utxo_id == participant_id => Some((
+                    position,
+                    *owner,
+                    (rsa_pubkey, *session_key, *commitment, *change, *signature),
+                )),
+                _ => None,
+            })
+            .ok_or(Error::InvalidStatus)?;
+
+        let message = challenge_message(
+            &room.id,
+            *participant_id,
+            nonce,
+            &rsa_pubkey,
+            session_key,
+            commitment,
+            change,
+        );
+        signature
+            .verify(message.to_vec(), owner)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        if connected != (&rsa_pubkey, session_key, commitment, change, signature) {
+            return Err(Error::ConflictingResubmission);
+        }
+
+        // Connect started the reveal if the room moved to it right after the connect
+        let started = matches!(
+            events.get(position + 1),
+            Some(Event::State(RoomState::Revealing(_)))
+        );
+        if !started {
+            return Ok(Submission::Repeated(None));
+        }
+
+        Ok(Submission::Repeated(Some(
+            self.commitments(&room.participants).await?,
+        )))
+    }
+
+    /// Return commitments of the connected participants to their entropy.
+    async fn commitments(&self, participants: &[U256]) -> ServiceResult<Commitments> {
+        self.storage
+            .participants()
+            .get_many(participants)
+            .await
+            .map_err(Error::storage)?
+            .into_iter()
+            .map(|participant| {
+                let commitment = participant.commitment.ok_or(Error::InvalidStatus)?;
+                Ok((participant.utxo_id, commitment))
+            })
+            .collect()
+    }
+
+    /// Reveal the entropy the participant committed to on connect, the message of the
+    /// entropy should be signed by the session key of the participant. Once all participants
+    /// revealed their entropy, order them by it, see [`ordering`], start the shuffling
+    /// process and return the [`ShuffleStart`].
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
+    pub async fn reveal_entropy(
+        &self,
+        participant_id: &U256,
+        entropy: H256,
+        session_signature: EcdsaSignature,
+    ) -> ServiceResult<Submission<Option<ShuffleStart>>> {
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
+        let mut room = self.room_by_id(&participant.room_id).await?;
+
+        let message = reveal_message(&room.id, *participant_id, entropy);
+        Self::verify_session_signature(&participant, message, &session_signature)?;
+
+        let mut reveals = match room.state {
+            RoomState::Waiting | RoomState::Connecting(_) => return Err(Error::InvalidStatus),
+            RoomState::Revealing(ref reveals) if !reveals.contains_key(participant_id) => {
+                reveals.clone()
             }
+            _ => return self.repeated_reveal(&room, participant_id, entropy).await,
+        };
+        self.check_deadline(
+            &room,
+            self.config.shuffle_timeout,
+            PolicyViolation::ShuffleDeadline,
+        )?;
+
+        let commitment = participant.commitment.ok_or(Error::InvalidStatus)?;
+        if ordering::commitment(&room.id, *participant_id, entropy) != commitment {
+            return Err(Error::InvalidReveal);
+        }
+
+        self.record(
+            &room.id,
+            Event::Revealed {
+                utxo_id: *participant_id,
+                entropy,
+                session_signature,
+            },
+        )
+        .await?;
+        reveals.insert(*participant_id, entropy);
+
+        if reveals.len() != room.participants.len() {
+            self.update_room_state(&room.id, RoomState::Revealing(reveals))
+                .await?;
+            return Ok(Submission::Applied(None));
+        }
+
+        room.participants = ordering::shuffle_order(&room.participants, ordering::seed(&reveals));
+        self.storage
+            .rooms()
+            .insert(room.clone())
+            .await
+            .map_err(Error::storage)?;
+
+        let keys = self.distribute_keys(room.participants.clone()).await?;
+        self.update_room_state(&room.id, RoomState::Shuffle(0))
+            .await?;
+
+        Ok(Submission::Applied(Some(ShuffleStart {
+            participants: room.participants,
+            reveals,
+            keys,
+        })))
+    }
+
+    /// Handle the reveal of the participant, that has revealed its entropy already. If it
+    /// is identical to the applied one, return the result of the original reveal.
+    async fn repeated_reveal(
+        &self,
+        room: &Room,
+        participant_id: &U256,
+        entropy: H256,
+    ) -> ServiceResult<Submission<Option<ShuffleStart>>> {
+        let events = self.events(&room.id).await?;
+
+        let (position, revealed) = events
+            .iter()
+            .enumerate()
+            .find_map(|(position, event)| match event {
+                Event::Revealed {
+                    utxo_id, entropy, ..
+                } if utxo_id == participant_id => Some((position, *entropy)),
+                _ => None,
+            })
+            .ok_or(Error::InvalidStatus)?;
+        if revealed != entropy {
+            return Err(Error::ConflictingResubmission);
+        }
+
+        // Reveal started the shuffle if the room moved to the first round right after it
+        let started = matches!(
+            events.get(position + 1),
+            Some(Event::State(RoomState::Shuffle(0)))
+        );
+        if !started {
+            return Ok(Submission::Repeated(None));
+        }
+
+        Ok(Submission::Repeated(Some(ShuffleStart {
+            participants: room.participants.clone(),
+            reveals: Self::revealed(&events),
+            keys: Self::participants_keys(&room.participants, &Self::connected_keys(&events))?,
+        })))
+    }
+
+    /// Return entropy revealed by the participants.
+    fn revealed(events: &[Event]) -> Reveals {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Revealed {
+                    utxo_id, entropy, ..
+                } => Some((*utxo_id, *entropy)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Return RSA public keys the participants are connected with.
+    fn connected_keys(events: &[Event]) -> HashMap<U256, RsaPublicKey> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Connected {
+                    utxo_id,
+                    rsa_pubkey,
+                    ..
+                } => Some((*utxo_id, rsa_pubkey.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Remove the participant from the room, that isn't shuffled yet, where the
+    /// [`leave_message`] is signed by the owner of the participant UTXO, that is got from
+    /// the `contract`.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
+    pub async fn leave_room<C: Contract>(
+        &self,
+        contract: &C,
+        participant_id: &U256,
+        signature: EcdsaSignature,
+    ) -> ServiceResult<Removal> {
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
+        let room = self.room_by_id(&participant.room_id).await?;
+
+        let utxo = contract
+            .get_utxo_by_id(*participant_id)
+            .await
+            .map_err(|err| Error::Contract(err.to_string()))?
+            .ok_or(Error::UtxoNotFound)?;
+        signature
+            .verify(
+                leave_message(&room.id, *participant_id).to_vec(),
+                utxo.owner,
+            )
+            .map_err(|_| Error::InvalidSignature)?;
+
+        let event = Event::Left {
+            utxo_id: *participant_id,
+            signature,
+        };
+        self.remove_participant(room, participant_id, event).await
+    }
+
+    /// Remove the participant from the room, that isn't shuffled yet, by the operator.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(room_id = %room_id, participant_id = %participant_id))
+    )]
+    pub async fn kick_participant(
+        &self,
+        room_id: &uuid::Uuid,
+        participant_id: &U256,
+    ) -> ServiceResult<Removal> {
+        let _lock = self.lock_room(room_id).await?;
+        let room = self.room_by_id(room_id).await?;
+        if !room.participants.contains(participant_id) {
+            return Err(Error::ParticipantNotInRoom);
+        }
+
+        let event = Event::Kicked {
+            utxo_id: *participant_id,
+        };
+        self.remove_participant(room, participant_id, event).await
+    }
+
+    async fn remove_participant(
+        &self,
+        mut room: Room,
+        participant_id: &U256,
+        event: Event,
+    ) -> ServiceResult<Removal> {
+        let mut connected = match room.state {
+            RoomState::Waiting => BTreeSet::new(),
+            RoomState::Connecting(ref connected) => connected.clone(),
             _ => return Err(Error::InvalidStatus),
         };
 
+        self.record(&room.id, event).await?;
+        self.storage
+            .participants()
+            .delete(*participant_id)
+            .await
+            .map_err(Error::storage)?;
+        room.participants.retain(|id| id != participant_id);
+        connected.remove(participant_id);
+        self.storage
+            .rooms()
+            .insert(room.clone())
+            .await
+            .map_err(Error::storage)?;
+
+        if room.participants.len() < self.config.min_participants {
+            self.record(&room.id, Event::Dissolved).await?;
+            telemetry::room_failed("dissolved");
+            let participants = self
+                .storage
+                .clear_room(&room.id)
+                .await
+                .map_err(Error::storage)?;
+
+            return Ok(Removal::Dissolved(participants));
+        }
+
+        if connected.is_empty() {
+            self.update_room_state(&room.id, RoomState::Waiting).await?;
+            return Ok(Removal::Resized);
+        }
         if connected.len() == room.participants.len() {
-            let keys = self.distribute_keys(room.participants).await?;
+            let commitments = self.commitments(&room.participants).await?;
+            self.update_room_state(&room.id, RoomState::Revealing(Reveals::new()))
+                .await?;
 
-            self.update_room_state(&room.id, RoomState::Shuffle(0))
-                .await;
-            return Ok(Some(keys));
+            return Ok(Removal::Started(commitments));
         }
 
         self.update_room_state(&room.id, RoomState::Connecting(connected))
-            .await;
+            .await?;
 
-        Ok(None)
+        Ok(Removal::Resized)
     }
 
-    async fn update_room_state(&self, room_id: &uuid::Uuid, state: RoomState) {
-        self.storage.rooms().update_state(*room_id, state).await;
+    /// Return the state of the shuffle, that the participant needs to resume it after the
+    /// restart of its node.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
+    pub async fn resume(&self, participant_id: &U256) -> ServiceResult<Resumption> {
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
+        let room = self.room_by_id(&participant.room_id).await?;
+        let position = Self::participant_position(&room, participant_id)?;
+
+        let commitments = match &room.state {
+            RoomState::Revealing(reveals) if !reveals.contains_key(participant_id) => {
+                Some(self.commitments(&room.participants).await?)
+            }
+            _ => None,
+        };
+
+        let (public_keys, reveals) = match room.state {
+            RoomState::Waiting | RoomState::Connecting(_) | RoomState::Revealing(_) => (None, None),
+            _ => {
+                let events = self.events(&room.id).await?;
+                let mut keys =
+                    Self::participants_keys(&room.participants, &Self::connected_keys(&events))?;
+
+                (keys.remove(participant_id), Some(Self::revealed(&events)))
+            }
+        };
+
+        let encoded_outputs = match (&room.state, &participant.state) {
+            (RoomState::Shuffle(round), ParticipantState::Start(_)) if *round == position => {
+                Some(self.round_outputs(&room, position).await?)
+            }
+            _ => None,
+        };
+
+        let outputs_to_sign = match (&room.state, &participant.state) {
+            (RoomState::Signatures((outputs, _)), ParticipantState::DecodedOutputs(_)) => {
+                Some(outputs.clone())
+            }
+            _ => None,
+        };
+
+        let tx_hash = match room.state {
+            RoomState::TransactionHash(tx_hash) => Some(tx_hash),
+            _ => None,
+        };
+
+        Ok(Resumption {
+            room_id: room.id,
+            token: room.token,
+            amount: room.amount,
+            fee: room.fee,
+            participants: room.participants,
+            status: ShuffleStatus::from(&participant.state),
+            commitments,
+            reveals,
+            public_keys,
+            encoded_outputs,
+            outputs_to_sign,
+            tx_hash,
+        })
+    }
+
+    /// Move the room to the state. If the state is of another kind, the current phase of the
+    /// room is finished and its duration is reported.
+    async fn update_room_state(&self, room_id: &uuid::Uuid, state: RoomState) -> ServiceResult<()> {
+        self.record(room_id, Event::State(state.clone())).await?;
+
+        // Nothing is updated for the cleared room
+        let Some(mut room) = self.get_room(room_id).await? else {
+            return Ok(());
+        };
+
+        let phase = RoomStateKind::from(&room.state);
+        if phase != RoomStateKind::from(&state) {
+            let now = self.clock.now();
+            telemetry::phase_finished(
+                phase.as_str(),
+                now.duration_since(room.phase_started_at)
+                    .unwrap_or_default(),
+            );
+            room.phase_started_at = now;
+        }
+        room.state = state;
+
+        self.storage
+            .rooms()
+            .insert(room)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Check that the `timeout` since the creation of the room isn't exceeded.
+    fn check_deadline(
+        &self,
+        room: &Room,
+        timeout: Option<Duration>,
+        violation: PolicyViolation,
+    ) -> ServiceResult<()> {
+        let Some(timeout) = timeout else {
+            return Ok(());
+        };
+
+        let elapsed = self
+            .clock
+            .now()
+            .duration_since(room.created_at)
+            .unwrap_or_default();
+        if elapsed > timeout {
+            return Err(violation.into());
+        }
+
+        Ok(())
     }
 
     async fn room_by_id(&self, room_id: &uuid::Uuid) -> ServiceResult<Room> {
@@ -104,14 +800,41 @@ impl Service {
             .rooms()
             .get(*room_id)
             .await
+            .map_err(Error::storage)?
             .ok_or(Error::RoomNotFound)
     }
 
+    async fn lock_room(&self, room_id: &uuid::Uuid) -> ServiceResult<S::RoomLock> {
+        self.storage
+            .lock_room(room_id)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Lock the room of the participant and return the participant read under the lock.
+    async fn lock_participant(
+        &self,
+        participant_id: &U256,
+    ) -> ServiceResult<(S::RoomLock, Participant)> {
+        loop {
+            let room_id = self.participant_by_id(participant_id).await?.room_id;
+            let lock = self.lock_room(&room_id).await?;
+
+            // Participant may be moved to another room while the lock is awaited
+            let participant = self.participant_by_id(participant_id).await?;
+            if participant.room_id == room_id {
+                telemetry::record_room(&room_id);
+                return Ok((lock, participant));
+            }
+        }
+    }
+
     async fn participant_by_id(&self, participant_id: &U256) -> ServiceResult<Participant> {
         self.storage
             .participants()
             .get(*participant_id)
             .await
+            .map_err(Error::storage)?
             .ok_or(Error::ParticipantNotFound)
     }
 
@@ -125,6 +848,7 @@ impl Service {
             .participants()
             .get_many(&participants)
             .await
+            .map_err(Error::storage)?
             .into_iter()
             .map(|p| {
                 let ParticipantState::Start(key) = p.state else {
@@ -134,6 +858,15 @@ impl Service {
             })
             .collect::<ServiceResult<HashMap<U256, RsaPublicKey>>>()?;
 
+        Self::participants_keys(&participants, &participants_keys)
+    }
+
+    /// Return the keys each participant encrypts the output with, where the participants
+    /// are connected with the given keys.
+    fn participants_keys(
+        participants: &[U256],
+        participants_keys: &HashMap<U256, RsaPublicKey>,
+    ) -> ServiceResult<HashMap<U256, Vec<RsaPublicKey>>> {
         let mut keys = HashMap::new();
 
         for (position, utxo_id) in participants.iter().enumerate() {
@@ -160,14 +893,27 @@ impl Service {
     }
 
     /// Return outputs that given participant should decrypt.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id))
+    )]
     pub async fn encoded_outputs(
         &self,
         participant_id: &U256,
     ) -> ServiceResult<Vec<EncodedOutput>> {
-        let participant = self.participant_by_id(participant_id).await?;
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
         let room = self.room_by_id(&participant.room_id).await?;
 
         let position = Self::participant_position(&room, participant_id)?;
+        self.round_outputs(&room, position).await
+    }
+
+    /// Return outputs that the participant at given position in the room should decrypt.
+    async fn round_outputs(
+        &self,
+        room: &Room,
+        position: usize,
+    ) -> ServiceResult<Vec<EncodedOutput>> {
         // Participant is the first one in the room, so he first adds his encoded outputs
         if position == 0 {
             return Ok(Vec::new());
@@ -195,55 +941,84 @@ impl Service {
             .ok_or(Error::ParticipantNotFound)
     }
 
-    /// Path decoded by participant outputs and store them in the storage.
+    /// Path decoded by participant outputs and store them in the storage. The message of the
+    /// outputs should be signed by the session key of the participant. The outputs should be
+    /// distinct, differ from the outputs of the previous participant, and have the length of
+    /// the address encoded by the keys of the next participants.
     ///
     /// If participant is the last one in the room, then return [`PassDecodedOutputsResult::Finished`].
     /// Otherwise, return [`PassDecodedOutputsResult::Round`] with position of the next participant in
     /// the room.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(participant_id = %participant_id, room_id, round))
+    )]
     pub async fn pass_decoded_outputs(
         &self,
         participant_id: &U256,
         decoded_outputs: Vec<EncodedOutput>,
-    ) -> ServiceResult<PassDecodedOutputsResult> {
-        let participant = self.participant_by_id(participant_id).await?;
+        session_signature: EcdsaSignature,
+    ) -> ServiceResult<Submission<PassDecodedOutputsResult>> {
+        let (_lock, participant) = self.lock_participant(participant_id).await?;
         let room = self.room_by_id(&participant.room_id).await?;
 
+        let message = decoded_outputs_message(&room.id, *participant_id, &decoded_outputs);
+        Self::verify_session_signature(&participant, message, &session_signature)?;
+
+        if !matches!(participant.state, ParticipantState::Start(_)) {
+            return self
+                .repeated_decoded_outputs(&room, participant_id, decoded_outputs)
+                .await;
+        }
+        self.check_deadline(
+            &room,
+            self.config.shuffle_timeout,
+            PolicyViolation::ShuffleDeadline,
+        )?;
+
         let position = Self::participant_position(&room, participant_id)?;
         let RoomState::Shuffle(current_round) = room.state else {
             return Err(Error::InvalidRound);
         };
+        telemetry::record_round(current_round);
         if position != current_round {
             return Err(Error::InvalidRound);
         }
         if decoded_outputs.len() != (position + 1) {
             return Err(Error::InvalidNumberOfOutputs);
         }
+        self.check_decoded_outputs(&room, participant_id, position, &decoded_outputs)
+            .await?;
 
-        // check that previous status is Start
-        let ParticipantState::Start(_) = participant.state else {
-            return Err(Error::InvalidStatus);
+        // If participant is the last one in the room, then his outputs are output addresses.
+        // They are built before the event is recorded, so the failed pass isn't recorded.
+        let outputs = if position == room.participants.len() - 1 {
+            Some(self.room_outputs(&room, &decoded_outputs).await?)
+        } else {
+            None
         };
 
-        // If participant is the last one in the room, then his outputs are output addresses
-        let outputs = if position == room.participants.len() - 1 {
-            let outputs = decoded_outputs
-                .clone()
-                .into_iter()
-                .map(|o| Output {
-                    amount: room.amount,
-                    owner: Address::from_slice(&o),
-                })
-                .collect::<Vec<Output>>();
+        self.record(
+            &room.id,
+            Event::DecodedOutputs {
+                utxo_id: *participant_id,
+                decoded_outputs: decoded_outputs.clone(),
+                session_signature,
+            },
+        )
+        .await?;
+
+        let outputs = if let Some(outputs) = outputs {
             self.update_room_state(
                 &room.id,
                 RoomState::Signatures((outputs.clone(), Vec::new())),
             )
-            .await;
+            .await?;
             PassDecodedOutputsResult::Finished(outputs)
         } else {
             let current_round = current_round + 1;
             self.update_room_state(&room.id, RoomState::Shuffle(current_round))
-                .await;
+                .await?;
             PassDecodedOutputsResult::Round(current_round)
         };
 
@@ -251,50 +1026,220 @@ impl Service {
             participant_id,
             ParticipantState::DecodedOutputs(decoded_outputs),
         )
-        .await;
+        .await?;
 
-        Ok(outputs)
+        Ok(Submission::Applied(outputs))
     }
 
-    async fn update_participant_state(&self, participant_id: &U256, state: ParticipantState) {
-        self.storage
+    /// Check the outputs decoded by the participant at the `position` against the outputs of
+    /// the previous participant and the keys of the next ones, see
+    /// [`validate_decoded_outputs`].
+    async fn check_decoded_outputs(
+        &self,
+        room: &Room,
+        participant_id: &U256,
+        position: usize,
+        decoded_outputs: &[EncodedOutput],
+    ) -> ServiceResult<()> {
+        let previous_outputs = self.round_outputs(room, position).await?;
+
+        // Next participants haven't passed their round yet, so they keep their keys
+        let next_participants = &room.participants[position + 1..];
+        let key_sizes = self
+            .storage
             .participants()
-            .update_state(*participant_id, state)
-            .await;
-    }
+            .get_many(next_participants)
+            .await
+            .map_err(Error::storage)?
+            .into_iter()
+            .map(|participant| {
+                let ParticipantState::Start(key) = participant.state else {
+                    return Err(Error::InvalidStatus);
+                };
+                Ok((participant.utxo_id, key.size()))
+            })
+            .collect::<ServiceResult<HashMap<U256, usize>>>()?;
 
-    /// Return outputs that given room should sign.
-    pub async fn outputs_to_sign(&self, room_id: &uuid::Uuid) -> ServiceResult<Vec<Output>> {
-        let room = self.room_by_id(room_id).await?;
-        let RoomState::Signatures((outputs, _)) = room.state else {
-            return Err(Error::InvalidStatus);
-        };
-        Ok(outputs)
+        // Outputs are encoded by the key of the last participant first
+        let key_sizes = next_participants
+            .iter()
+            .rev()
+            .map(|utxo_id| {
+                key_sizes
+                    .get(utxo_id)
+                    .copied()
+                    .ok_or(Error::ParticipantNotFound)
+            })
+            .collect::<ServiceResult<Vec<usize>>>()?;
+
+        validate_decoded_outputs(decoded_outputs, &previous_outputs, key_sizes).map_err(|err| {
+            match err {
+                InvalidDecodedOutputs::Duplicate => Error::DuplicateOutputs(*participant_id),
+                InvalidDecodedOutputs::Forwarded => Error::ForwardedOutputs(*participant_id),
+                InvalidDecodedOutputs::Length(len) => {
+                    Error::InvalidOutputLength(*participant_id, len)
+                }
+                InvalidDecodedOutputs::Keys(err) => Error::Rsa(err),
+            }
+        })
     }
 
-    /// Pass signature of the output and store it in the storage.
+    /// Handle the decoded outputs of the participant, that has passed them already. If they
+    /// are identical to the applied ones, return the result of the original pass.
+    async fn repeated_decoded_outputs(
+        &self,
+        room: &Room,
+        participant_id: &U256,
+        decoded_outputs: Vec<EncodedOutput>,
+    ) -> ServiceResult<Submission<PassDecodedOutputsResult>> {
+        let passed = self
+            .events(&room.id)
+            .await?
+            .into_iter()
+            .find_map(|event| match event {
+                Event::DecodedOutputs {
+                    utxo_id,
+                    decoded_outputs,
+                    ..
+                } if utxo_id == *participant_id => Some(decoded_outputs),
+                _ => None,
+            })
+            .ok_or(Error::InvalidStatus)?;
+        if passed != decoded_outputs {
+            return Err(Error::ConflictingResubmission);
+        }
+
+        let position = Self::participant_position(room, participant_id)?;
+        let result = if position == room.participants.len() - 1 {
+            PassDecodedOutputsResult::Finished(self.room_outputs(room, &decoded_outputs).await?)
+        } else {
+            PassDecodedOutputsResult::Round(position + 1)
+        };
+
+        Ok(Submission::Repeated(result))
+    }
+
+    /// Return outputs of the room transaction made of the outputs decoded by its last
+    /// participant, the change outputs of the participants in the shuffle order and the fee
+    /// output, see [`room_outputs`].
+    async fn room_outputs(
+        &self,
+        room: &Room,
+        decoded_outputs: &[EncodedOutput],
+    ) -> ServiceResult<Vec<Output>> {
+        let mut changes: HashMap<U256, Output> = self
+            .storage
+            .participants()
+            .get_many(&room.participants)
+            .await
+            .map_err(Error::storage)?
+            .into_iter()
+            .filter_map(|participant| Some((participant.utxo_id, participant.change?)))
+            .collect();
+        let changes = room
+            .participants
+            .iter()
+            .filter_map(|utxo_id| changes.remove(utxo_id));
+
+        room_outputs(room.amount, room.fee.as_ref(), decoded_outputs, changes)
+            .ok_or(Error::FeeOverflow)
+    }
+
+    /// Check that the message is signed by the session key of the connected participant.
+    fn verify_session_signature(
+        participant: &Participant,
+        message: [u8; 32],
+        signature: &EcdsaSignature,
+    ) -> ServiceResult<()> {
+        let session_key = participant.session_key.ok_or(Error::InvalidStatus)?;
+
+        signature
+            .verify(message.to_vec(), session_key)
+            .map_err(|_| Error::InvalidSessionSignature)
+    }
+
+    async fn update_participant_state(
+        &self,
+        participant_id: &U256,
+        state: ParticipantState,
+    ) -> ServiceResult<()> {
+        self.storage
+            .participants()
+            .update_state(*participant_id, state)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Return outputs that given room should sign.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(room_id = %room_id))
+    )]
+    pub async fn outputs_to_sign(&self, room_id: &uuid::Uuid) -> ServiceResult<Vec<Output>> {
+        let room = self.room_by_id(room_id).await?;
+        let RoomState::Signatures((outputs, _)) = room.state else {
+            return Err(Error::InvalidStatus);
+        };
+        Ok(outputs)
+    }
+
+    /// Pass signature of the output and store it in the storage. The message of the
+    /// signature should be signed by the session key of the participant.
     ///
     /// If all participants passed their signatures, return all inputs and outputs.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(room_id = %room_id, participant_id = %participant_id))
+    )]
     pub async fn pass_signature(
         &self,
         room_id: &uuid::Uuid,
         participant_id: &U256,
         signature: Signature,
-    ) -> ServiceResult<Option<(Vec<Output>, Vec<Input>)>> {
+        session_signature: EcdsaSignature,
+    ) -> ServiceResult<Submission<Option<RoomTransfer>>> {
+        let _lock = self.lock_room(room_id).await?;
         let room = self.room_by_id(room_id).await?;
         let _position = Self::participant_position(&room, participant_id)?;
 
+        // TODO: validate signature here
+
+        let participant = self.participant_by_id(participant_id).await?;
+        let message = outputs_signature_message(&room.id, *participant_id, signature.as_bytes());
+        Self::verify_session_signature(&participant, message, &session_signature)?;
+
+        if matches!(
+            participant.state,
+            ParticipantState::SigningOutput(_) | ParticipantState::Finish
+        ) {
+            return self
+                .repeated_signature(&room, participant_id, signature)
+                .await;
+        }
+        self.check_deadline(
+            &room,
+            self.config.shuffle_timeout,
+            PolicyViolation::ShuffleDeadline,
+        )?;
+
         let RoomState::Signatures((outputs, mut passed)) = room.state else {
             return Err(Error::InvalidStatus);
         };
 
-        // TODO: validate signature here
-
-        let participant = self.participant_by_id(participant_id).await?;
         // check that previous status is Start
         let ParticipantState::DecodedOutputs(_) = participant.state else {
             return Err(Error::InvalidStatus);
         };
+
+        self.record(
+            &room.id,
+            Event::SignaturePassed {
+                utxo_id: *participant_id,
+                signature,
+                session_signature,
+            },
+        )
+        .await?;
         passed.push(*participant_id);
 
         let input = Input {
@@ -303,15 +1248,15 @@ impl Service {
         };
 
         self.update_participant_state(participant_id, ParticipantState::SigningOutput(input))
-            .await;
+            .await?;
 
         let participants_passed = passed.len();
 
         self.update_room_state(&room.id, RoomState::Signatures((outputs.clone(), passed)))
-            .await;
+            .await?;
 
         if participants_passed != room.participants.len() {
-            return Ok(None);
+            return Ok(Submission::Applied(None));
         }
 
         let mut inputs = Vec::new();
@@ -324,29 +1269,1532 @@ impl Service {
             inputs.push(input);
         }
 
-        Ok(Some((outputs, inputs)))
+        Ok(Submission::Applied(Some((outputs, inputs))))
+    }
+
+    /// Handle the signature of the participant, that has passed it already. If it is
+    /// identical to the applied one, return the result of the original pass, the inputs and
+    /// outputs are returned if it was the last signature of the room.
+    async fn repeated_signature(
+        &self,
+        room: &Room,
+        participant_id: &U256,
+        signature: Signature,
+    ) -> ServiceResult<Submission<Option<RoomTransfer>>> {
+        let events = self.events(&room.id).await?;
+
+        let signatures = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::SignaturePassed {
+                    utxo_id, signature, ..
+                } => Some((*utxo_id, *signature)),
+                _ => None,
+            })
+            .collect::<HashMap<U256, Signature>>();
+        let passed = signatures.get(participant_id).ok_or(Error::InvalidStatus)?;
+        if *passed != signature {
+            return Err(Error::ConflictingResubmission);
+        }
+
+        let last = events.iter().rev().find_map(|event| match event {
+            Event::SignaturePassed { utxo_id, .. } => Some(utxo_id),
+            _ => None,
+        });
+        if signatures.len() != room.participants.len() || last != Some(participant_id) {
+            return Ok(Submission::Repeated(None));
+        }
+
+        let outputs = events
+            .iter()
+            .find_map(|event| match event {
+                Event::State(RoomState::Signatures((outputs, _))) => Some(outputs.clone()),
+                _ => None,
+            })
+            .ok_or(Error::InvalidStatus)?;
+        let inputs = room
+            .participants
+            .iter()
+            .map(|utxo_id| {
+                let signature = signatures.get(utxo_id).ok_or(Error::InvalidStatus)?;
+
+                Ok(Input {
+                    id: *utxo_id,
+                    signature: Bytes::from(signature.as_bytes().to_vec()),
+                })
+            })
+            .collect::<ServiceResult<Vec<Input>>>()?;
+
+        Ok(Submission::Repeated(Some((outputs, inputs))))
+    }
+
+    /// Save hash of the transaction made of the room outputs and inputs, which are signed by
+    /// all the participants, and finish the participants. Saving the same hash again has no
+    /// effect.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(room_id = %room_id, tx_hash = ?tx_hash))
+    )]
+    pub async fn set_transaction_hash(
+        &self,
+        room_id: &uuid::Uuid,
+        tx_hash: Hash,
+    ) -> ServiceResult<()> {
+        let _lock = self.lock_room(room_id).await?;
+        let room = self.room_by_id(room_id).await?;
+
+        let passed = match room.state {
+            RoomState::TransactionHash(saved) if saved == tx_hash => return Ok(()),
+            RoomState::TransactionHash(_) => return Err(Error::ConflictingResubmission),
+            RoomState::Signatures((_, passed)) => passed,
+            _ => return Err(Error::InvalidStatus),
+        };
+        if passed.len() != room.participants.len() {
+            return Err(Error::InvalidStatus);
+        }
+
+        self.update_room_state(room_id, RoomState::TransactionHash(tx_hash))
+            .await?;
+        telemetry::room_completed();
+
+        for participant_id in room.participants.iter() {
+            self.update_participant_state(participant_id, ParticipantState::Finish)
+                .await?;
+        }
+
+        Ok(())
     }
 
     /// Get participant by id.
-    pub async fn get_participant(&self, participant_id: &U256) -> Option<Participant> {
-        self.storage.participants().get(*participant_id).await
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(participant_id = %participant_id))
+    )]
+    pub async fn get_participant(
+        &self,
+        participant_id: &U256,
+    ) -> ServiceResult<Option<Participant>> {
+        self.storage
+            .participants()
+            .get(*participant_id)
+            .await
+            .map_err(Error::storage)
     }
 
     /// Get room by id.
-    pub async fn get_room(&self, room_id: &uuid::Uuid) -> Option<Room> {
-        self.storage.rooms().get(*room_id).await
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(room_id = %room_id))
+    )]
+    pub async fn get_room(&self, room_id: &uuid::Uuid) -> ServiceResult<Option<Room>> {
+        self.storage
+            .rooms()
+            .get(*room_id)
+            .await
+            .map_err(Error::storage)
+    }
+
+    /// Get transcript of the room, it is kept after the room is cleared.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(room_id = %room_id))
+    )]
+    pub async fn transcript(&self, room_id: &uuid::Uuid) -> ServiceResult<Option<Transcript>> {
+        let entries = self
+            .storage
+            .transcripts()
+            .get(*room_id)
+            .await
+            .map_err(Error::storage)?;
+
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Transcript {
+            room_id: *room_id,
+            entries,
+        }))
+    }
+
+    /// Return events of the room transcript, which are the record of the applied calls.
+    async fn events(&self, room_id: &uuid::Uuid) -> ServiceResult<Vec<Event>> {
+        let entries = self
+            .storage
+            .transcripts()
+            .get(*room_id)
+            .await
+            .map_err(Error::storage)?;
+
+        Ok(entries.into_iter().map(|entry| entry.event).collect())
+    }
+
+    /// Append the event to the room transcript.
+    async fn record(&self, room_id: &uuid::Uuid, event: Event) -> ServiceResult<()> {
+        self.storage
+            .transcripts()
+            .append(*room_id, event, self.clock.now())
+            .await
+            .map_err(Error::storage)?;
+
+        Ok(())
     }
 
     /// Clear room and participants from the storage.
-    pub async fn clear_room(&self, room_id: &uuid::Uuid) {
-        self.storage.clear_room(room_id).await;
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, fields(room_id = %room_id)))]
+    pub async fn clear_room(&self, room_id: &uuid::Uuid) -> ServiceResult<()> {
+        let _lock = self.lock_room(room_id).await?;
+
+        self.storage
+            .clear_room(room_id)
+            .await
+            .map_err(Error::storage)?;
+
+        Ok(())
+    }
+
+    /// Remove the rooms, that exceeded the [`RoomTtl`] of their state, with their
+    /// participants, so the UTXOs of the unfinished rooms can be placed in another room.
+    /// Transcripts of the removed rooms, that exceeded the transcript retention window, are
+    /// removed as well.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all))]
+    pub async fn reap(&self) -> ServiceResult<Reaped> {
+        let mut reaped = Reaped::default();
+        let now = self.clock.now();
+
+        let rooms = self.storage.rooms().list().await.map_err(Error::storage)?;
+        for room in rooms {
+            let _lock = self.lock_room(&room.id).await?;
+            // Room may be changed or cleared since it is listed
+            let Some(room) = self.get_room(&room.id).await? else {
+                continue;
+            };
+            let Some(ttl) = self.config.room_ttl.of(&room.state) else {
+                continue;
+            };
+            if now
+                .duration_since(self.last_event_time(&room).await?)
+                .unwrap_or_default()
+                <= ttl
+            {
+                continue;
+            }
+
+            if matches!(room.state, RoomState::TransactionHash(_)) {
+                reaped.finished.push(room.id);
+            } else {
+                self.record(&room.id, Event::Expired).await?;
+                telemetry::room_failed("expired");
+                reaped.expired.push(room.id);
+            }
+
+            self.storage
+                .clear_room(&room.id)
+                .await
+                .map_err(Error::storage)?;
+        }
+
+        let Some(retention) = self.config.transcript_retention else {
+            return Ok(reaped);
+        };
+        let transcripts = self
+            .storage
+            .transcripts()
+            .list()
+            .await
+            .map_err(Error::storage)?;
+        for room_id in transcripts {
+            let _lock = self.lock_room(&room_id).await?;
+            if self.get_room(&room_id).await?.is_some() {
+                continue;
+            }
+
+            let entries = self
+                .storage
+                .transcripts()
+                .get(room_id)
+                .await
+                .map_err(Error::storage)?;
+            let expired = entries.last().is_some_and(|entry| {
+                now.duration_since(entry.recorded_at).unwrap_or_default() > retention
+            });
+            if expired {
+                self.storage
+                    .transcripts()
+                    .delete(room_id)
+                    .await
+                    .map_err(Error::storage)?;
+                reaped.transcripts.push(room_id);
+            }
+        }
+
+        Ok(reaped)
+    }
+
+    /// Reap the rooms every `period`, see [`Service::reap`]. The future runs until the
+    /// storage fails, so it is expected to be spawned on a clone of the service.
+    pub async fn run_reaper(&self, period: Duration) -> ServiceResult<()> {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let reaped = self.reap().await?;
+            if reaped != Reaped::default() {
+                log::debug!("reaped {reaped:?}");
+            }
+        }
+    }
+
+    /// Return time of the last event of the room.
+    async fn last_event_time(&self, room: &Room) -> ServiceResult<SystemTime> {
+        let entries = self
+            .storage
+            .transcripts()
+            .get(room.id)
+            .await
+            .map_err(Error::storage)?;
+
+        Ok(entries
+            .last()
+            .map_or(room.created_at, |entry| entry.recorded_at))
+    }
+}
+
+/// Outputs and inputs of the room transfer, that are returned by the last signature passed
+/// to [`Service::pass_signature`].
+pub type RoomTransfer = (Vec<Output>, Vec<Input>);
+
+/// Rooms and transcripts removed by [`Service::reap`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reaped {
+    /// Rooms, that weren't finished in the time-to-live of their state.
+    pub expired: Vec<uuid::Uuid>,
+    /// Finished rooms, that are kept for the time-to-live of the finished state.
+    pub finished: Vec<uuid::Uuid>,
+    /// Removed rooms, whose transcripts exceeded the retention window.
+    pub transcripts: Vec<uuid::Uuid>,
+}
+
+/// Result of the call, that changes the room. The call, that is identical to the applied
+/// one, isn't applied again and returns the result of the original call, while the call,
+/// that differs from it, fails with [`Error::ConflictingResubmission`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Submission<T> {
+    /// Call is applied.
+    Applied(T),
+    /// Call repeats the applied one.
+    Repeated(T),
+}
+
+impl<T> Submission<T> {
+    pub fn is_repeated(&self) -> bool {
+        matches!(self, Self::Repeated(_))
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Applied(result) | Self::Repeated(result) => result,
+        }
     }
 }
 
+/// Result of the participant removal from the room.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Removal {
+    /// Room is left with the rest of the participants.
+    Resized,
+    /// Rest of the participants are connected, so the reveal is started with the
+    /// commitments, as it is by [`Service::connect_participant`].
+    Started(Commitments),
+    /// Room has less participants than the minimum, so it is cleared with the rest of the
+    /// participants.
+    Dissolved(Vec<U256>),
+}
+
+/// Shuffle started by the last reveal of the entropy, see [`Service::reveal_entropy`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShuffleStart {
+    /// Participants in the shuffle order derived from the reveals.
+    pub participants: Vec<U256>,
+    pub reveals: Reveals,
+    /// Keys each participant encrypts its output with.
+    pub keys: HashMap<U256, Vec<RsaPublicKey>>,
+}
+
 /// Result of the `pass_decoded_outputs` method.
+#[derive(Debug, Clone, PartialEq)]
 pub enum PassDecodedOutputsResult {
     /// All participants decoded their outputs, so the next step is to sign them.
     Finished(Vec<Output>),
     /// Not all participants decoded their outputs, so the next step is to shuffle outputs.
     Round(usize),
 }
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::abi::ethereum_types::H520;
+    use ethers_core::types::{Address, H256, U256};
+    use ethers_signers::{LocalWallet, Signer};
+
+    use std::time::Duration;
+
+    use super::{
+        error::Error, ConfigError, PassDecodedOutputsResult, PolicyViolation, Reaped, Removal,
+        RoomTtl, Service, ServiceConfig, ServiceResult, ShuffleStart, Submission,
+    };
+    use crate::auth::{
+        challenge_message, decoded_outputs_message, leave_message, outputs_signature_message,
+        reveal_message,
+    };
+    use crate::clock::ManualClock;
+    use crate::mock::MockContract;
+    use crate::ordering::{self, Commitments, Reveals};
+    use crate::rsa::{encoded_len, generate_private_key, RsaPublicKey, MIN_KEY_SIZE};
+    use crate::service::transcript::{Entry, Event, Transcript, Violation};
+    use crate::service::types::{Room, RoomState};
+    use crate::types::{Fee, ShuffleStatus};
+
+    const PARTICIPANTS: usize = 8;
+
+    #[derive(Clone)]
+    struct Member {
+        utxo_id: U256,
+        owner: LocalWallet,
+        session: LocalWallet,
+        entropy: H256,
+        /// Address of the change the member connects with.
+        change: Option<Address>,
+    }
+
+    /// Deposit the UTXOs of the new members to the contract.
+    fn members(contract: &MockContract, number: usize) -> Vec<Member> {
+        (0..number)
+            .map(|_| {
+                let owner = LocalWallet::new(&mut rand::thread_rng());
+                let utxo_id = contract.deposit(Address::zero(), owner.address(), U256::from(100));
+                Member {
+                    utxo_id,
+                    owner,
+                    session: LocalWallet::new(&mut rand::thread_rng()),
+                    entropy: H256::random(),
+                    change: None,
+                }
+            })
+            .collect()
+    }
+
+    fn rsa_pubkey() -> RsaPublicKey {
+        RsaPublicKey::from(&generate_private_key(MIN_KEY_SIZE).expect("failed to generate a key"))
+    }
+
+    /// Return random outputs of the participant in the `round`, that have the length of the
+    /// address encoded by the [`rsa_pubkey`] of the next participants.
+    fn decoded_outputs(round: usize, participants: usize) -> Vec<Vec<u8>> {
+        let len = encoded_len(
+            Address::len_bytes(),
+            vec![MIN_KEY_SIZE / 8; participants - round - 1],
+        )
+        .expect("key size is supported");
+
+        (0..=round)
+            .map(|_| (0..len).map(|_| rand::random()).collect())
+            .collect()
+    }
+
+    async fn create_room(service: &Service, members: &[Member]) -> ServiceResult<Room> {
+        service
+            .create_room(
+                Address::zero(),
+                U256::from(100),
+                members.iter().map(|member| member.utxo_id).collect(),
+            )
+            .await
+    }
+
+    /// Challenge the member and connect it with the signed challenge.
+    async fn connect(
+        service: Service,
+        contract: MockContract,
+        member: Member,
+        rsa_pubkey: RsaPublicKey,
+    ) -> ServiceResult<Submission<Option<Commitments>>> {
+        let (utxo_id, session_key) = (member.utxo_id, member.session.address());
+        let (room_id, nonce) = service.challenge(&utxo_id).await?;
+        let commitment = ordering::commitment(&room_id, utxo_id, member.entropy);
+        let message = challenge_message(
+            &room_id,
+            utxo_id,
+            nonce,
+            &rsa_pubkey,
+            session_key,
+            commitment,
+            member.change,
+        );
+        let signature = member
+            .owner
+            .sign_message(message)
+            .await
+            .expect("failed to sign");
+
+        service
+            .connect_participant(
+                &contract,
+                &utxo_id,
+                rsa_pubkey,
+                session_key,
+                commitment,
+                member.change,
+                signature,
+            )
+            .await
+    }
+
+    /// Reveal the given entropy of the connected member.
+    async fn reveal(
+        service: Service,
+        room_id: uuid::Uuid,
+        member: Member,
+        entropy: H256,
+    ) -> ServiceResult<Submission<Option<ShuffleStart>>> {
+        let session_signature = member
+            .session
+            .sign_message(reveal_message(&room_id, member.utxo_id, entropy))
+            .await
+            .expect("failed to sign");
+
+        service
+            .reveal_entropy(&member.utxo_id, entropy, session_signature)
+            .await
+    }
+
+    /// Pass the decoded outputs of the member signed by its session key.
+    async fn pass(
+        service: &Service,
+        room_id: uuid::Uuid,
+        member: &Member,
+        decoded_outputs: Vec<Vec<u8>>,
+    ) -> ServiceResult<Submission<PassDecodedOutputsResult>> {
+        let session_signature = member
+            .session
+            .sign_message(decoded_outputs_message(
+                &room_id,
+                member.utxo_id,
+                &decoded_outputs,
+            ))
+            .await
+            .expect("failed to sign");
+
+        service
+            .pass_decoded_outputs(&member.utxo_id, decoded_outputs, session_signature)
+            .await
+    }
+
+    /// Change the events of the transcript by `tamper` and chain its entries again, so the
+    /// change is found only by the replay of the events.
+    fn rechain(transcript: Transcript, mut tamper: impl FnMut(&mut Event)) -> Transcript {
+        let mut entries: Vec<Entry> = Vec::new();
+        for mut entry in transcript.entries {
+            tamper(&mut entry.event);
+            entries.push(Entry::recorded_at(
+                entries.last(),
+                entry.event,
+                entry.recorded_at,
+            ));
+        }
+
+        Transcript {
+            room_id: transcript.room_id,
+            entries,
+        }
+    }
+
+    /// Create the room, where the participants connect and reveal their entropy
+    /// concurrently, and return the service with the room members in the shuffle order.
+    async fn connected_room() -> (Service, uuid::Uuid, Vec<Member>) {
+        let contract = MockContract::new();
+        let members = members(&contract, PARTICIPANTS);
+        let rsa_pubkey = rsa_pubkey();
+
+        let service = Service::new();
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+
+        let handles = members
+            .iter()
+            .map(|member| {
+                tokio::spawn(connect(
+                    service.clone(),
+                    contract.clone(),
+                    member.clone(),
+                    rsa_pubkey.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let mut started = 0;
+        for handle in handles {
+            let commitments = handle
+                .await
+                .expect("connecting task panicked")
+                .expect("failed to connect");
+            started += usize::from(commitments.into_inner().is_some());
+        }
+        assert_eq!(
+            started, 1,
+            "reveal should be started by the last connect only"
+        );
+
+        let handles = members
+            .iter()
+            .map(|member| {
+                tokio::spawn(reveal(
+                    service.clone(),
+                    room.id,
+                    member.clone(),
+                    member.entropy,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let mut started = Vec::new();
+        for handle in handles {
+            let start = handle
+                .await
+                .expect("revealing task panicked")
+                .expect("failed to reveal");
+            started.extend(start.into_inner());
+        }
+        let [start] = started.as_slice() else {
+            panic!("shuffle should be started by the last reveal only");
+        };
+
+        let members = start
+            .participants
+            .iter()
+            .map(|utxo_id| {
+                members
+                    .iter()
+                    .find(|member| member.utxo_id == *utxo_id)
+                    .cloned()
+                    .expect("participant isn't a member")
+            })
+            .collect();
+
+        (service, room.id, members)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_connects_start_shuffle_once() {
+        let (service, room_id, _) = connected_room().await;
+
+        let room = service
+            .get_room(&room_id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        assert_eq!(room.state, RoomState::Shuffle(0));
+    }
+
+    #[tokio::test]
+    async fn shuffle_order_is_derived_from_reveals() {
+        let contract = MockContract::new();
+        let members = members(&contract, 3);
+        let rsa_pubkey = rsa_pubkey();
+        let service = Service::new();
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+
+        let mut commitments = None;
+        for member in members.iter() {
+            commitments = connect(
+                service.clone(),
+                contract.clone(),
+                member.clone(),
+                rsa_pubkey.clone(),
+            )
+            .await
+            .expect("failed to connect")
+            .into_inner();
+        }
+        let commitments = commitments.expect("reveal isn't started");
+
+        // Entropy can't be changed after the commitment
+        let changed = reveal(service.clone(), room.id, members[0].clone(), H256::random()).await;
+        assert!(matches!(changed, Err(Error::InvalidReveal)));
+
+        let revealed = reveal(
+            service.clone(),
+            room.id,
+            members[0].clone(),
+            members[0].entropy,
+        )
+        .await
+        .expect("failed to reveal");
+        assert_eq!(revealed, Submission::Applied(None));
+        let repeated = reveal(
+            service.clone(),
+            room.id,
+            members[0].clone(),
+            members[0].entropy,
+        )
+        .await
+        .expect("failed to repeat reveal");
+        assert_eq!(repeated, Submission::Repeated(None));
+
+        let mut start = None;
+        for member in members[1..].iter() {
+            start = reveal(service.clone(), room.id, member.clone(), member.entropy)
+                .await
+                .expect("failed to reveal")
+                .into_inner();
+        }
+        let start = start.expect("shuffle isn't started");
+
+        ordering::verify_order(&room.id, &commitments, &start.reveals, &start.participants)
+            .expect("order isn't derived from the reveals");
+        let room = service
+            .get_room(&room.id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        assert_eq!(room.participants, start.participants);
+        assert_eq!(room.state, RoomState::Shuffle(0));
+
+        service
+            .transcript(&room.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent")
+            .verify()
+            .expect("transcript of the ordered room is invalid");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_signatures_are_applied_once() {
+        let (service, room_id, members) = connected_room().await;
+
+        for (round, member) in members.iter().enumerate() {
+            let decoded_outputs = decoded_outputs(round, PARTICIPANTS);
+            let session_signature = member
+                .session
+                .sign_message(decoded_outputs_message(
+                    &room_id,
+                    member.utxo_id,
+                    &decoded_outputs,
+                ))
+                .await
+                .expect("failed to sign");
+
+            let result = service
+                .pass_decoded_outputs(&member.utxo_id, decoded_outputs, session_signature)
+                .await
+                .expect("failed to pass decoded outputs");
+            if round + 1 == PARTICIPANTS {
+                assert!(matches!(
+                    result,
+                    Submission::Applied(PassDecodedOutputsResult::Finished(_))
+                ));
+            }
+        }
+
+        // Every participant passes its signature twice at the same time
+        let mut handles = Vec::new();
+        for member in members.iter() {
+            let signature = H520::random();
+            let session_signature = member
+                .session
+                .sign_message(outputs_signature_message(
+                    &room_id,
+                    member.utxo_id,
+                    signature.as_bytes(),
+                ))
+                .await
+                .expect("failed to sign");
+
+            for _ in 0..2 {
+                let (service, utxo_id) = (service.clone(), member.utxo_id);
+                handles.push(tokio::spawn(async move {
+                    service
+                        .pass_signature(&room_id, &utxo_id, signature, session_signature)
+                        .await
+                }));
+            }
+        }
+
+        let (mut accepted, mut finished) = (0, 0);
+        for handle in handles {
+            let submission = handle
+                .await
+                .expect("signing task panicked")
+                .expect("failed to pass signature");
+            if let Submission::Applied(result) = submission {
+                accepted += 1;
+                finished += usize::from(result.is_some());
+            }
+        }
+        assert_eq!(
+            accepted, PARTICIPANTS,
+            "every signature should be applied once"
+        );
+        assert_eq!(
+            finished, 1,
+            "transfer should be returned by the last signature only"
+        );
+
+        let room = service
+            .get_room(&room_id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        let RoomState::Signatures((_, passed)) = room.state else {
+            panic!("unexpected room state {:?}", room.state);
+        };
+        assert_eq!(passed.len(), PARTICIPANTS);
+
+        service
+            .transcript(&room_id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent")
+            .verify()
+            .expect("transcript of the concurrent room is invalid");
+    }
+
+    #[tokio::test]
+    async fn resubmissions_return_original_result() {
+        let (service, room_id, members) = connected_room().await;
+        let member = &members[0];
+
+        let pass = |decoded_outputs: Vec<Vec<u8>>| async {
+            let session_signature = member
+                .session
+                .sign_message(decoded_outputs_message(
+                    &room_id,
+                    member.utxo_id,
+                    &decoded_outputs,
+                ))
+                .await
+                .expect("failed to sign");
+
+            service
+                .pass_decoded_outputs(&member.utxo_id, decoded_outputs, session_signature)
+                .await
+        };
+
+        let decoded_outputs = decoded_outputs(0, PARTICIPANTS);
+        let applied = pass(decoded_outputs.clone())
+            .await
+            .expect("failed to pass decoded outputs");
+        assert_eq!(
+            applied,
+            Submission::Applied(PassDecodedOutputsResult::Round(1))
+        );
+
+        let repeated = pass(decoded_outputs)
+            .await
+            .expect("failed to repeat decoded outputs");
+        assert_eq!(
+            repeated,
+            Submission::Repeated(PassDecodedOutputsResult::Round(1))
+        );
+
+        let conflicting = pass(vec![Address::random().as_bytes().to_vec()]).await;
+        assert!(matches!(conflicting, Err(Error::ConflictingResubmission)));
+
+        let room = service
+            .get_room(&room_id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        assert_eq!(room.state, RoomState::Shuffle(1));
+    }
+
+    #[tokio::test]
+    async fn invalid_decoded_outputs_are_rejected() {
+        let (service, room_id, members) = connected_room().await;
+
+        let pass = |member: &Member, decoded_outputs: Vec<Vec<u8>>| {
+            let service = service.clone();
+            let member = member.clone();
+            async move {
+                let session_signature = member
+                    .session
+                    .sign_message(decoded_outputs_message(
+                        &room_id,
+                        member.utxo_id,
+                        &decoded_outputs,
+                    ))
+                    .await
+                    .expect("failed to sign");
+
+                service
+                    .pass_decoded_outputs(&member.utxo_id, decoded_outputs, session_signature)
+                    .await
+            }
+        };
+
+        let first_outputs = decoded_outputs(0, PARTICIPANTS);
+        pass(&members[0], first_outputs.clone())
+            .await
+            .expect("failed to pass decoded outputs");
+
+        let second = &members[1];
+        let outputs = decoded_outputs(1, PARTICIPANTS);
+        let expected_len = outputs[0].len();
+
+        let duplicate = pass(second, vec![outputs[0].clone(); 2]).await;
+        assert!(matches!(duplicate, Err(Error::DuplicateOutputs(id)) if id == second.utxo_id));
+
+        let forwarded = pass(second, vec![first_outputs[0].clone(), outputs[1].clone()]).await;
+        assert!(matches!(forwarded, Err(Error::ForwardedOutputs(id)) if id == second.utxo_id));
+
+        let short = pass(
+            second,
+            (0..2)
+                .map(|_| Address::random().as_bytes().to_vec())
+                .collect(),
+        )
+        .await;
+        assert!(matches!(
+            short,
+            Err(Error::InvalidOutputLength(id, len)) if id == second.utxo_id && len == expected_len
+        ));
+
+        let applied = pass(second, outputs)
+            .await
+            .expect("failed to pass decoded outputs");
+        assert_eq!(
+            applied,
+            Submission::Applied(PassDecodedOutputsResult::Round(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn submissions_signed_by_another_key_are_rejected() {
+        let contract = MockContract::new();
+        let members = members(&contract, 3);
+        let rsa_pubkey = rsa_pubkey();
+        let service = Service::new();
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+        for member in members.iter() {
+            connect(
+                service.clone(),
+                contract.clone(),
+                member.clone(),
+                rsa_pubkey.clone(),
+            )
+            .await
+            .expect("failed to connect");
+        }
+
+        let impostor = |member: &Member| Member {
+            session: LocalWallet::new(&mut rand::thread_rng()),
+            ..member.clone()
+        };
+
+        let forged = reveal(
+            service.clone(),
+            room.id,
+            impostor(&members[0]),
+            members[0].entropy,
+        )
+        .await;
+        assert!(matches!(forged, Err(Error::InvalidSessionSignature)));
+
+        let mut start = None;
+        for member in members.iter() {
+            start = reveal(service.clone(), room.id, member.clone(), member.entropy)
+                .await
+                .expect("failed to reveal")
+                .into_inner();
+        }
+        let start = start.expect("shuffle isn't started");
+
+        for (round, utxo_id) in start.participants.iter().enumerate() {
+            let member = members
+                .iter()
+                .find(|member| member.utxo_id == *utxo_id)
+                .expect("participant isn't a member");
+            let outputs = decoded_outputs(round, members.len());
+
+            for (signer, is_valid) in [(impostor(member), false), (member.clone(), true)] {
+                let session_signature = signer
+                    .session
+                    .sign_message(decoded_outputs_message(&room.id, member.utxo_id, &outputs))
+                    .await
+                    .expect("failed to sign");
+                let passed = service
+                    .pass_decoded_outputs(&member.utxo_id, outputs.clone(), session_signature)
+                    .await;
+
+                if is_valid {
+                    passed.expect("failed to pass decoded outputs");
+                } else {
+                    assert!(matches!(passed, Err(Error::InvalidSessionSignature)));
+                }
+            }
+        }
+
+        let signature = H520::random();
+        let session_signature = impostor(&members[0])
+            .session
+            .sign_message(outputs_signature_message(
+                &room.id,
+                members[0].utxo_id,
+                signature.as_bytes(),
+            ))
+            .await
+            .expect("failed to sign");
+        let forged = service
+            .pass_signature(&room.id, &members[0].utxo_id, signature, session_signature)
+            .await;
+        assert!(matches!(forged, Err(Error::InvalidSessionSignature)));
+
+        let room = service
+            .get_room(&room.id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        let RoomState::Signatures((_, passed)) = room.state else {
+            panic!("unexpected room state {:?}", room.state);
+        };
+        assert!(passed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resume_returns_missed_steps() {
+        let (service, room_id, members) = connected_room().await;
+        let resume = |member: &Member| {
+            let (service, utxo_id) = (service.clone(), member.utxo_id);
+            async move { service.resume(&utxo_id).await.expect("failed to resume") }
+        };
+
+        let first = resume(&members[0]).await;
+        assert_eq!(first.room_id, room_id);
+        assert_eq!(first.status, ShuffleStatus::ShuffleStart);
+        assert_eq!(
+            first.public_keys.map(|keys| keys.len()),
+            Some(PARTICIPANTS - 1)
+        );
+        assert_eq!(first.encoded_outputs, Some(Vec::new()));
+        assert!(first.outputs_to_sign.is_none() && first.tx_hash.is_none());
+
+        // The second participant waits for the round of the first one
+        assert!(resume(&members[1]).await.encoded_outputs.is_none());
+
+        let decoded_outputs = decoded_outputs(0, PARTICIPANTS);
+        let session_signature = members[0]
+            .session
+            .sign_message(decoded_outputs_message(
+                &room_id,
+                members[0].utxo_id,
+                &decoded_outputs,
+            ))
+            .await
+            .expect("failed to sign");
+        service
+            .pass_decoded_outputs(
+                &members[0].utxo_id,
+                decoded_outputs.clone(),
+                session_signature,
+            )
+            .await
+            .expect("failed to pass decoded outputs");
+
+        let first = resume(&members[0]).await;
+        assert_eq!(first.status, ShuffleStatus::Shuffle);
+        assert!(first.encoded_outputs.is_none());
+
+        let second = resume(&members[1]).await;
+        assert_eq!(second.encoded_outputs, Some(decoded_outputs));
+    }
+
+    #[tokio::test]
+    async fn utxo_is_placed_in_one_room() {
+        let contract = MockContract::new();
+        let members = members(&contract, 3);
+        let service = Service::new();
+
+        let single = create_room(&service, &members[..1]).await;
+        assert!(matches!(
+            single,
+            Err(Error::Policy(PolicyViolation::ParticipantsNumber {
+                min: 2,
+                ..
+            }))
+        ));
+
+        let repeated = [members[0].clone(), members[0].clone()];
+        let repeated = create_room(&service, &repeated).await;
+        assert!(matches!(repeated, Err(Error::ParticipantAlreadyInRoom)));
+
+        create_room(&service, &members[..2])
+            .await
+            .expect("failed to create room");
+        let overlapping = create_room(&service, &members[1..]).await;
+        assert!(matches!(overlapping, Err(Error::ParticipantAlreadyInRoom)));
+    }
+
+    #[tokio::test]
+    async fn removal_resizes_room() {
+        let contract = MockContract::new();
+        let members = members(&contract, 4);
+        let rsa_pubkey = rsa_pubkey();
+        let service = Service::new();
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+
+        for member in members[..2].iter() {
+            connect(
+                service.clone(),
+                contract.clone(),
+                member.clone(),
+                rsa_pubkey.clone(),
+            )
+            .await
+            .expect("failed to connect");
+        }
+
+        // Leave is signed by the UTXO owner
+        let leaving = &members[3];
+        let message = leave_message(&room.id, leaving.utxo_id);
+        let forged = leaving
+            .session
+            .sign_message(message)
+            .await
+            .expect("failed to sign");
+        let result = service
+            .leave_room(&contract, &leaving.utxo_id, forged)
+            .await;
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+
+        let signature = leaving
+            .owner
+            .sign_message(message)
+            .await
+            .expect("failed to sign");
+        let removal = service
+            .leave_room(&contract, &leaving.utxo_id, signature)
+            .await
+            .expect("failed to leave");
+        assert_eq!(removal, Removal::Resized);
+
+        // Rest of the participants are connected once the last unconnected one is kicked
+        let removal = service
+            .kick_participant(&room.id, &members[2].utxo_id)
+            .await
+            .expect("failed to kick");
+        let Removal::Started(commitments) = removal else {
+            panic!("reveal isn't started: {removal:?}");
+        };
+        assert_eq!(commitments.len(), 2);
+
+        let room = service
+            .get_room(&room.id)
+            .await
+            .expect("failed to get room")
+            .expect("room is absent");
+        assert_eq!(room.state, RoomState::Revealing(Reveals::new()));
+        assert_eq!(
+            room.participants,
+            vec![members[0].utxo_id, members[1].utxo_id]
+        );
+
+        let kicked = service
+            .kick_participant(&room.id, &members[0].utxo_id)
+            .await;
+        assert!(matches!(kicked, Err(Error::InvalidStatus)));
+
+        service
+            .transcript(&room.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent")
+            .verify()
+            .expect("transcript of the resized room is invalid");
+    }
+
+    #[tokio::test]
+    async fn room_below_minimum_is_dissolved() {
+        let contract = MockContract::new();
+        let members = members(&contract, 2);
+        let service = Service::new();
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+        connect(
+            service.clone(),
+            contract.clone(),
+            members[0].clone(),
+            rsa_pubkey(),
+        )
+        .await
+        .expect("failed to connect");
+
+        let removal = service
+            .kick_participant(&room.id, &members[1].utxo_id)
+            .await
+            .expect("failed to kick");
+        assert_eq!(removal, Removal::Dissolved(vec![members[0].utxo_id]));
+
+        let stored = service
+            .get_room(&room.id)
+            .await
+            .expect("failed to get room");
+        assert!(stored.is_none(), "dissolved room is kept");
+        service
+            .transcript(&room.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent")
+            .verify()
+            .expect("transcript of the dissolved room is invalid");
+
+        // UTXOs of the dissolved room can be placed in another one
+        create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+    }
+
+    #[tokio::test]
+    async fn policy_is_enforced() {
+        let contract = MockContract::new();
+        let clock = ManualClock::default();
+        let members = members(&contract, 3);
+        let invalid = Service::new().with_config(ServiceConfig {
+            min_participants: 3,
+            max_participants: 2,
+            ..Default::default()
+        });
+        assert!(matches!(invalid, Err(ConfigError::Invalid(_))));
+
+        let service = Service::new()
+            .with_config(ServiceConfig {
+                max_participants: 2,
+                allowed_amounts: vec![U256::from(100)],
+                min_rsa_key_size: 2 * MIN_KEY_SIZE,
+                connect_timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            })
+            .expect("invalid config")
+            .with_clock(clock.clone());
+
+        let oversized = create_room(&service, &members).await;
+        assert!(matches!(
+            oversized,
+            Err(Error::Policy(PolicyViolation::ParticipantsNumber {
+                min: 2,
+                max: 2
+            }))
+        ));
+        let amount = service
+            .create_room(
+                Address::zero(),
+                U256::from(50),
+                vec![members[0].utxo_id, members[1].utxo_id],
+            )
+            .await;
+        assert!(matches!(
+            amount,
+            Err(Error::Policy(PolicyViolation::Amount(amount))) if amount == U256::from(50)
+        ));
+
+        create_room(&service, &members[..2])
+            .await
+            .expect("failed to create room");
+        let small_key = connect(
+            service.clone(),
+            contract.clone(),
+            members[0].clone(),
+            rsa_pubkey(),
+        )
+        .await;
+        assert!(matches!(
+            small_key,
+            Err(Error::Policy(PolicyViolation::RsaKeySize { size, .. })) if size == MIN_KEY_SIZE
+        ));
+
+        clock.advance(Duration::from_secs(61));
+        let rsa_pubkey = RsaPublicKey::from(
+            &generate_private_key(2 * MIN_KEY_SIZE).expect("failed to generate a key"),
+        );
+        let late = connect(service, contract, members[0].clone(), rsa_pubkey).await;
+        assert!(matches!(
+            late,
+            Err(Error::Policy(PolicyViolation::ConnectDeadline))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fee_and_change_are_paid_by_outputs() {
+        let contract = MockContract::new();
+        let mut members = members(&contract, 3);
+        let fee = Fee {
+            recipient: Address::random(),
+            amount: U256::from(1),
+        };
+        let service = Service::new()
+            .with_config(ServiceConfig {
+                fee: Some(fee.clone()),
+                ..Default::default()
+            })
+            .expect("invalid config");
+
+        // UTXO of the last member exceeds the room amount and the fee
+        let change = Address::random();
+        members[2].utxo_id =
+            contract.deposit(Address::zero(), members[2].owner.address(), U256::from(150));
+        let room = service
+            .create_room(
+                Address::zero(),
+                U256::from(99),
+                members.iter().map(|member| member.utxo_id).collect(),
+            )
+            .await
+            .expect("failed to create room");
+        assert_eq!(room.fee, Some(fee.clone()));
+
+        let rsa_pubkey = rsa_pubkey();
+        let without_change = connect(
+            service.clone(),
+            contract.clone(),
+            members[2].clone(),
+            rsa_pubkey.clone(),
+        )
+        .await;
+        assert!(matches!(without_change, Err(Error::InvalidChange)));
+
+        members[2].change = Some(change);
+        for member in members.iter() {
+            connect(
+                service.clone(),
+                contract.clone(),
+                member.clone(),
+                rsa_pubkey.clone(),
+            )
+            .await
+            .expect("failed to connect");
+        }
+
+        let mut order = Vec::new();
+        for member in members.iter() {
+            let start = reveal(service.clone(), room.id, member.clone(), member.entropy)
+                .await
+                .expect("failed to reveal");
+            if let Some(start) = start.into_inner() {
+                order = start.participants;
+            }
+        }
+
+        let mut finished = None;
+        for (round, utxo_id) in order.iter().enumerate() {
+            let member = members
+                .iter()
+                .find(|member| member.utxo_id == *utxo_id)
+                .expect("participant isn't a member");
+            let decoded_outputs = decoded_outputs(round, order.len());
+            let session_signature = member
+                .session
+                .sign_message(decoded_outputs_message(
+                    &room.id,
+                    member.utxo_id,
+                    &decoded_outputs,
+                ))
+                .await
+                .expect("failed to sign");
+
+            if let PassDecodedOutputsResult::Finished(outputs) = service
+                .pass_decoded_outputs(&member.utxo_id, decoded_outputs, session_signature)
+                .await
+                .expect("failed to pass decoded outputs")
+                .into_inner()
+            {
+                finished = Some(outputs);
+            }
+        }
+
+        let outputs = finished.expect("shuffle isn't finished");
+        assert_eq!(outputs.len(), 5);
+        assert!(outputs[..3]
+            .iter()
+            .all(|output| output.amount == U256::from(99)));
+        assert_eq!(outputs[3].owner, change);
+        assert_eq!(outputs[3].amount, U256::from(50));
+        assert_eq!(outputs[4].owner, fee.recipient);
+        assert_eq!(outputs[4].amount, U256::from(3));
+
+        service
+            .transcript(&room.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent")
+            .verify()
+            .expect("transcript of the room with the fee is invalid");
+    }
+
+    #[tokio::test]
+    async fn expired_rooms_are_reaped() {
+        let contract = MockContract::new();
+        let members = members(&contract, 2);
+        let clock = ManualClock::default();
+        let service = Service::new()
+            .with_config(ServiceConfig {
+                room_ttl: RoomTtl {
+                    waiting: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+                transcript_retention: Some(Duration::from_secs(120)),
+                ..Default::default()
+            })
+            .expect("invalid config")
+            .with_clock(clock.clone());
+
+        let expired = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+        assert_eq!(
+            service.reap().await.expect("failed to reap"),
+            Reaped::default()
+        );
+
+        clock.advance(Duration::from_secs(61));
+        let reaped = service.reap().await.expect("failed to reap");
+        assert_eq!(reaped.expired, vec![expired.id]);
+        assert!(reaped.transcripts.is_empty());
+        assert!(service
+            .get_participant(&members[0].utxo_id)
+            .await
+            .expect("failed to get participant")
+            .is_none());
+
+        // UTXOs of the expired room can be placed in another one, while the transcript of
+        // the expired room is kept for the retention window
+        let room = create_room(&service, &members)
+            .await
+            .expect("failed to create room");
+        let transcript = service
+            .transcript(&expired.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent");
+        transcript
+            .verify()
+            .expect("transcript of the expired room is invalid");
+        assert_eq!(
+            transcript.entries.last().map(|entry| &entry.event),
+            Some(&Event::Expired)
+        );
+
+        clock.advance(Duration::from_secs(121));
+        let reaped = service.reap().await.expect("failed to reap");
+        assert_eq!(reaped.expired, vec![room.id]);
+        assert_eq!(reaped.transcripts, vec![expired.id]);
+        assert!(service
+            .transcript(&expired.id)
+            .await
+            .expect("failed to get transcript")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn overflowing_fee_is_rejected_by_replay() {
+        let (service, room_id, members) = connected_room().await;
+        for (round, member) in members.iter().enumerate() {
+            pass(
+                &service,
+                room_id,
+                member,
+                decoded_outputs(round, PARTICIPANTS),
+            )
+            .await
+            .expect("failed to pass decoded outputs");
+        }
+        let transcript = service
+            .transcript(&room_id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent");
+
+        let fee = Fee {
+            recipient: Address::random(),
+            amount: U256::MAX / 2 + 1,
+        };
+        let tampered = rechain(transcript, |event| match event {
+            Event::RoomCreated { fee: room_fee, .. } => *room_fee = Some(fee.clone()),
+            // UTXOs cover the fee without the change
+            Event::Connected { amount, .. } => *amount = U256::from(100) + fee.amount,
+            _ => {}
+        });
+
+        let err = tampered.verify().expect_err("overflowing fee is accepted");
+        assert_eq!(err.violation, Violation::FeeOverflow);
+    }
+
+    #[tokio::test]
+    async fn invalid_outputs_are_rejected_by_replay() {
+        let (service, room_id, members) = connected_room().await;
+        let mut passed = Vec::new();
+        for (round, member) in members.iter().enumerate() {
+            let decoded_outputs = decoded_outputs(round, PARTICIPANTS);
+            pass(&service, room_id, member, decoded_outputs.clone())
+                .await
+                .expect("failed to pass decoded outputs");
+            passed.push(decoded_outputs);
+        }
+        let transcript = service
+            .transcript(&room_id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent");
+        transcript.verify().expect("transcript is invalid");
+
+        // Outputs of the second round are replaced by the ones the service rejects, signed by
+        // the session key, so only the rules of the outputs are broken
+        let member = &members[1];
+        let mut duplicate = passed[1].clone();
+        duplicate[1] = duplicate[0].clone();
+        let mut forwarded = passed[1].clone();
+        forwarded[0] = passed[0][0].clone();
+        let short = decoded_outputs(2, PARTICIPANTS)[..2].to_vec();
+        let expected_len = passed[1][0].len();
+
+        for (decoded_outputs, violation) in [
+            (duplicate, Violation::DuplicateOutputs(member.utxo_id)),
+            (forwarded, Violation::ForwardedOutputs(member.utxo_id)),
+            (
+                short,
+                Violation::InvalidOutputLength(member.utxo_id, expected_len),
+            ),
+        ] {
+            let session_signature = member
+                .session
+                .sign_message(decoded_outputs_message(
+                    &room_id,
+                    member.utxo_id,
+                    &decoded_outputs,
+                ))
+                .await
+                .expect("failed to sign");
+            let forged = Event::DecodedOutputs {
+                utxo_id: member.utxo_id,
+                decoded_outputs,
+                session_signature,
+            };
+
+            let tampered = rechain(transcript.clone(), |event| {
+                if matches!(event, Event::DecodedOutputs { utxo_id, .. } if *utxo_id == member.utxo_id)
+                {
+                    *event = forged.clone();
+                }
+            });
+            let err = tampered.verify().expect_err("invalid outputs are accepted");
+            assert_eq!(err.violation, violation);
+        }
+    }
+}
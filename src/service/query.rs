@@ -0,0 +1,309 @@
+//! Read-only queries of the rooms and participants for the operators of the [`Service`].
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use ethers_core::types::{Address, U256};
+
+use super::error::Error;
+use super::storage::{ParticipantsStorage, RoomsStorage, Storage, TranscriptsStorage};
+use super::types::{Participant, Room, RoomState};
+use super::{Service, ServiceResult};
+use crate::types::ShuffleStatus;
+
+/// Kind of the [`RoomState`] without its data, that the rooms are filtered and counted by.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RoomStateKind {
+    Waiting,
+    Connecting,
+    Revealing,
+    Shuffle,
+    Signatures,
+    TransactionHash,
+}
+
+impl RoomStateKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Waiting => "waiting",
+            Self::Connecting => "connecting",
+            Self::Revealing => "revealing",
+            Self::Shuffle => "shuffle",
+            Self::Signatures => "signatures",
+            Self::TransactionHash => "transaction_hash",
+        }
+    }
+}
+
+impl From<&RoomState> for RoomStateKind {
+    fn from(state: &RoomState) -> Self {
+        match state {
+            RoomState::Waiting => Self::Waiting,
+            RoomState::Connecting(_) => Self::Connecting,
+            RoomState::Revealing(_) => Self::Revealing,
+            RoomState::Shuffle(_) => Self::Shuffle,
+            RoomState::Signatures(_) => Self::Signatures,
+            RoomState::TransactionHash(_) => Self::TransactionHash,
+        }
+    }
+}
+
+/// Filter of the rooms listed by [`Service::list_rooms`], the absent fields match any room.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomFilter {
+    /// Rooms in one of the states, any state matches if it is empty.
+    pub states: Vec<RoomStateKind>,
+    pub token: Option<Address>,
+    pub amount: Option<U256>,
+    /// Rooms created at least this time ago.
+    pub min_age: Option<Duration>,
+    /// Rooms created at most this time ago.
+    pub max_age: Option<Duration>,
+}
+
+impl RoomFilter {
+    fn matches(&self, room: &Room, now: SystemTime) -> bool {
+        let age = now.duration_since(room.created_at).unwrap_or_default();
+
+        (self.states.is_empty() || self.states.contains(&RoomStateKind::from(&room.state)))
+            && self.token.is_none_or(|token| token == room.token)
+            && self.amount.is_none_or(|amount| amount == room.amount)
+            && self.min_age.is_none_or(|min_age| age >= min_age)
+            && self.max_age.is_none_or(|max_age| age <= max_age)
+    }
+}
+
+/// Page of the listed items, that skips `offset` items and takes at most `limit` of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: 100,
+        }
+    }
+}
+
+/// Rooms of the [`Page`] with the total number of the rooms matching the filter.
+#[derive(Debug, Clone)]
+pub struct RoomsPage {
+    pub rooms: Vec<Room>,
+    pub total: usize,
+}
+
+/// Numbers of the rooms, participants and transcripts kept by the service.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub rooms: BTreeMap<RoomStateKind, usize>,
+    pub participants: BTreeMap<ShuffleStatus, usize>,
+    /// Transcripts including the ones of the removed rooms.
+    pub transcripts: usize,
+}
+
+impl<S: Storage> Service<S> {
+    /// Return the page of the rooms matching the filter, the rooms are ordered by the time
+    /// they are created at.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, level = "debug"))]
+    pub async fn list_rooms(&self, filter: &RoomFilter, page: Page) -> ServiceResult<RoomsPage> {
+        let now = self.clock.now();
+
+        let mut rooms: Vec<Room> = self
+            .storage
+            .rooms()
+            .list()
+            .await
+            .map_err(Error::storage)?
+            .into_iter()
+            .filter(|room| filter.matches(room, now))
+            .collect();
+        rooms.sort_by_key(|room| (room.created_at, room.id));
+
+        let total = rooms.len();
+        let rooms = rooms
+            .into_iter()
+            .skip(page.offset)
+            .take(page.limit)
+            .collect();
+
+        Ok(RoomsPage { rooms, total })
+    }
+
+    /// Return participants of the room with their states, in the order of the room.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, level = "debug", fields(room_id = %room_id))
+    )]
+    pub async fn room_participants(&self, room_id: &uuid::Uuid) -> ServiceResult<Vec<Participant>> {
+        let room = self.room_by_id(room_id).await?;
+
+        let mut participants = self
+            .storage
+            .participants()
+            .get_many(&room.participants)
+            .await
+            .map_err(Error::storage)?;
+        participants.sort_by_key(|participant| {
+            room.participants
+                .iter()
+                .position(|utxo_id| *utxo_id == participant.utxo_id)
+        });
+
+        Ok(participants)
+    }
+
+    /// Return numbers of the rooms by their states, of the participants by their statuses
+    /// and of the transcripts.
+    #[cfg_attr(feature = "telemetry", tracing::instrument(skip_all, level = "debug"))]
+    pub async fn counts(&self) -> ServiceResult<Counts> {
+        let mut counts = Counts::default();
+
+        let rooms = self.storage.rooms().list().await.map_err(Error::storage)?;
+        for room in rooms.iter() {
+            *counts
+                .rooms
+                .entry(RoomStateKind::from(&room.state))
+                .or_default() += 1;
+
+            let participants = self
+                .storage
+                .participants()
+                .get_many(&room.participants)
+                .await
+                .map_err(Error::storage)?;
+            for participant in participants.iter() {
+                *counts
+                    .participants
+                    .entry(ShuffleStatus::from(&participant.state))
+                    .or_default() += 1;
+            }
+        }
+
+        counts.transcripts = self
+            .storage
+            .transcripts()
+            .list()
+            .await
+            .map_err(Error::storage)?
+            .len();
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers_core::types::{Address, U256};
+
+    use super::{Page, RoomFilter, RoomStateKind};
+    use crate::clock::ManualClock;
+    use crate::service::storage::{RoomsStorage, Storage};
+    use crate::service::types::RoomState;
+    use crate::service::Service;
+    use crate::types::ShuffleStatus;
+
+    #[tokio::test]
+    async fn rooms_are_filtered_and_paged() {
+        let clock = ManualClock::default();
+        let service = Service::new().with_clock(clock.clone());
+
+        let mut rooms = Vec::new();
+        for (utxo_id, amount) in [(0, 100), (2, 100), (4, 200)] {
+            let participants = vec![U256::from(utxo_id), U256::from(utxo_id + 1)];
+            rooms.push(
+                service
+                    .create_room(Address::zero(), U256::from(amount), participants)
+                    .await
+                    .expect("failed to create room"),
+            );
+            clock.advance(Duration::from_secs(1));
+        }
+        service
+            .storage
+            .rooms()
+            .update_state(rooms[1].id, RoomState::Shuffle(0))
+            .await
+            .expect("failed to update room");
+
+        let all = service
+            .list_rooms(&RoomFilter::default(), Page::default())
+            .await
+            .expect("failed to list rooms");
+        assert_eq!(all.total, 3);
+        assert_eq!(
+            all.rooms.iter().map(|room| room.id).collect::<Vec<_>>(),
+            rooms.iter().map(|room| room.id).collect::<Vec<_>>()
+        );
+
+        let page = service
+            .list_rooms(
+                &RoomFilter {
+                    amount: Some(U256::from(100)),
+                    ..Default::default()
+                },
+                Page {
+                    offset: 1,
+                    limit: 1,
+                },
+            )
+            .await
+            .expect("failed to list rooms");
+        assert_eq!(page.total, 2);
+        assert_eq!(page.rooms[0].id, rooms[1].id);
+
+        let waiting = service
+            .list_rooms(
+                &RoomFilter {
+                    states: vec![RoomStateKind::Waiting],
+                    token: Some(Address::zero()),
+                    amount: Some(U256::from(100)),
+                    ..Default::default()
+                },
+                Page::default(),
+            )
+            .await
+            .expect("failed to list rooms");
+        assert_eq!(waiting.total, 1);
+        assert_eq!(waiting.rooms[0].id, rooms[0].id);
+
+        let old = service
+            .list_rooms(
+                &RoomFilter {
+                    min_age: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+                Page::default(),
+            )
+            .await
+            .expect("failed to list rooms");
+        assert_eq!(old.total, 0);
+
+        let participants = service
+            .room_participants(&rooms[2].id)
+            .await
+            .expect("failed to list participants");
+        assert_eq!(
+            participants
+                .iter()
+                .map(|participant| participant.utxo_id)
+                .collect::<Vec<_>>(),
+            rooms[2].participants
+        );
+
+        let counts = service.counts().await.expect("failed to count");
+        assert_eq!(counts.rooms.get(&RoomStateKind::Waiting), Some(&2));
+        assert_eq!(counts.rooms.get(&RoomStateKind::Shuffle), Some(&1));
+        assert_eq!(
+            counts.participants.get(&ShuffleStatus::SearchParticipants),
+            Some(&6)
+        );
+        assert_eq!(counts.transcripts, 3);
+    }
+}
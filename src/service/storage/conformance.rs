@@ -0,0 +1,767 @@
+//! Conformance suite for the [`Storage`] implementations.
+//!
+//! Every check panics with a describing message if the storage behaves differently from
+//! the [`ServiceStorage`](super::inmemory::ServiceStorage). Checks use random ids, so they
+//! can be run one after another against the same storage instance:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn my_storage_conforms() {
+//!     coin_shuffle_core::service::storage::conformance::run(MyStorage::new()).await;
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+use ethers_core::types::{Address, U256};
+
+use super::{ParticipantsStorage, RoomsStorage, Storage, TranscriptsStorage};
+use crate::service::transcript::{Entry, Event};
+use crate::service::types::{Participant, ParticipantState, Room, RoomState};
+
+/// Number of tasks writing to the storage at the same time in [`concurrent_writers`].
+const CONCURRENT_WRITERS: usize = 16;
+
+/// Run all the checks of the suite.
+pub async fn run<S: Storage + 'static>(storage: S) {
+    rooms_insert_get_delete(&storage).await;
+    rooms_update_state(&storage).await;
+    rooms_duplicate_insert(&storage).await;
+    rooms_list(&storage).await;
+    participants_insert_get_delete(&storage).await;
+    participants_get_many(&storage).await;
+    participants_update_state(&storage).await;
+    participants_duplicate_insert(&storage).await;
+    transcripts_append_get(&storage).await;
+    transcripts_list_delete(&storage).await;
+    concurrent_writers(&storage).await;
+    room_lock(&storage).await;
+    clear_room(&storage).await;
+}
+
+pub async fn rooms_insert_get_delete<S: Storage>(storage: &S) {
+    let room = random_room(3);
+
+    assert!(
+        storage
+            .rooms()
+            .get(room.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is present before the insert"
+    );
+
+    storage
+        .rooms()
+        .insert(room.clone())
+        .await
+        .expect("insert room");
+
+    let stored = storage
+        .rooms()
+        .get(room.id)
+        .await
+        .expect("get room")
+        .expect("inserted room is absent");
+    assert_room_eq(&stored, &room);
+
+    storage.rooms().delete(room.id).await.expect("delete room");
+    assert!(
+        storage
+            .rooms()
+            .get(room.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is present after the delete"
+    );
+
+    storage
+        .rooms()
+        .delete(room.id)
+        .await
+        .expect("deleting of the absent room must not fail");
+}
+
+pub async fn rooms_update_state<S: Storage>(storage: &S) {
+    let room = random_room(3);
+    storage
+        .rooms()
+        .insert(room.clone())
+        .await
+        .expect("insert room");
+
+    let state = RoomState::Connecting(BTreeSet::from([room.participants[0]]));
+    storage
+        .rooms()
+        .update_state(room.id, state.clone())
+        .await
+        .expect("update room state");
+
+    let stored = storage
+        .rooms()
+        .get(room.id)
+        .await
+        .expect("get room")
+        .expect("updated room is absent");
+    assert_eq!(stored.state, state, "room state isn't updated");
+    assert_eq!(
+        stored.participants, room.participants,
+        "room participants are changed by the state update"
+    );
+
+    let absent = uuid::Uuid::new_v4();
+    storage
+        .rooms()
+        .update_state(absent, RoomState::Shuffle(0))
+        .await
+        .expect("updating of the absent room must not fail");
+    assert!(
+        storage
+            .rooms()
+            .get(absent)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is created by the state update"
+    );
+
+    storage.rooms().delete(room.id).await.expect("delete room");
+}
+
+pub async fn rooms_duplicate_insert<S: Storage>(storage: &S) {
+    let room = random_room(3);
+    storage
+        .rooms()
+        .insert(room.clone())
+        .await
+        .expect("insert room");
+
+    let mut replacement = room.clone();
+    replacement.state = RoomState::Shuffle(1);
+    storage
+        .rooms()
+        .insert(replacement.clone())
+        .await
+        .expect("insert of the room with the same id must replace it");
+
+    let stored = storage
+        .rooms()
+        .get(room.id)
+        .await
+        .expect("get room")
+        .expect("replaced room is absent");
+    assert_room_eq(&stored, &replacement);
+
+    storage.rooms().delete(room.id).await.expect("delete room");
+}
+
+pub async fn rooms_list<S: Storage>(storage: &S) {
+    let rooms = (0..3).map(|_| random_room(2)).collect::<Vec<_>>();
+    for room in rooms.iter() {
+        storage
+            .rooms()
+            .insert(room.clone())
+            .await
+            .expect("insert room");
+    }
+    storage
+        .rooms()
+        .delete(rooms[2].id)
+        .await
+        .expect("delete room");
+
+    // Storage may be shared with the other checks, so it may have other rooms as well
+    let listed = storage.rooms().list().await.expect("list rooms");
+    for room in rooms[..2].iter() {
+        let stored = listed
+            .iter()
+            .find(|stored| stored.id == room.id)
+            .expect("inserted room isn't listed");
+        assert_room_eq(stored, room);
+    }
+    assert!(
+        listed.iter().all(|stored| stored.id != rooms[2].id),
+        "deleted room is listed"
+    );
+
+    for room in rooms[..2].iter() {
+        storage.rooms().delete(room.id).await.expect("delete room");
+    }
+}
+
+pub async fn participants_insert_get_delete<S: Storage>(storage: &S) {
+    let participant = Participant::new(random_utxo_id(), uuid::Uuid::new_v4());
+
+    assert!(
+        storage
+            .participants()
+            .get(participant.utxo_id)
+            .await
+            .expect("get participant")
+            .is_none(),
+        "participant is present before the insert"
+    );
+
+    storage
+        .participants()
+        .insert(participant.clone())
+        .await
+        .expect("insert participant");
+
+    let stored = storage
+        .participants()
+        .get(participant.utxo_id)
+        .await
+        .expect("get participant")
+        .expect("inserted participant is absent");
+    assert_participant_eq(&stored, &participant);
+
+    storage
+        .participants()
+        .delete(participant.utxo_id)
+        .await
+        .expect("delete participant");
+    assert!(
+        storage
+            .participants()
+            .get(participant.utxo_id)
+            .await
+            .expect("get participant")
+            .is_none(),
+        "participant is present after the delete"
+    );
+
+    storage
+        .participants()
+        .delete(participant.utxo_id)
+        .await
+        .expect("deleting of the absent participant must not fail");
+}
+
+pub async fn participants_get_many<S: Storage>(storage: &S) {
+    let room_id = uuid::Uuid::new_v4();
+    let participants = (0..3)
+        .map(|_| Participant::new(random_utxo_id(), room_id))
+        .collect::<Vec<_>>();
+
+    for participant in participants.iter() {
+        storage
+            .participants()
+            .insert(participant.clone())
+            .await
+            .expect("insert participant");
+    }
+
+    let mut ids = participants.iter().map(|p| p.utxo_id).collect::<Vec<_>>();
+    ids.insert(1, random_utxo_id());
+
+    let stored = storage
+        .participants()
+        .get_many(&ids)
+        .await
+        .expect("get many participants");
+    assert_eq!(
+        stored.len(),
+        participants.len(),
+        "absent participants must be skipped"
+    );
+    for (stored, participant) in stored.iter().zip(participants.iter()) {
+        assert_participant_eq(stored, participant);
+    }
+
+    for participant in participants.iter() {
+        storage
+            .participants()
+            .delete(participant.utxo_id)
+            .await
+            .expect("delete participant");
+    }
+}
+
+pub async fn participants_update_state<S: Storage>(storage: &S) {
+    let participant = Participant::new(random_utxo_id(), uuid::Uuid::new_v4());
+    storage
+        .participants()
+        .insert(participant.clone())
+        .await
+        .expect("insert participant");
+
+    let state = ParticipantState::DecodedOutputs(vec![vec![1, 2, 3]]);
+    storage
+        .participants()
+        .update_state(participant.utxo_id, state.clone())
+        .await
+        .expect("update participant state");
+
+    let stored = storage
+        .participants()
+        .get(participant.utxo_id)
+        .await
+        .expect("get participant")
+        .expect("updated participant is absent");
+    assert_eq!(stored.state, state, "participant state isn't updated");
+    assert_eq!(
+        stored.room_id, participant.room_id,
+        "participant room is changed by the state update"
+    );
+
+    let absent = random_utxo_id();
+    storage
+        .participants()
+        .update_state(absent, ParticipantState::Finish)
+        .await
+        .expect("updating of the absent participant must not fail");
+    assert!(
+        storage
+            .participants()
+            .get(absent)
+            .await
+            .expect("get participant")
+            .is_none(),
+        "participant is created by the state update"
+    );
+
+    storage
+        .participants()
+        .delete(participant.utxo_id)
+        .await
+        .expect("delete participant");
+}
+
+pub async fn participants_duplicate_insert<S: Storage>(storage: &S) {
+    let participant = Participant::new(random_utxo_id(), uuid::Uuid::new_v4());
+    storage
+        .participants()
+        .insert(participant.clone())
+        .await
+        .expect("insert participant");
+
+    let replacement = Participant::new(participant.utxo_id, uuid::Uuid::new_v4());
+    storage
+        .participants()
+        .insert(replacement.clone())
+        .await
+        .expect("insert of the participant with the same UTXO id must replace it");
+
+    let stored = storage
+        .participants()
+        .get(participant.utxo_id)
+        .await
+        .expect("get participant")
+        .expect("replaced participant is absent");
+    assert_participant_eq(&stored, &replacement);
+
+    storage
+        .participants()
+        .delete(participant.utxo_id)
+        .await
+        .expect("delete participant");
+}
+
+pub async fn transcripts_append_get<S: Storage>(storage: &S) {
+    let room = random_room(2);
+    let events = vec![
+        Event::RoomCreated {
+            token: room.token,
+            amount: room.amount,
+            fee: room.fee.clone(),
+            participants: room.participants.clone(),
+        },
+        Event::State(RoomState::Shuffle(0)),
+        Event::State(RoomState::Shuffle(1)),
+    ];
+
+    assert!(
+        storage
+            .transcripts()
+            .get(room.id)
+            .await
+            .expect("get transcript")
+            .is_empty(),
+        "transcript is present before the append"
+    );
+
+    let mut expected: Vec<Entry> = Vec::new();
+    for (index, event) in events.into_iter().enumerate() {
+        let recorded_at = SystemTime::UNIX_EPOCH + Duration::from_secs(index as u64);
+        let entry = storage
+            .transcripts()
+            .append(room.id, event.clone(), recorded_at)
+            .await
+            .expect("append to transcript");
+        assert_eq!(
+            entry,
+            Entry::recorded_at(expected.last(), event, recorded_at),
+            "appended entry isn't chained to the last one"
+        );
+
+        expected.push(entry);
+    }
+
+    let stored = storage
+        .transcripts()
+        .get(room.id)
+        .await
+        .expect("get transcript");
+    assert_eq!(stored, expected, "transcript entries mismatch");
+
+    assert!(
+        storage
+            .transcripts()
+            .get(uuid::Uuid::new_v4())
+            .await
+            .expect("get transcript")
+            .is_empty(),
+        "transcript of the other room isn't empty"
+    );
+}
+
+pub async fn transcripts_list_delete<S: Storage>(storage: &S) {
+    let (kept, deleted) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    for room_id in [kept, deleted] {
+        storage
+            .transcripts()
+            .append(
+                room_id,
+                Event::State(RoomState::Shuffle(0)),
+                SystemTime::now(),
+            )
+            .await
+            .expect("append to transcript");
+    }
+
+    storage
+        .transcripts()
+        .delete(deleted)
+        .await
+        .expect("delete transcript");
+    assert!(
+        storage
+            .transcripts()
+            .get(deleted)
+            .await
+            .expect("get transcript")
+            .is_empty(),
+        "transcript is present after the delete"
+    );
+    storage
+        .transcripts()
+        .delete(deleted)
+        .await
+        .expect("deleting of the absent transcript must not fail");
+
+    let listed = storage
+        .transcripts()
+        .list()
+        .await
+        .expect("list transcripts");
+    assert!(listed.contains(&kept), "transcript isn't listed");
+    assert!(!listed.contains(&deleted), "deleted transcript is listed");
+
+    storage
+        .transcripts()
+        .delete(kept)
+        .await
+        .expect("delete transcript");
+}
+
+/// Insert rooms with participants and update their states from several tasks at once,
+/// none of the writes must be lost.
+pub async fn concurrent_writers<S: Storage + 'static>(storage: &S) {
+    let rooms = (0..CONCURRENT_WRITERS)
+        .map(|_| random_room(4))
+        .collect::<Vec<_>>();
+
+    let handles = rooms
+        .iter()
+        .cloned()
+        .map(|room| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .rooms()
+                    .insert(room.clone())
+                    .await
+                    .expect("insert room");
+
+                for utxo_id in room.participants.iter() {
+                    storage
+                        .participants()
+                        .insert(Participant::new(*utxo_id, room.id))
+                        .await
+                        .expect("insert participant");
+                    tokio::task::yield_now().await;
+                }
+
+                storage
+                    .rooms()
+                    .update_state(room.id, RoomState::Shuffle(room.participants.len()))
+                    .await
+                    .expect("update room state");
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.await.expect("writer task panicked");
+    }
+
+    // Participants of the same room are updated by different tasks.
+    let handles = rooms
+        .iter()
+        .flat_map(|room| room.participants.iter().copied())
+        .map(|utxo_id| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .participants()
+                    .update_state(utxo_id, ParticipantState::Finish)
+                    .await
+                    .expect("update participant state");
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.await.expect("writer task panicked");
+    }
+
+    for room in rooms.iter() {
+        let stored = storage
+            .rooms()
+            .get(room.id)
+            .await
+            .expect("get room")
+            .expect("concurrently inserted room is lost");
+        assert_eq!(
+            stored.state,
+            RoomState::Shuffle(room.participants.len()),
+            "concurrent room state update is lost"
+        );
+
+        let participants = storage
+            .participants()
+            .get_many(&room.participants)
+            .await
+            .expect("get many participants");
+        assert_eq!(
+            participants.len(),
+            room.participants.len(),
+            "concurrently inserted participant is lost"
+        );
+        assert!(
+            participants
+                .iter()
+                .all(|p| p.state == ParticipantState::Finish && p.room_id == room.id),
+            "concurrent participant state update is lost"
+        );
+
+        storage.clear_room(&room.id).await.expect("clear room");
+    }
+}
+
+/// Tasks read and update the state of the same room under its lock, so none of the
+/// updates is lost, while the lock of another room is taken independently.
+pub async fn room_lock<S: Storage + 'static>(storage: &S) {
+    let room = random_room(2);
+    storage
+        .rooms()
+        .insert(room.clone())
+        .await
+        .expect("insert room");
+    storage
+        .rooms()
+        .update_state(room.id, RoomState::Shuffle(0))
+        .await
+        .expect("update room state");
+
+    let handles = (0..CONCURRENT_WRITERS)
+        .map(|_| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let _lock = storage.lock_room(&room.id).await.expect("lock room");
+
+                let stored = storage
+                    .rooms()
+                    .get(room.id)
+                    .await
+                    .expect("get room")
+                    .expect("locked room is absent");
+                let RoomState::Shuffle(round) = stored.state else {
+                    panic!("unexpected room state {:?}", stored.state);
+                };
+                tokio::task::yield_now().await;
+
+                storage
+                    .rooms()
+                    .update_state(room.id, RoomState::Shuffle(round + 1))
+                    .await
+                    .expect("update room state");
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.await.expect("locking task panicked");
+    }
+
+    let stored = storage
+        .rooms()
+        .get(room.id)
+        .await
+        .expect("get room")
+        .expect("locked room is absent");
+    assert_eq!(
+        stored.state,
+        RoomState::Shuffle(CONCURRENT_WRITERS),
+        "room state update under the lock is lost"
+    );
+
+    let lock = storage.lock_room(&room.id).await.expect("lock room");
+    let other = storage
+        .lock_room(&uuid::Uuid::new_v4())
+        .await
+        .expect("lock another room");
+    drop((lock, other));
+
+    storage.clear_room(&room.id).await.expect("clear room");
+}
+
+pub async fn clear_room<S: Storage>(storage: &S) {
+    let room = random_room(3);
+    let neighbour = Participant::new(random_utxo_id(), uuid::Uuid::new_v4());
+
+    storage
+        .rooms()
+        .insert(room.clone())
+        .await
+        .expect("insert room");
+    for utxo_id in room.participants.iter() {
+        storage
+            .participants()
+            .insert(Participant::new(*utxo_id, room.id))
+            .await
+            .expect("insert participant");
+    }
+    storage
+        .participants()
+        .insert(neighbour.clone())
+        .await
+        .expect("insert participant");
+    storage
+        .transcripts()
+        .append(
+            room.id,
+            Event::State(RoomState::Shuffle(0)),
+            SystemTime::now(),
+        )
+        .await
+        .expect("append to transcript");
+
+    let cleared = storage.clear_room(&room.id).await.expect("clear room");
+    assert_eq!(
+        cleared, room.participants,
+        "cleared participants differ from the room ones"
+    );
+
+    assert!(
+        storage
+            .rooms()
+            .get(room.id)
+            .await
+            .expect("get room")
+            .is_none(),
+        "room is present after the clear"
+    );
+    assert!(
+        storage
+            .participants()
+            .get_many(&room.participants)
+            .await
+            .expect("get many participants")
+            .is_empty(),
+        "room participants are present after the clear"
+    );
+    assert!(
+        storage
+            .participants()
+            .get(neighbour.utxo_id)
+            .await
+            .expect("get participant")
+            .is_some(),
+        "participant of the other room is removed by the clear"
+    );
+    assert_eq!(
+        storage
+            .transcripts()
+            .get(room.id)
+            .await
+            .expect("get transcript")
+            .len(),
+        1,
+        "transcript of the room is removed by the clear"
+    );
+
+    assert!(
+        storage
+            .clear_room(&uuid::Uuid::new_v4())
+            .await
+            .expect("clearing of the absent room must not fail")
+            .is_empty(),
+        "clearing of the absent room returned participants"
+    );
+
+    storage
+        .participants()
+        .delete(neighbour.utxo_id)
+        .await
+        .expect("delete participant");
+}
+
+fn random_utxo_id() -> U256 {
+    U256::from_big_endian(&rand::random::<[u8; 32]>())
+}
+
+fn random_room(participants_number: usize) -> Room {
+    Room::new(
+        Address::random(),
+        U256::from(rand::random::<u64>()),
+        (0..participants_number).map(|_| random_utxo_id()).collect(),
+    )
+}
+
+fn assert_room_eq(stored: &Room, expected: &Room) {
+    assert_eq!(stored.id, expected.id, "room id mismatch");
+    assert_eq!(stored.token, expected.token, "room token mismatch");
+    assert_eq!(stored.amount, expected.amount, "room amount mismatch");
+    assert_eq!(stored.state, expected.state, "room state mismatch");
+    assert_eq!(
+        stored.participants, expected.participants,
+        "room participants mismatch"
+    );
+}
+
+fn assert_participant_eq(stored: &Participant, expected: &Participant) {
+    assert_eq!(
+        stored.utxo_id, expected.utxo_id,
+        "participant UTXO id mismatch"
+    );
+    assert_eq!(
+        stored.room_id, expected.room_id,
+        "participant room mismatch"
+    );
+    assert_eq!(stored.state, expected.state, "participant state mismatch");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::storage::inmemory::ServiceStorage;
+
+    #[tokio::test]
+    async fn inmemory_storage_conforms() {
+        super::run(ServiceStorage::new()).await;
+    }
+}
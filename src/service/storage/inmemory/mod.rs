@@ -1,51 +1,84 @@
-use ethers_core::types::U256;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Weak};
+
+use async_trait::async_trait;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+use super::Storage;
 
 mod participants;
 mod rooms;
+mod transcripts;
+
+pub use participants::ParticipantsMemoryStorage;
+pub use rooms::RoomsMemoryStorage;
+pub use transcripts::TranscriptsMemoryStorage;
 
+/// Default realization of the Service's [`Storage`].
 #[derive(Clone)]
 pub struct ServiceStorage {
-    participants: participants::ParticipantsStorage,
-    rooms: rooms::RoomsStorage,
+    participants: ParticipantsMemoryStorage,
+    rooms: RoomsMemoryStorage,
+    transcripts: TranscriptsMemoryStorage,
+    /// Locks of the rooms, that are removed once nobody holds or waits for them.
+    locks: Arc<std::sync::Mutex<HashMap<Uuid, Weak<Mutex<()>>>>>,
 }
 
 impl ServiceStorage {
     pub fn new() -> Self {
         Self {
-            participants: participants::ParticipantsStorage::new(),
-            rooms: rooms::RoomsStorage::new(),
+            participants: ParticipantsMemoryStorage::new(),
+            rooms: RoomsMemoryStorage::new(),
+            transcripts: TranscriptsMemoryStorage::new(),
+            locks: Arc::new(std::sync::Mutex::new(HashMap::new())),
         }
     }
+}
+
+impl Default for ServiceStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Storage for ServiceStorage {
+    type Error = Infallible;
+    type Participants = ParticipantsMemoryStorage;
+    type Rooms = RoomsMemoryStorage;
+    type Transcripts = TranscriptsMemoryStorage;
+    type RoomLock = OwnedMutexGuard<()>;
 
-    pub fn participants(&self) -> &participants::ParticipantsStorage {
+    fn participants(&self) -> &Self::Participants {
         &self.participants
     }
 
-    pub fn rooms(&self) -> &rooms::RoomsStorage {
+    fn rooms(&self) -> &Self::Rooms {
         &self.rooms
     }
 
-    /// Delete room, participants instances in storage and return deleted participants
-    /// UTXO ids
-    pub async fn clear_room(&self, room: &uuid::Uuid) -> Vec<U256> {
-        let room = self.rooms.get(*room).await;
+    fn transcripts(&self) -> &Self::Transcripts {
+        &self.transcripts
+    }
 
-        if let Some(room) = room {
-            self.rooms.delete(room.id).await;
+    async fn lock_room(&self, room_id: &Uuid) -> Result<Self::RoomLock, Self::Error> {
+        let lock = {
+            let mut locks = self.locks.lock().expect("room locks are poisoned");
 
-            for participant in room.participants.iter() {
-                self.participants.delete(*participant).await;
-            }
+            match locks.get(room_id).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    locks.retain(|_, lock| lock.strong_count() > 0);
 
-            return room.participants;
-        }
-
-        vec![]
-    }
-}
+                    let lock = Arc::new(Mutex::new(()));
+                    locks.insert(*room_id, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
 
-impl Default for ServiceStorage {
-    fn default() -> Self {
-        Self::new()
+        Ok(lock.lock_owned().await)
     }
 }
@@ -1,56 +1,70 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
 
+use async_trait::async_trait;
 use ethers_core::types::U256;
 use tokio::sync::Mutex;
 
+use crate::service::storage::ParticipantsStorage;
 use crate::service::types::{Participant, ParticipantState};
 
-/// `ParticipantsStorage` - provides inmemory storage for [`Participant`] entities.
+/// `ParticipantsMemoryStorage` - provides inmemory storage for [`Participant`] entities.
 #[derive(Clone)]
-pub struct ParticipantsStorage {
+pub struct ParticipantsMemoryStorage {
     participants: Arc<Mutex<HashMap<U256, Participant>>>,
 }
 
-impl Default for ParticipantsStorage {
+impl Default for ParticipantsMemoryStorage {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl ParticipantsStorage {
+impl ParticipantsMemoryStorage {
     pub fn new() -> Self {
         Self {
             participants: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+}
+
+#[async_trait]
+impl ParticipantsStorage for ParticipantsMemoryStorage {
+    type Error = Infallible;
 
-    pub async fn insert(&self, participant: Participant) {
+    async fn insert(&self, participant: Participant) -> Result<(), Self::Error> {
         let mut participants = self.participants.lock().await;
         participants.insert(participant.utxo_id, participant);
+        Ok(())
     }
 
-    pub async fn get(&self, utxo_id: U256) -> Option<Participant> {
+    async fn get(&self, utxo_id: U256) -> Result<Option<Participant>, Self::Error> {
         let participants = self.participants.lock().await;
-        participants.get(&utxo_id).cloned()
+        Ok(participants.get(&utxo_id).cloned())
     }
 
-    pub async fn get_many(&self, utxo_ids: &[U256]) -> Vec<Participant> {
+    async fn get_many(&self, utxo_ids: &[U256]) -> Result<Vec<Participant>, Self::Error> {
         let participants = self.participants.lock().await;
-        utxo_ids
+        Ok(utxo_ids
             .iter()
             .filter_map(|utxo_id| participants.get(utxo_id).cloned())
-            .collect()
+            .collect())
     }
 
-    pub async fn delete(&self, utxo_id: U256) {
+    async fn delete(&self, utxo_id: U256) -> Result<(), Self::Error> {
         let mut participants = self.participants.lock().await;
         participants.remove(&utxo_id);
+        Ok(())
     }
 
-    pub async fn update_state(&self, utxo_id: U256, state: ParticipantState) {
+    async fn update_state(
+        &self,
+        utxo_id: U256,
+        state: ParticipantState,
+    ) -> Result<(), Self::Error> {
         let mut participants = self.participants.lock().await;
         if let Some(participant) = participants.get_mut(&utxo_id) {
             participant.state = state;
         }
+        Ok(())
     }
 }
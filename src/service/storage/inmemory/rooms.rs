@@ -1,48 +1,64 @@
 use crate::service::types::RoomState;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
 
+use async_trait::async_trait;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use crate::service::storage::RoomsStorage;
 use crate::service::types::Room;
 
+/// `RoomsMemoryStorage` - provides inmemory storage for [`Room`] entities.
 #[derive(Clone)]
-pub struct RoomsStorage {
+pub struct RoomsMemoryStorage {
     rooms: Arc<Mutex<HashMap<Uuid, Room>>>,
 }
 
-impl Default for RoomsStorage {
+impl Default for RoomsMemoryStorage {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl RoomsStorage {
+impl RoomsMemoryStorage {
     pub fn new() -> Self {
         Self {
             rooms: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+}
+
+#[async_trait]
+impl RoomsStorage for RoomsMemoryStorage {
+    type Error = Infallible;
 
-    pub async fn insert(&self, room: Room) {
+    async fn insert(&self, room: Room) -> Result<(), Self::Error> {
         let mut rooms = self.rooms.lock().await;
         rooms.insert(room.id, room);
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Room>, Self::Error> {
+        let rooms = self.rooms.lock().await;
+        Ok(rooms.get(&id).cloned())
     }
 
-    pub async fn get(&self, id: Uuid) -> Option<Room> {
+    async fn list(&self) -> Result<Vec<Room>, Self::Error> {
         let rooms = self.rooms.lock().await;
-        rooms.get(&id).cloned()
+        Ok(rooms.values().cloned().collect())
     }
 
-    pub async fn delete(&self, id: Uuid) {
+    async fn delete(&self, id: Uuid) -> Result<(), Self::Error> {
         let mut rooms = self.rooms.lock().await;
         rooms.remove(&id);
+        Ok(())
     }
 
-    pub async fn update_state(&self, id: Uuid, state: RoomState) {
+    async fn update_state(&self, id: Uuid, state: RoomState) -> Result<(), Self::Error> {
         let mut rooms = self.rooms.lock().await;
         if let Some(room) = rooms.get_mut(&id) {
             room.state = state;
         }
+        Ok(())
     }
 }
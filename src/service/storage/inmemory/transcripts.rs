@@ -0,0 +1,63 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::service::storage::TranscriptsStorage;
+use crate::service::transcript::{Entry, Event};
+
+/// `TranscriptsMemoryStorage` - provides inmemory storage for the room transcripts.
+#[derive(Clone)]
+pub struct TranscriptsMemoryStorage {
+    transcripts: Arc<Mutex<HashMap<Uuid, Vec<Entry>>>>,
+}
+
+impl Default for TranscriptsMemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TranscriptsMemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            transcripts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptsStorage for TranscriptsMemoryStorage {
+    type Error = Infallible;
+
+    async fn append(
+        &self,
+        room_id: Uuid,
+        event: Event,
+        recorded_at: SystemTime,
+    ) -> Result<Entry, Self::Error> {
+        let mut transcripts = self.transcripts.lock().await;
+        let entries = transcripts.entry(room_id).or_default();
+
+        let entry = Entry::recorded_at(entries.last(), event, recorded_at);
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    async fn get(&self, room_id: Uuid) -> Result<Vec<Entry>, Self::Error> {
+        let transcripts = self.transcripts.lock().await;
+        Ok(transcripts.get(&room_id).cloned().unwrap_or_default())
+    }
+
+    async fn list(&self) -> Result<Vec<Uuid>, Self::Error> {
+        let transcripts = self.transcripts.lock().await;
+        Ok(transcripts.keys().copied().collect())
+    }
+
+    async fn delete(&self, room_id: Uuid) -> Result<(), Self::Error> {
+        let mut transcripts = self.transcripts.lock().await;
+        transcripts.remove(&room_id);
+        Ok(())
+    }
+}
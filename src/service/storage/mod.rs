@@ -1 +1,109 @@
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use ethers_core::types::U256;
+use uuid::Uuid;
+
+use crate::service::transcript::{Entry, Event};
+use crate::service::types::{Participant, ParticipantState, Room, RoomState};
+
 pub mod inmemory;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod conformance;
+
+/// Storage of the [`Room`] entities.
+#[async_trait]
+pub trait RoomsStorage: Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Insert room, the room with the same id is replaced.
+    async fn insert(&self, room: Room) -> Result<(), Self::Error>;
+    async fn get(&self, id: Uuid) -> Result<Option<Room>, Self::Error>;
+    /// Return all the rooms in any order.
+    async fn list(&self) -> Result<Vec<Room>, Self::Error>;
+    /// Delete room, deleting of the absent room is not an error.
+    async fn delete(&self, id: Uuid) -> Result<(), Self::Error>;
+    /// Update state of the room, nothing happens if the room is absent.
+    async fn update_state(&self, id: Uuid, state: RoomState) -> Result<(), Self::Error>;
+}
+
+/// Storage of the [`Participant`] entities.
+#[async_trait]
+pub trait ParticipantsStorage: Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Insert participant, the participant with the same UTXO id is replaced.
+    async fn insert(&self, participant: Participant) -> Result<(), Self::Error>;
+    async fn get(&self, utxo_id: U256) -> Result<Option<Participant>, Self::Error>;
+    /// Return participants with given UTXO ids, absent ones are skipped.
+    async fn get_many(&self, utxo_ids: &[U256]) -> Result<Vec<Participant>, Self::Error>;
+    /// Delete participant, deleting of the absent participant is not an error.
+    async fn delete(&self, utxo_id: U256) -> Result<(), Self::Error>;
+    /// Update state of the participant, nothing happens if the participant is absent.
+    async fn update_state(&self, utxo_id: U256, state: ParticipantState)
+        -> Result<(), Self::Error>;
+}
+
+/// Storage of the room transcripts, they are kept after the room is cleared until the
+/// retention window of the [`ServiceConfig`](crate::service::ServiceConfig) is over.
+#[async_trait]
+pub trait TranscriptsStorage: Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Append the event recorded at given time to the transcript of the room and return the
+    /// appended entry, that is created by [`Entry::recorded_at`] from the last entry of the
+    /// transcript.
+    async fn append(
+        &self,
+        room_id: Uuid,
+        event: Event,
+        recorded_at: SystemTime,
+    ) -> Result<Entry, Self::Error>;
+    /// Return entries of the room transcript, empty if the room has none.
+    async fn get(&self, room_id: Uuid) -> Result<Vec<Entry>, Self::Error>;
+    /// Return ids of the rooms that have transcripts, in any order.
+    async fn list(&self) -> Result<Vec<Uuid>, Self::Error>;
+    /// Delete transcript of the room, deleting of the absent transcript is not an error.
+    async fn delete(&self, room_id: Uuid) -> Result<(), Self::Error>;
+}
+
+/// Storage that is required for the [`Service`](crate::service::Service) work.
+///
+/// Changes of the room are made by the service in several steps: the room, its participants
+/// and transcript are read, checked and written back. The steps are made while the
+/// [`Storage::lock_room`] guard is held, so the changes of the same room are applied one
+/// after another, while the different rooms are changed concurrently.
+#[async_trait]
+pub trait Storage: Clone + Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+    type Participants: ParticipantsStorage<Error = Self::Error>;
+    type Rooms: RoomsStorage<Error = Self::Error>;
+    type Transcripts: TranscriptsStorage<Error = Self::Error>;
+    /// Guard of the room lock, the lock is released on drop.
+    type RoomLock: Send;
+
+    fn participants(&self) -> &Self::Participants;
+    fn rooms(&self) -> &Self::Rooms;
+    fn transcripts(&self) -> &Self::Transcripts;
+
+    /// Lock the room with given id, waiting for the current holder to release it. The room
+    /// doesn't have to exist, so the lock can be taken before the room is created.
+    async fn lock_room(&self, room_id: &Uuid) -> Result<Self::RoomLock, Self::Error>;
+
+    /// Delete room, participants instances in storage and return deleted participants
+    /// UTXO ids. Transcript of the room is kept.
+    async fn clear_room(&self, room_id: &Uuid) -> Result<Vec<U256>, Self::Error> {
+        let Some(room) = self.rooms().get(*room_id).await? else {
+            return Ok(vec![]);
+        };
+
+        self.rooms().delete(room.id).await?;
+
+        for participant in room.participants.iter() {
+            self.participants().delete(*participant).await?;
+        }
+
+        Ok(room.participants)
+    }
+}
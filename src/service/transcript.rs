@@ -0,0 +1,614 @@
+//! Hash-chained transcript of the room, that is kept by the service after the room is
+//! cleared, so the failed shuffle can be investigated offline.
+//!
+//! Every [`Entry`] holds an [`Event`]: either a message submitted by the participant with
+//! its signatures, or the state the room moved to. The hash of the entry covers its index,
+//! the time it is recorded at, the event and the hash of the previous entry, so the entries
+//! can't be changed, removed or reordered without breaking the chain.
+//! [`Transcript::verify`] replays the transcript against the protocol rules and reports the
+//! first invalid step, the shuffle order of the room is derived from the recorded reveals
+//! the same way the service does it.
+//!
+//! Owners and amounts of the UTXOs are recorded as they were returned by the contract on
+//! connect, the verifier trusts them, as it doesn't access the contract.
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use coin_shuffle_contracts_bindings::utxo::types::Output;
+use ethers_core::abi::ethereum_types::Signature;
+use ethers_core::types::{Address, Signature as EcdsaSignature, H256, U256};
+use ethers_core::utils::keccak256;
+use rsa::{PublicKeyParts, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::types::{
+    room_outputs, validate_decoded_outputs, EncodedOutput, InvalidDecodedOutputs, RoomState,
+};
+use crate::auth::{
+    challenge_message, decoded_outputs_message, outputs_signature_message, reveal_message,
+};
+use crate::ordering::{self, Reveals};
+use crate::types::Fee;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub room_id: Uuid,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Position of the entry in the transcript.
+    pub index: u64,
+    /// Time the event is recorded at.
+    pub recorded_at: SystemTime,
+    pub event: Event,
+    /// Hash of the previous entry, zero for the first one.
+    pub prev_hash: H256,
+    pub hash: H256,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    /// Room is created, it is the first event of the transcript.
+    RoomCreated {
+        token: Address,
+        amount: U256,
+        fee: Option<Fee>,
+        participants: Vec<U256>,
+    },
+    /// Participant is issued the challenge nonce.
+    Challenged { utxo_id: U256, nonce: H256 },
+    /// Participant connected with the challenge signed by the UTXO owner.
+    Connected {
+        utxo_id: U256,
+        owner: Address,
+        /// Amount of the UTXO, that the change is left from.
+        amount: U256,
+        rsa_pubkey: RsaPublicKey,
+        session_key: Address,
+        commitment: H256,
+        change: Option<Address>,
+        signature: EcdsaSignature,
+    },
+    /// Participant revealed the entropy it committed to on connect.
+    Revealed {
+        utxo_id: U256,
+        entropy: H256,
+        session_signature: EcdsaSignature,
+    },
+    /// Participant passed the outputs decoded in its round.
+    DecodedOutputs {
+        utxo_id: U256,
+        decoded_outputs: Vec<EncodedOutput>,
+        session_signature: EcdsaSignature,
+    },
+    /// Participant passed its signature of the room outputs.
+    SignaturePassed {
+        utxo_id: U256,
+        signature: Signature,
+        session_signature: EcdsaSignature,
+    },
+    /// Participant left the room with the [`leave_message`](crate::auth::leave_message)
+    /// signed by the UTXO owner.
+    Left {
+        utxo_id: U256,
+        signature: EcdsaSignature,
+    },
+    /// Participant is removed from the room by the operator.
+    Kicked { utxo_id: U256 },
+    /// Room has less participants than the minimum after the removal, so it is dissolved.
+    /// It is the last event of the transcript.
+    Dissolved,
+    /// Room isn't finished in the time-to-live of its state, so it is removed by the reaper.
+    /// It is the last event of the transcript.
+    Expired,
+    /// Room moved to the state.
+    State(RoomState),
+}
+
+impl Entry {
+    /// Create the entry following the `prev` one, that is recorded now. The first entry of
+    /// the transcript has no previous one.
+    pub fn new(prev: Option<&Entry>, event: Event) -> Self {
+        Self::recorded_at(prev, event, SystemTime::now())
+    }
+
+    /// Create the entry following the `prev` one, that is recorded at given time.
+    pub fn recorded_at(prev: Option<&Entry>, event: Event, recorded_at: SystemTime) -> Self {
+        let (index, prev_hash) = match prev {
+            Some(prev) => (prev.index + 1, prev.hash),
+            None => (0, H256::zero()),
+        };
+
+        Self {
+            index,
+            recorded_at,
+            hash: Self::compute_hash(index, recorded_at, prev_hash, &event),
+            event,
+            prev_hash,
+        }
+    }
+
+    fn compute_hash(index: u64, recorded_at: SystemTime, prev_hash: H256, event: &Event) -> H256 {
+        let recorded_at = recorded_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        let mut message = prev_hash.as_bytes().to_vec();
+        message.extend_from_slice(&index.to_be_bytes());
+        message.extend_from_slice(&recorded_at.to_be_bytes());
+        message.append(&mut serde_json::to_vec(event).expect("event is always serializable"));
+
+        H256::from(keccak256(message))
+    }
+}
+
+impl Transcript {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Replay the transcript against the protocol rules and return the first invalid step.
+    /// Transcript of the room that isn't finished yet is valid, if all its steps are.
+    pub fn verify(&self) -> Result<(), InvalidStep> {
+        let mut replay = Replay::new(self.room_id);
+        let mut prev: Option<&Entry> = None;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let invalid = |violation| InvalidStep { index, violation };
+
+            if entry.index != index as u64 {
+                return Err(invalid(Violation::InvalidIndex(entry.index)));
+            }
+            if entry.prev_hash != prev.map_or_else(H256::zero, |prev| prev.hash) {
+                return Err(invalid(Violation::BrokenChain));
+            }
+            if entry.hash
+                != Entry::compute_hash(
+                    entry.index,
+                    entry.recorded_at,
+                    entry.prev_hash,
+                    &entry.event,
+                )
+            {
+                return Err(invalid(Violation::InvalidHash));
+            }
+
+            replay.step(&entry.event).map_err(invalid)?;
+            prev = Some(entry);
+        }
+
+        if replay.pending.is_some() {
+            return Err(InvalidStep {
+                index: self.entries.len(),
+                violation: Violation::MissingState,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("step {index} is invalid: {violation}")]
+pub struct InvalidStep {
+    pub index: usize,
+    pub violation: Violation,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum Violation {
+    #[error("entry has the index {0}")]
+    InvalidIndex(u64),
+    #[error("entry isn't chained to the previous one")]
+    BrokenChain,
+    #[error("hash doesn't match the entry")]
+    InvalidHash,
+    #[error("room isn't created")]
+    NotCreated,
+    #[error("room is already created")]
+    AlreadyCreated,
+    #[error("participant {0} isn't in the room")]
+    UnknownParticipant(U256),
+    #[error("participant {0} is already challenged")]
+    AlreadyChallenged(U256),
+    #[error("participant {0} isn't challenged")]
+    NotChallenged(U256),
+    #[error("entropy of participant {0} doesn't match its commitment")]
+    InvalidReveal(U256),
+    #[error("change of participant {0} doesn't match the amount of its UTXO")]
+    InvalidChange(U256),
+    #[error("challenge of participant {0} isn't signed by the UTXO owner")]
+    InvalidChallengeSignature(U256),
+    #[error("message of participant {0} isn't signed by its session key")]
+    InvalidSessionSignature(U256),
+    #[error("event isn't expected in the {0:?} state")]
+    UnexpectedEvent(RoomState),
+    #[error("participant {0} submitted outputs out of its round")]
+    OutOfRound(U256),
+    #[error("participant {0} submitted invalid number of outputs")]
+    InvalidNumberOfOutputs(U256),
+    #[error("participant {0} passed duplicate outputs")]
+    DuplicateOutputs(U256),
+    #[error("participant {0} passed the outputs of the previous participant unchanged")]
+    ForwardedOutputs(U256),
+    #[error("participant {0} passed outputs of invalid length, {1} bytes are expected")]
+    InvalidOutputLength(U256, usize),
+    #[error("keys of the participants next to {0} can't encode the outputs")]
+    UnsupportedKeys(U256),
+    #[error("fee output of the room overflows")]
+    FeeOverflow,
+    #[error("participant {0} revealed the entropy twice")]
+    DuplicateReveal(U256),
+    #[error("participant {0} passed the signature twice")]
+    DuplicateSignature(U256),
+    #[error("room moved to the {recorded:?} state, while {expected:?} is expected")]
+    UnexpectedState {
+        recorded: RoomState,
+        expected: RoomState,
+    },
+    #[error("state change isn't recorded after the event")]
+    MissingState,
+    #[error("room is dissolved")]
+    Dissolved,
+    #[error("room is expired")]
+    Expired,
+}
+
+/// Room of the transcript rebuilt from its events.
+struct Replay {
+    room_id: Uuid,
+    room: Option<(U256, Vec<U256>)>,
+    fee: Option<Fee>,
+    state: RoomState,
+    /// State the room should move to by the next entry.
+    pending: Option<RoomState>,
+    /// Pending state follows the removal of the participant, so the room may be dissolved
+    /// instead.
+    removal: bool,
+    dissolved: bool,
+    expired: bool,
+    nonces: HashMap<U256, H256>,
+    session_keys: HashMap<U256, Address>,
+    commitments: HashMap<U256, H256>,
+    /// Change outputs of the connected participants.
+    changes: HashMap<U256, Output>,
+    /// Sizes in bytes of the RSA keys of the connected participants.
+    key_sizes: HashMap<U256, usize>,
+    /// Outputs passed in the previous round.
+    previous_outputs: Vec<EncodedOutput>,
+}
+
+impl Replay {
+    fn new(room_id: Uuid) -> Self {
+        Self {
+            room_id,
+            room: None,
+            fee: None,
+            state: RoomState::Waiting,
+            pending: None,
+            removal: false,
+            dissolved: false,
+            expired: false,
+            nonces: HashMap::new(),
+            session_keys: HashMap::new(),
+            commitments: HashMap::new(),
+            changes: HashMap::new(),
+            key_sizes: HashMap::new(),
+            previous_outputs: Vec::new(),
+        }
+    }
+
+    fn step(&mut self, event: &Event) -> Result<(), Violation> {
+        if self.dissolved {
+            return Err(Violation::Dissolved);
+        }
+        if self.expired {
+            return Err(Violation::Expired);
+        }
+
+        match event {
+            Event::State(recorded) => return self.change_state(recorded),
+            Event::Dissolved => return self.dissolve(),
+            _ if self.pending.is_some() => return Err(Violation::MissingState),
+            Event::Expired => {
+                self.room()?;
+                if matches!(self.state, RoomState::TransactionHash(_)) {
+                    return Err(Violation::UnexpectedEvent(self.state.clone()));
+                }
+
+                self.expired = true;
+            }
+            Event::RoomCreated {
+                amount,
+                fee,
+                participants,
+                ..
+            } => {
+                if self.room.is_some() {
+                    return Err(Violation::AlreadyCreated);
+                }
+
+                self.room = Some((*amount, participants.clone()));
+                self.fee = fee.clone();
+            }
+            Event::Challenged { utxo_id, nonce } => {
+                let (_, participants) = self.room()?;
+                Self::position(&participants, utxo_id)?;
+                if self.nonces.contains_key(utxo_id) || self.session_keys.contains_key(utxo_id) {
+                    return Err(Violation::AlreadyChallenged(*utxo_id));
+                }
+
+                self.nonces.insert(*utxo_id, *nonce);
+            }
+            Event::Connected {
+                utxo_id,
+                owner,
+                amount: utxo_amount,
+                rsa_pubkey,
+                session_key,
+                commitment,
+                change,
+                signature,
+            } => {
+                let (amount, participants) = self.room()?;
+                Self::position(&participants, utxo_id)?;
+                let mut connected = match &self.state {
+                    RoomState::Waiting => BTreeSet::new(),
+                    RoomState::Connecting(connected) => connected.clone(),
+                    state => return Err(Violation::UnexpectedEvent(state.clone())),
+                };
+                let nonce = self
+                    .nonces
+                    .remove(utxo_id)
+                    .ok_or(Violation::NotChallenged(*utxo_id))?;
+
+                let message = challenge_message(
+                    &self.room_id,
+                    *utxo_id,
+                    nonce,
+                    rsa_pubkey,
+                    *session_key,
+                    *commitment,
+                    *change,
+                );
+                signature
+                    .verify(message.to_vec(), *owner)
+                    .map_err(|_| Violation::InvalidChallengeSignature(*utxo_id))?;
+
+                let change_amount = crate::types::change(*utxo_amount, amount, self.fee.as_ref())
+                    .ok_or(Violation::InvalidChange(*utxo_id))?;
+                match change {
+                    Some(address) if !change_amount.is_zero() => {
+                        self.changes.insert(
+                            *utxo_id,
+                            Output {
+                                amount: change_amount,
+                                owner: *address,
+                            },
+                        );
+                    }
+                    None if change_amount.is_zero() => {}
+                    _ => return Err(Violation::InvalidChange(*utxo_id)),
+                }
+
+                self.session_keys.insert(*utxo_id, *session_key);
+                self.key_sizes.insert(*utxo_id, rsa_pubkey.size());
+                self.commitments.insert(*utxo_id, *commitment);
+                connected.insert(*utxo_id);
+                self.pending = Some(if connected.len() == participants.len() {
+                    RoomState::Revealing(Reveals::new())
+                } else {
+                    RoomState::Connecting(connected)
+                });
+            }
+            Event::Revealed {
+                utxo_id,
+                entropy,
+                session_signature,
+            } => {
+                let (amount, participants) = self.room()?;
+                Self::position(&participants, utxo_id)?;
+                let RoomState::Revealing(reveals) = &self.state else {
+                    return Err(Violation::UnexpectedEvent(self.state.clone()));
+                };
+                if reveals.contains_key(utxo_id) {
+                    return Err(Violation::DuplicateReveal(*utxo_id));
+                }
+
+                let message = reveal_message(&self.room_id, *utxo_id, *entropy);
+                self.verify_session_signature(utxo_id, message, session_signature)?;
+                if self.commitments.get(utxo_id)
+                    != Some(&ordering::commitment(&self.room_id, *utxo_id, *entropy))
+                {
+                    return Err(Violation::InvalidReveal(*utxo_id));
+                }
+
+                let mut reveals = reveals.clone();
+                reveals.insert(*utxo_id, *entropy);
+                self.pending = Some(if reveals.len() == participants.len() {
+                    let order = ordering::shuffle_order(&participants, ordering::seed(&reveals));
+                    self.room = Some((amount, order));
+
+                    RoomState::Shuffle(0)
+                } else {
+                    RoomState::Revealing(reveals)
+                });
+            }
+            Event::DecodedOutputs {
+                utxo_id,
+                decoded_outputs,
+                session_signature,
+            } => {
+                let (amount, participants) = self.room()?;
+                let position = Self::position(&participants, utxo_id)?;
+                let RoomState::Shuffle(round) = self.state else {
+                    return Err(Violation::UnexpectedEvent(self.state.clone()));
+                };
+                if position != round {
+                    return Err(Violation::OutOfRound(*utxo_id));
+                }
+                if decoded_outputs.len() != round + 1 {
+                    return Err(Violation::InvalidNumberOfOutputs(*utxo_id));
+                }
+
+                let message = decoded_outputs_message(&self.room_id, *utxo_id, decoded_outputs);
+                self.verify_session_signature(utxo_id, message, session_signature)?;
+
+                // Outputs are encoded by the key of the last participant first
+                let key_sizes = participants[round + 1..]
+                    .iter()
+                    .rev()
+                    .map(|utxo_id| self.key_sizes.get(utxo_id).copied())
+                    .collect::<Option<Vec<usize>>>()
+                    .ok_or(Violation::UnexpectedEvent(self.state.clone()))?;
+                validate_decoded_outputs(decoded_outputs, &self.previous_outputs, key_sizes)
+                    .map_err(|err| match err {
+                        InvalidDecodedOutputs::Duplicate => Violation::DuplicateOutputs(*utxo_id),
+                        InvalidDecodedOutputs::Forwarded => Violation::ForwardedOutputs(*utxo_id),
+                        InvalidDecodedOutputs::Length(len) => {
+                            Violation::InvalidOutputLength(*utxo_id, len)
+                        }
+                        InvalidDecodedOutputs::Keys(_) => Violation::UnsupportedKeys(*utxo_id),
+                    })?;
+                self.previous_outputs = decoded_outputs.clone();
+
+                self.pending = Some(if round + 1 == participants.len() {
+                    let changes = participants
+                        .iter()
+                        .filter_map(|utxo_id| self.changes.get(utxo_id).cloned());
+                    let outputs = room_outputs(amount, self.fee.as_ref(), decoded_outputs, changes)
+                        .ok_or(Violation::FeeOverflow)?;
+
+                    RoomState::Signatures((outputs, Vec::new()))
+                } else {
+                    RoomState::Shuffle(round + 1)
+                });
+            }
+            Event::SignaturePassed {
+                utxo_id,
+                signature,
+                session_signature,
+            } => {
+                let (_, participants) = self.room()?;
+                Self::position(&participants, utxo_id)?;
+                let RoomState::Signatures((outputs, passed)) = &self.state else {
+                    return Err(Violation::UnexpectedEvent(self.state.clone()));
+                };
+                if passed.contains(utxo_id) {
+                    return Err(Violation::DuplicateSignature(*utxo_id));
+                }
+
+                let message =
+                    outputs_signature_message(&self.room_id, *utxo_id, signature.as_bytes());
+                self.verify_session_signature(utxo_id, message, session_signature)?;
+
+                let mut passed = passed.clone();
+                passed.push(*utxo_id);
+                self.pending = Some(RoomState::Signatures((outputs.clone(), passed)));
+            }
+            Event::Left { utxo_id, .. } | Event::Kicked { utxo_id } => {
+                let (amount, mut participants) = self.room()?;
+                let position = Self::position(&participants, utxo_id)?;
+                let mut connected = match &self.state {
+                    RoomState::Waiting => BTreeSet::new(),
+                    RoomState::Connecting(connected) => connected.clone(),
+                    state => return Err(Violation::UnexpectedEvent(state.clone())),
+                };
+
+                participants.remove(position);
+                connected.remove(utxo_id);
+                self.nonces.remove(utxo_id);
+                self.session_keys.remove(utxo_id);
+                self.key_sizes.remove(utxo_id);
+                self.commitments.remove(utxo_id);
+                self.changes.remove(utxo_id);
+
+                self.pending = Some(if connected.is_empty() {
+                    RoomState::Waiting
+                } else if connected.len() == participants.len() {
+                    RoomState::Revealing(Reveals::new())
+                } else {
+                    RoomState::Connecting(connected)
+                });
+                self.removal = true;
+                self.room = Some((amount, participants));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dissolve(&mut self) -> Result<(), Violation> {
+        if self.pending.take().is_none() || !self.removal {
+            return Err(Violation::UnexpectedEvent(self.state.clone()));
+        }
+
+        self.dissolved = true;
+
+        Ok(())
+    }
+
+    fn change_state(&mut self, recorded: &RoomState) -> Result<(), Violation> {
+        self.removal = false;
+        let expected = match self.pending.take() {
+            Some(expected) => expected,
+            // Transaction is sent by the coordinator, once all the signatures are passed
+            None => match (&self.state, recorded, &self.room) {
+                (
+                    RoomState::Signatures((_, passed)),
+                    RoomState::TransactionHash(_),
+                    Some((_, participants)),
+                ) if passed.len() == participants.len() => recorded.clone(),
+                _ => self.state.clone(),
+            },
+        };
+
+        if recorded != &expected {
+            return Err(Violation::UnexpectedState {
+                recorded: recorded.clone(),
+                expected,
+            });
+        }
+
+        self.state = expected;
+
+        Ok(())
+    }
+
+    /// Return amount and participants of the created room.
+    fn room(&self) -> Result<(U256, Vec<U256>), Violation> {
+        self.room.clone().ok_or(Violation::NotCreated)
+    }
+
+    fn verify_session_signature(
+        &self,
+        utxo_id: &U256,
+        message: [u8; 32],
+        signature: &EcdsaSignature,
+    ) -> Result<(), Violation> {
+        let session_key = self
+            .session_keys
+            .get(utxo_id)
+            .ok_or(Violation::UnexpectedEvent(self.state.clone()))?;
+
+        signature
+            .verify(message.to_vec(), *session_key)
+            .map_err(|_| Violation::InvalidSessionSignature(*utxo_id))
+    }
+
+    fn position(participants: &[U256], utxo_id: &U256) -> Result<usize, Violation> {
+        participants
+            .iter()
+            .position(|id| id == utxo_id)
+            .ok_or(Violation::UnknownParticipant(*utxo_id))
+    }
+}
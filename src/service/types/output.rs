@@ -1 +1,80 @@
+use std::collections::BTreeSet;
+
+use coin_shuffle_contracts_bindings::utxo::types::Output;
+use ethers_core::types::{Address, U256};
+
+use crate::types::Fee;
+
 pub type EncodedOutput = Vec<u8>;
+
+/// Rule of the shuffle broken by the outputs decoded by the participant.
+#[derive(thiserror::Error, Debug)]
+pub enum InvalidDecodedOutputs {
+    #[error("outputs are duplicate")]
+    Duplicate,
+    #[error("outputs of the previous participant are passed unchanged")]
+    Forwarded,
+    #[error("outputs should have {0} bytes")]
+    Length(usize),
+    #[error("keys of the next participants can't encode the outputs: {0}")]
+    Keys(#[from] crate::rsa::Error),
+}
+
+/// Check that the decoded outputs are distinct, aren't the `previous_outputs` of the
+/// previous participant passed unchanged, and have the length of the address encoded by the
+/// keys of the next participants, whose sizes in bytes are given in the order the keys are
+/// applied, so by the key of the last participant first.
+pub fn validate_decoded_outputs(
+    decoded_outputs: &[EncodedOutput],
+    previous_outputs: &[EncodedOutput],
+    key_sizes: impl IntoIterator<Item = usize>,
+) -> Result<(), InvalidDecodedOutputs> {
+    if decoded_outputs.iter().collect::<BTreeSet<_>>().len() != decoded_outputs.len() {
+        return Err(InvalidDecodedOutputs::Duplicate);
+    }
+    if decoded_outputs
+        .iter()
+        .any(|output| previous_outputs.contains(output))
+    {
+        return Err(InvalidDecodedOutputs::Forwarded);
+    }
+
+    let expected_len = crate::rsa::encoded_len(Address::len_bytes(), key_sizes)?;
+    if decoded_outputs
+        .iter()
+        .any(|output| output.len() != expected_len)
+    {
+        return Err(InvalidDecodedOutputs::Length(expected_len));
+    }
+
+    Ok(())
+}
+
+/// Return outputs of the room transaction: the outputs decoded by the last participant,
+/// each of the room `amount`, followed by the change outputs of the participants and the
+/// output of the fee, if it is charged. `None` is returned if the fee of the participants
+/// overflows.
+pub fn room_outputs(
+    amount: U256,
+    fee: Option<&Fee>,
+    decoded_outputs: &[EncodedOutput],
+    changes: impl IntoIterator<Item = Output>,
+) -> Option<Vec<Output>> {
+    let mut outputs: Vec<Output> = decoded_outputs
+        .iter()
+        .map(|output| Output {
+            amount,
+            owner: Address::from_slice(output),
+        })
+        .collect();
+    outputs.extend(changes);
+
+    if let Some(fee) = fee.filter(|fee| !fee.amount.is_zero()) {
+        outputs.push(Output {
+            amount: fee.amount.checked_mul(U256::from(decoded_outputs.len()))?,
+            owner: fee.recipient,
+        });
+    }
+
+    Some(outputs)
+}
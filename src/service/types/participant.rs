@@ -1,15 +1,19 @@
-use coin_shuffle_contracts_bindings::utxo::types::Input;
-use ethers_core::types::U256;
+use coin_shuffle_contracts_bindings::utxo::types::{Input, Output};
+use ethers_core::types::{Address, H256, U256};
 use rsa::RsaPublicKey;
 use uuid::Uuid;
 
 use super::EncodedOutput;
+use crate::types::ShuffleStatus;
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum State {
     /// Participant havn't started the process of shuffle, but room is created.
     Wait,
+    /// Participant is issued the nonce, that it should sign by the UTXO owner key to
+    /// connect to the room.
+    Challenged(H256),
     /// Shuffle started, the participant receiving RSA public
     /// keys, that are required for shuffle process.
     Start(RsaPublicKey),
@@ -27,6 +31,14 @@ pub struct Participant {
     pub room_id: uuid::Uuid,
     pub utxo_id: U256,
     pub state: State,
+    /// Address of the session key, that signs the messages of the participant. It is bound
+    /// to the UTXO owner on connect, see [`auth`](crate::auth).
+    pub session_key: Option<Address>,
+    /// Commitment to the entropy of the shuffle order, that is made on connect.
+    pub commitment: Option<H256>,
+    /// Output of the UTXO amount left after the room amount and the fee, it is sent to the
+    /// address passed on connect.
+    pub change: Option<Output>,
 }
 
 impl Participant {
@@ -35,6 +47,21 @@ impl Participant {
             room_id,
             utxo_id,
             state: State::Wait,
+            session_key: None,
+            commitment: None,
+            change: None,
+        }
+    }
+}
+
+impl From<&State> for ShuffleStatus {
+    fn from(state: &State) -> Self {
+        match state {
+            State::Wait | State::Challenged(_) => Self::SearchParticipants,
+            State::Start(_) => Self::ShuffleStart,
+            State::DecodedOutputs(_) => Self::Shuffle,
+            State::SigningOutput(_) => Self::SigningOutputs,
+            State::Finish => Self::TxHashDistribution,
         }
     }
 }
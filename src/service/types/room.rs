@@ -1,5 +1,8 @@
 use std::collections::BTreeSet;
+use std::time::SystemTime;
 
+use crate::ordering::Reveals;
+use crate::types::Fee;
 use coin_shuffle_contracts_bindings::utxo::types::Output;
 use ethers_core::{
     abi::Hash,
@@ -14,6 +17,9 @@ pub enum State {
     /// Users are connecting to the room by sending their public keys.
     /// The set contains all the users that are connected to the room.
     Connecting(BTreeSet<U256>),
+    /// All users are connected and reveal the entropy the shuffle order is derived from,
+    /// see [`ordering`](crate::ordering). The map contains the entropy revealed so far.
+    Revealing(Reveals),
     /// Current shuffle round number of the room.
     Shuffle(usize),
     /// Decoded outputs of the last user in the shuffle process with UTXO's
@@ -29,12 +35,21 @@ pub struct Room {
     pub id: uuid::Uuid,
     pub token: Address,
     pub amount: U256,
+    /// Fee every participant pays to the coordinator, it is taken from the
+    /// [`ServiceConfig`](crate::service::ServiceConfig) on creation.
+    pub fee: Option<Fee>,
     pub state: State,
 
     /// List of all UTXO that are participating in the room.
     /// Order in this vector represents the order which user participating
     /// in shuffle round.
     pub participants: Vec<U256>,
+
+    /// Time the room was created at, the deadlines of the room are counted from it.
+    pub created_at: SystemTime,
+    /// Time the room moved to the kind of its current state, that the duration of the room
+    /// phases is reported by.
+    pub phase_started_at: SystemTime,
 }
 
 impl Room {
@@ -43,12 +58,17 @@ impl Room {
     }
 
     pub fn with_id(id: uuid::Uuid, token: Address, amount: U256, participants: Vec<U256>) -> Self {
+        let now = SystemTime::now();
+
         Self {
             id,
             token,
             amount,
+            fee: None,
             state: State::Waiting,
             participants,
+            created_at: now,
+            phase_started_at: now,
         }
     }
 }
@@ -0,0 +1,461 @@
+//! In-process simulation of the shuffle, where a number of nodes are driven by their
+//! [`Client`]s through the [`Coordinator`] over the in-memory transport, and the transfer
+//! is made by the [`MockContract`]. Faults injected
+//! into the simulation let the tests check how both the nodes and the service react to
+//! the lost, corrupted or never sent messages.
+//!
+//! ```ignore
+//! let report = Simulation::new(3)
+//!     .with_fault(Fault::Stall { participant: 1 })
+//!     .run()
+//!     .await;
+//!
+//! assert!(matches!(report.participants[0].outcome, Outcome::TimedOut));
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers_core::types::{Address, H256, U256};
+use ethers_signers::{LocalWallet, Signer};
+
+use crate::client::{Client, ClientError, Event};
+use crate::mock::MockContract;
+use crate::node::{storage::RoomMemoryStorage, Node};
+use crate::protocol::Notification;
+use crate::rsa::MIN_KEY_SIZE;
+use crate::service::{
+    coordinator::Coordinator,
+    transcript::Transcript,
+    types::{ParticipantState, Room},
+    Service,
+};
+
+pub mod transport;
+
+pub use transport::{Hub, SimTransport};
+
+/// Error of the simulated participant.
+pub type SimError =
+    ClientError<LocalWallet, RoomMemoryStorage<LocalWallet>, MockContract, SimTransport>;
+
+const DEFAULT_AMOUNT: u64 = 100;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Fault injected into the simulation, participants are referenced by their position in
+/// the participants the room is created with, that isn't the shuffle order, see
+/// [`Report::position`]. Every fault except [`Fault::Stall`] is triggered once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Notification of given kind isn't delivered to the participant.
+    DropNotification {
+        participant: usize,
+        kind: NotificationKind,
+    },
+    /// First of the outputs decoded by the participant is corrupted on the way to the
+    /// coordinator, after the participant signed them.
+    CorruptCiphertext { participant: usize },
+    /// Participant connects to the room, but doesn't process any notification after that.
+    Stall { participant: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    RevealEntropy,
+    ShuffleStarted,
+    ShuffleRound,
+    SignOutputs,
+    TransactionHash,
+    RoomFailed,
+}
+
+impl NotificationKind {
+    pub fn of(notification: &Notification) -> Self {
+        match notification {
+            Notification::RevealEntropy { .. } => Self::RevealEntropy,
+            Notification::ShuffleStarted { .. } => Self::ShuffleStarted,
+            Notification::ShuffleRound { .. } => Self::ShuffleRound,
+            Notification::SignOutputs { .. } => Self::SignOutputs,
+            Notification::TransactionHash { .. } => Self::TransactionHash,
+            Notification::RoomFailed { .. } => Self::RoomFailed,
+        }
+    }
+}
+
+/// How the participant's shuffle ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Transaction with given hash is verified by the node.
+    Finished(H256),
+    Failed(SimError),
+    /// Participant didn't finish in the simulation timeout.
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub struct ParticipantReport {
+    pub utxo_id: U256,
+    pub outcome: Outcome,
+    /// Events reported by the client before the outcome.
+    pub events: Vec<Event>,
+    /// State of the participant in the service after the simulation.
+    pub state: Option<ParticipantState>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// Room in the service after the simulation.
+    pub room: Room,
+    /// Reports of the participants in the order the room is created with.
+    pub participants: Vec<ParticipantReport>,
+    pub contract: MockContract,
+    /// Transcript of the room recorded by the service.
+    pub transcript: Transcript,
+}
+
+impl Report {
+    /// Return position in the shuffle order of the participant at given position in the
+    /// room creation.
+    pub fn position(&self, participant: usize) -> Option<usize> {
+        let utxo_id = self.participants.get(participant)?.utxo_id;
+
+        self.room.participants.iter().position(|id| *id == utxo_id)
+    }
+
+    /// Return true if all the participants verified the same transaction.
+    pub fn is_successful(&self) -> bool {
+        let mut hashes = self
+            .participants
+            .iter()
+            .map(|participant| match participant.outcome {
+                Outcome::Finished(tx_hash) => Some(tx_hash),
+                _ => None,
+            });
+
+        match hashes.next() {
+            Some(Some(tx_hash)) => hashes.all(|hash| hash == Some(tx_hash)),
+            _ => false,
+        }
+    }
+}
+
+/// Builder of the shuffle simulation with one room.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    participants: usize,
+    amount: U256,
+    rsa_key_size: usize,
+    timeout: Duration,
+    faults: Vec<Fault>,
+    contract: MockContract,
+}
+
+impl Simulation {
+    pub fn new(participants: usize) -> Self {
+        Self {
+            participants,
+            amount: U256::from(DEFAULT_AMOUNT),
+            // Keys are generated for every participant, so the smallest ones are used
+            rsa_key_size: MIN_KEY_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+            faults: Vec::new(),
+            contract: MockContract::new(),
+        }
+    }
+
+    /// Return contract of the simulation, which can be used to script its errors before
+    /// the run.
+    pub fn contract(&self) -> &MockContract {
+        &self.contract
+    }
+
+    pub fn with_amount(mut self, amount: U256) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    pub fn with_rsa_key_size(mut self, bits: usize) -> Self {
+        self.rsa_key_size = bits;
+        self
+    }
+
+    /// Set time in which every participant should finish the shuffle.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        self.faults.push(fault);
+        self
+    }
+
+    /// Mint UTXOs of the participants, create the room for them and run the shuffle of
+    /// every participant concurrently until it ends or times out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the room can't be created or the participant's task panics.
+    pub async fn run(self) -> Report {
+        let contract = self.contract;
+        let service = Service::new();
+
+        let wallets: Vec<LocalWallet> = (0..self.participants)
+            .map(|_| LocalWallet::new(&mut rand::thread_rng()))
+            .collect();
+        let utxos: Vec<U256> = wallets
+            .iter()
+            .map(|wallet| contract.deposit(Address::zero(), wallet.address(), self.amount))
+            .collect();
+
+        let room = service
+            .create_room(Address::zero(), self.amount, utxos.clone())
+            .await
+            .expect("failed to create room");
+
+        let hub = Arc::new(Hub::new(
+            Coordinator::new(service.clone(), contract.clone()),
+            utxos.clone(),
+            self.faults,
+        ));
+
+        let mut tasks = Vec::new();
+        for (wallet, utxo_id) in wallets.into_iter().zip(utxos.iter().copied()) {
+            let node = Node::new(RoomMemoryStorage::new(), contract.clone())
+                .with_rsa_key_size(self.rsa_key_size);
+            let mut client = Client::new(node, hub.connect(utxo_id), contract.clone());
+            let output = Address::random().as_bytes().to_vec();
+            let timeout = self.timeout;
+
+            tasks.push(tokio::spawn(async move {
+                let mut events = Vec::new();
+                let run = client.run(utxo_id, output, wallet, |event| events.push(event));
+
+                let outcome = match tokio::time::timeout(timeout, run).await {
+                    Ok(Ok(tx_hash)) => Outcome::Finished(tx_hash),
+                    Ok(Err(err)) => Outcome::Failed(err),
+                    Err(_) => Outcome::TimedOut,
+                };
+
+                (outcome, events)
+            }));
+        }
+
+        let mut participants = Vec::new();
+        for (task, utxo_id) in tasks.into_iter().zip(utxos) {
+            let (outcome, events) = task.await.expect("participant task panicked");
+            let state = service
+                .get_participant(&utxo_id)
+                .await
+                .expect("failed to get participant")
+                .map(|participant| participant.state);
+
+            participants.push(ParticipantReport {
+                utxo_id,
+                outcome,
+                events,
+                state,
+            });
+        }
+
+        let room = service
+            .get_room(&room.id)
+            .await
+            .expect("failed to get room")
+            .expect("room is removed");
+        let transcript = service
+            .transcript(&room.id)
+            .await
+            .expect("failed to get transcript")
+            .expect("transcript is absent");
+
+        Report {
+            room,
+            participants,
+            contract,
+            transcript,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fault, NotificationKind, Outcome, Simulation};
+    use crate::client::Error as ClientError;
+    use crate::mock::{Call, MockError};
+    use crate::protocol::ErrorKind;
+    use crate::service::transcript::{Entry, Event, Transcript, Violation};
+    use crate::service::types::{ParticipantState, RoomState};
+
+    #[tokio::test]
+    async fn shuffle_is_finished() {
+        let report = Simulation::new(3).run().await;
+
+        assert!(report.is_successful(), "shuffle failed: {report:?}");
+        report.transcript.verify().expect("transcript is invalid");
+        let RoomState::TransactionHash(tx_hash) = report.room.state else {
+            panic!("invalid room state: {:?}", report.room.state);
+        };
+
+        let transfer = report
+            .contract
+            .transfer_by_hash(tx_hash)
+            .expect("transfer is absent");
+        assert_eq!(transfer.inputs.len(), 3);
+        for participant in report.participants.iter() {
+            assert_eq!(participant.state, Some(ParticipantState::Finish));
+            assert!(
+                report
+                    .contract
+                    .utxo(participant.utxo_id)
+                    .expect("utxo is absent")
+                    .is_spent
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_transfer() {
+        let simulation = Simulation::new(3);
+        simulation.contract().fail_next(
+            Call::Transfer,
+            MockError::Scripted("out of gas".to_string()),
+        );
+
+        let report = simulation.run().await;
+
+        // Every participant signed the outputs and is notified about the failure
+        assert!(matches!(report.room.state, RoomState::Signatures(_)));
+        for participant in report.participants.iter() {
+            assert!(matches!(
+                participant.outcome,
+                Outcome::Failed(ClientError::RoomFailed(_))
+            ));
+            assert!(
+                !report
+                    .contract
+                    .utxo(participant.utxo_id)
+                    .expect("utxo is absent")
+                    .is_spent
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_round_notification() {
+        let report = Simulation::new(3)
+            .with_fault(Fault::DropNotification {
+                participant: 1,
+                kind: NotificationKind::ShuffleRound,
+            })
+            .run()
+            .await;
+
+        // Nobody tells the participant that it's its round, so the room is stuck
+        let round = report.position(1).expect("participant isn't in the room");
+        assert_eq!(report.room.state, RoomState::Shuffle(round));
+        for participant in report.participants.iter() {
+            assert!(matches!(participant.outcome, Outcome::TimedOut));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn corrupted_ciphertext() {
+        let report = Simulation::new(3)
+            .with_fault(Fault::CorruptCiphertext { participant: 0 })
+            .run()
+            .await;
+
+        // Outputs don't match the session signature of the participant, so they are rejected
+        let round = report.position(0).expect("participant isn't in the room");
+        assert_eq!(report.room.state, RoomState::Shuffle(round));
+        assert!(matches!(
+            &report.participants[0].outcome,
+            Outcome::Failed(ClientError::Rejected(err)) if err.kind == ErrorKind::Unauthorized
+        ));
+        assert!(matches!(report.participants[1].outcome, Outcome::TimedOut));
+        assert!(matches!(report.participants[2].outcome, Outcome::TimedOut));
+        report.transcript.verify().expect("transcript is invalid");
+    }
+
+    #[tokio::test]
+    async fn tampered_transcript() {
+        let transcript = Simulation::new(3).run().await.transcript;
+
+        let exported = transcript.to_json().expect("failed to export transcript");
+        assert_eq!(
+            Transcript::from_json(&exported).expect("failed to import transcript"),
+            transcript
+        );
+
+        let position = transcript
+            .entries
+            .iter()
+            .position(|entry| matches!(entry.event, Event::DecodedOutputs { .. }))
+            .expect("decoded outputs aren't recorded");
+        let mut tampered = transcript.clone();
+        if let Event::DecodedOutputs {
+            decoded_outputs, ..
+        } = &mut tampered.entries[position].event
+        {
+            decoded_outputs[0][0] ^= 0xff;
+        }
+
+        let err = tampered.verify().expect_err("tampered entry is accepted");
+        assert_eq!(err.index, position);
+        assert_eq!(err.violation, Violation::InvalidHash);
+
+        // Chain rebuilt over the tampered entry doesn't help, as the outputs are signed
+        let err = rechain(tampered)
+            .verify()
+            .expect_err("tampered entry is accepted");
+        assert_eq!(err.index, position);
+        assert!(matches!(
+            err.violation,
+            Violation::InvalidSessionSignature(_)
+        ));
+
+        let mut skipped = transcript;
+        skipped.entries.remove(position + 1);
+        let err = rechain(skipped)
+            .verify()
+            .expect_err("skipped state is accepted");
+        assert_eq!(err.index, position + 1);
+        assert_eq!(err.violation, Violation::MissingState);
+    }
+
+    fn rechain(transcript: Transcript) -> Transcript {
+        let mut entries: Vec<Entry> = Vec::new();
+        for entry in transcript.entries {
+            entries.push(Entry::new(entries.last(), entry.event));
+        }
+
+        Transcript {
+            room_id: transcript.room_id,
+            entries,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_participant() {
+        let report = Simulation::new(3)
+            .with_fault(Fault::Stall { participant: 2 })
+            .run()
+            .await;
+
+        // Stalled participant is connected, but doesn't reveal its entropy, so the shuffle
+        // isn't started
+        let RoomState::Revealing(reveals) = &report.room.state else {
+            panic!("invalid room state: {:?}", report.room.state);
+        };
+        assert_eq!(reveals.len(), 2);
+        assert!(!reveals.contains_key(&report.participants[2].utxo_id));
+        assert!(matches!(
+            report.participants[2].state,
+            Some(ParticipantState::Start(_))
+        ));
+        for participant in report.participants.iter() {
+            assert!(matches!(participant.outcome, Outcome::TimedOut));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ethers_core::types::U256;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::{Fault, NotificationKind};
+use crate::client::Transport;
+use crate::mock::MockContract;
+use crate::protocol::{Notification, Request, Response};
+use crate::service::coordinator::Coordinator;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransportError {
+    #[error("coordinator is closed the connection")]
+    Closed,
+}
+
+/// In-process coordinator, that handles requests of the participants one by one, in order
+/// of their arrival, and applies the injected faults to the messages.
+pub struct Hub {
+    coordinator: Coordinator<MockContract>,
+    /// UTXO ids of the room participants in the order the room is created with.
+    participants: Vec<U256>,
+    connections: Mutex<HashMap<U256, UnboundedSender<Notification>>>,
+    /// Faults that are not triggered yet.
+    faults: Mutex<Vec<Fault>>,
+    order: tokio::sync::Mutex<()>,
+}
+
+impl Hub {
+    pub fn new(
+        coordinator: Coordinator<MockContract>,
+        participants: Vec<U256>,
+        faults: Vec<Fault>,
+    ) -> Self {
+        Self {
+            coordinator,
+            participants,
+            connections: Mutex::new(HashMap::new()),
+            faults: Mutex::new(faults),
+            order: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Open connection of the participant with given UTXO.
+    pub fn connect(self: &Arc<Self>, utxo_id: U256) -> SimTransport {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.connections
+            .lock()
+            .expect("connections lock is poisoned")
+            .insert(utxo_id, sender);
+
+        let stalled = self
+            .faults
+            .lock()
+            .expect("faults lock is poisoned")
+            .iter()
+            .any(|fault| matches!(fault, Fault::Stall { participant } if self.participant(*participant) == Some(utxo_id)));
+
+        SimTransport {
+            hub: Arc::clone(self),
+            notifications: receiver,
+            stalled,
+        }
+    }
+
+    pub async fn handle(&self, request: Request) -> Response {
+        let _order = self.order.lock().await;
+
+        let request = self.corrupt(request);
+        let handled = self.coordinator.handle(request).await;
+
+        let connections = self
+            .connections
+            .lock()
+            .expect("connections lock is poisoned");
+        for (utxo_id, notification) in handled.notifications {
+            if self.drop(&utxo_id, &notification) {
+                log::debug!("notification to {utxo_id} is dropped: {notification:?}");
+                continue;
+            }
+
+            // Participant may be already gone after the failure
+            if let Some(connection) = connections.get(&utxo_id) {
+                let _ = connection.send(notification);
+            }
+        }
+
+        handled.response
+    }
+
+    fn participant(&self, position: usize) -> Option<U256> {
+        self.participants.get(position).copied()
+    }
+
+    /// Apply the [`Fault::CorruptCiphertext`] to the request, if it is the one.
+    fn corrupt(&self, mut request: Request) -> Request {
+        let Request::PassDecodedOutputs {
+            utxo_id,
+            decoded_outputs,
+            ..
+        } = &mut request
+        else {
+            return request;
+        };
+
+        let triggered = self.trigger(|fault| {
+            matches!(fault, Fault::CorruptCiphertext { participant } if self.participant(*participant) == Some(*utxo_id))
+        });
+        if triggered {
+            if let Some(byte) = decoded_outputs
+                .first_mut()
+                .and_then(|output| output.first_mut())
+            {
+                *byte ^= 0xff;
+            }
+        }
+
+        request
+    }
+
+    /// Check if the notification should be dropped by the [`Fault::DropNotification`].
+    fn drop(&self, utxo_id: &U256, notification: &Notification) -> bool {
+        self.trigger(|fault| {
+            matches!(
+                fault,
+                Fault::DropNotification { participant, kind }
+                    if self.participant(*participant) == Some(*utxo_id)
+                        && *kind == NotificationKind::of(notification)
+            )
+        })
+    }
+
+    /// Remove the first fault matching the predicate and return true if there is one.
+    fn trigger<F: Fn(&Fault) -> bool>(&self, predicate: F) -> bool {
+        let mut faults = self.faults.lock().expect("faults lock is poisoned");
+
+        match faults.iter().position(predicate) {
+            Some(position) => {
+                faults.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Connection of the participant to the [`Hub`].
+pub struct SimTransport {
+    hub: Arc<Hub>,
+    notifications: UnboundedReceiver<Notification>,
+    /// Participant doesn't receive any notifications after the connection.
+    stalled: bool,
+}
+
+#[async_trait]
+impl Transport for SimTransport {
+    type Error = TransportError;
+
+    async fn request(&mut self, request: Request) -> Result<Response, Self::Error> {
+        Ok(self.hub.handle(request).await)
+    }
+
+    async fn notification(&mut self) -> Result<Notification, Self::Error> {
+        if self.stalled {
+            std::future::pending::<()>().await;
+        }
+
+        self.notifications
+            .recv()
+            .await
+            .ok_or(TransportError::Closed)
+    }
+}
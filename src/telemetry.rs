@@ -0,0 +1,143 @@
+//! Tracing and metrics of the [`Service`](crate::service::Service) and
+//! [`Node`](crate::node::Node) operations, that are enabled by the `telemetry` feature.
+//!
+//! Operations are wrapped in the [`tracing`] spans with the room id, UTXO id and shuffle
+//! round as their fields, while keys, signatures, entropy and outputs are never recorded.
+//! Metrics are reported through the [`metrics`] facade under the names below, so they reach
+//! the exporter installed by the application. Without the feature both are no-ops.
+
+use std::time::Duration;
+
+/// Counter of the created rooms.
+pub const ROOMS_CREATED: &str = "coin_shuffle_rooms_created_total";
+/// Counter of the rooms, that reached the transaction hash.
+pub const ROOMS_COMPLETED: &str = "coin_shuffle_rooms_completed_total";
+/// Counter of the rooms, that are dissolved or expired, labeled by the `reason`.
+pub const ROOMS_FAILED: &str = "coin_shuffle_rooms_failed_total";
+/// Histogram of the time in seconds the room spent in the `phase`, which is the kind of
+/// the [`RoomState`](crate::service::types::RoomState).
+pub const PHASE_DURATION: &str = "coin_shuffle_phase_duration_seconds";
+/// Histogram of the time in seconds of the RSA encryption of the message.
+pub const RSA_ENCRYPT_DURATION: &str = "coin_shuffle_rsa_encrypt_duration_seconds";
+/// Histogram of the time in seconds of the RSA decryption of the message.
+pub const RSA_DECRYPT_DURATION: &str = "coin_shuffle_rsa_decrypt_duration_seconds";
+
+#[cfg(feature = "service")]
+pub(crate) fn room_created() {
+    #[cfg(feature = "telemetry")]
+    metrics::counter!(ROOMS_CREATED).increment(1);
+}
+
+#[cfg(feature = "service")]
+pub(crate) fn room_completed() {
+    #[cfg(feature = "telemetry")]
+    metrics::counter!(ROOMS_COMPLETED).increment(1);
+}
+
+#[cfg(feature = "service")]
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn room_failed(reason: &'static str) {
+    #[cfg(feature = "telemetry")]
+    metrics::counter!(ROOMS_FAILED, "reason" => reason).increment(1);
+}
+
+#[cfg(feature = "service")]
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn phase_finished(phase: &'static str, duration: Duration) {
+    #[cfg(feature = "telemetry")]
+    metrics::histogram!(PHASE_DURATION, "phase" => phase).record(duration.as_secs_f64());
+}
+
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn rsa_encrypted(duration: Duration) {
+    #[cfg(feature = "telemetry")]
+    metrics::histogram!(RSA_ENCRYPT_DURATION).record(duration.as_secs_f64());
+}
+
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn rsa_decrypted(duration: Duration) {
+    #[cfg(feature = "telemetry")]
+    metrics::histogram!(RSA_DECRYPT_DURATION).record(duration.as_secs_f64());
+}
+
+/// Record the room id in the current span, which is known only after the participant is
+/// read for the operations called by the UTXO id.
+#[cfg(feature = "service")]
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn record_room(room_id: &uuid::Uuid) {
+    #[cfg(feature = "telemetry")]
+    tracing::Span::current().record("room_id", tracing::field::display(room_id));
+}
+
+/// Record the shuffle round in the current span.
+#[cfg(feature = "service")]
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn record_round(round: usize) {
+    #[cfg(feature = "telemetry")]
+    tracing::Span::current().record("round", round);
+}
+
+#[cfg(all(test, feature = "telemetry", feature = "service", feature = "client"))]
+mod tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    use super::{PHASE_DURATION, ROOMS_COMPLETED, ROOMS_CREATED, RSA_DECRYPT_DURATION};
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn shuffle_is_measured() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        // Recorder is local to the thread, so the whole shuffle runs on it
+        let report = metrics::with_local_recorder(&recorder, || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to build runtime")
+                .block_on(Simulation::new(2).run())
+        });
+        assert!(report.is_successful());
+
+        let metrics = snapshotter.snapshot().into_vec();
+        let counter = |name: &str| {
+            metrics.iter().find_map(|(key, _, _, value)| match value {
+                DebugValue::Counter(count)
+                    if key.kind() == MetricKind::Counter && key.key().name() == name =>
+                {
+                    Some(*count)
+                }
+                _ => None,
+            })
+        };
+        assert_eq!(counter(ROOMS_CREATED), Some(1));
+        assert_eq!(counter(ROOMS_COMPLETED), Some(1));
+
+        let mut phases: Vec<&str> = metrics
+            .iter()
+            .filter(|(key, ..)| key.key().name() == PHASE_DURATION)
+            .flat_map(|(key, ..)| key.key().labels().map(|label| label.value()))
+            .collect();
+        phases.sort();
+        assert_eq!(
+            phases,
+            [
+                "connecting",
+                "revealing",
+                "shuffle",
+                "signatures",
+                "waiting"
+            ]
+        );
+
+        let decrypted = metrics.iter().find_map(|(key, _, _, value)| match value {
+            DebugValue::Histogram(values) if key.key().name() == RSA_DECRYPT_DURATION => {
+                Some(values.len())
+            }
+            _ => None,
+        });
+        // Output of the first participant is decrypted by the second one
+        assert_eq!(decrypted, Some(1));
+    }
+}
@@ -1,4 +1,5 @@
-use ethers_core::types::{Address, Signature, U256};
+use crate::ordering::{Commitments, Reveals};
+use ethers_core::types::{Address, Signature, H256, U256};
 use rsa::RsaPublicKey;
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -24,7 +25,7 @@ impl Room {
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 pub enum ShuffleStatus {
     SearchParticipants,
@@ -34,6 +35,35 @@ pub enum ShuffleStatus {
     TxHashDistribution,
 }
 
+/// State of the shuffle as the service knows it, that the participant needs to resume the
+/// shuffle after its node is restarted.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Resumption {
+    pub room_id: uuid::Uuid,
+    pub token: Address,
+    pub amount: U256,
+    /// Fee the participants of the room pay to the coordinator.
+    pub fee: Option<Fee>,
+    /// UTXO ids of the room participants in the shuffle order.
+    pub participants: Vec<U256>,
+    /// Status of the participant in the service.
+    pub status: ShuffleStatus,
+    /// Commitments of the participants, if the participant should reveal its entropy.
+    pub commitments: Option<Commitments>,
+    /// Entropy the participants are ordered by, it is known once the shuffle is started.
+    pub reveals: Option<Reveals>,
+    /// Keys the participant encodes its output with, they are known once the shuffle is
+    /// started.
+    pub public_keys: Option<Vec<RsaPublicKey>>,
+    /// Outputs the participant should decode, if it is the round of the participant.
+    pub encoded_outputs: Option<Vec<Vec<u8>>>,
+    /// Outputs the participant should sign, if it hasn't signed them yet.
+    pub outputs_to_sign: Option<Vec<coin_shuffle_contracts_bindings::utxo::types::Output>>,
+    /// Hash of the transaction made of the room outputs.
+    pub tx_hash: Option<H256>,
+}
+
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Participant {
@@ -69,3 +99,21 @@ pub struct Output {
     pub amount: U256,
     pub owner: Address,
 }
+
+/// Fee the coordinator charges every participant of the room. The fees of the participants
+/// are paid by the single output of the room transaction to the `recipient`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fee {
+    pub recipient: Address,
+    /// Fee of a participant.
+    pub amount: U256,
+}
+
+/// Return the change of the UTXO of `utxo_amount` in the room of `amount`, that is left after
+/// the room amount and the fee, or `None` if the UTXO doesn't cover them.
+pub fn change(utxo_amount: U256, amount: U256, fee: Option<&Fee>) -> Option<U256> {
+    let fee = fee.map(|fee| fee.amount).unwrap_or_default();
+
+    utxo_amount.checked_sub(amount.checked_add(fee)?)
+}